@@ -12,7 +12,7 @@ impl KeyExtractor for UserToken {
     type Key = String;
     type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
 
-    #[cfg(feature = "log")]
+    #[cfg(any(feature = "log", feature = "tracing"))]
     fn name(&self) -> &'static str {
         "Bearer token"
     }
@@ -48,8 +48,8 @@ impl KeyExtractor for UserToken {
             )
     }
 
-    #[cfg(feature = "log")]
-    fn key_name(&self, key: &Self::Key) -> Option<String> {
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn key_name(&self, _key: &Self::Key) -> Option<String> {
         Some("String".to_owned())
     }
 }
@@ -12,7 +12,7 @@ impl KeyExtractor for RealIpKeyExtractor {
 
     type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
 
-    #[cfg(feature = "log")]
+    #[cfg(any(feature = "log", feature = "tracing"))]
     fn name(&self) -> &'static str {
         "real IP"
     }
@@ -61,7 +61,7 @@ impl KeyExtractor for RealIpKeyExtractor {
         }
     }
 
-    #[cfg(feature = "log")]
+    #[cfg(any(feature = "log", feature = "tracing"))]
     fn key_name(&self, key: &Self::Key) -> Option<String> {
         Some(key.to_string())
     }
@@ -0,0 +1,116 @@
+//! Compares the per-request cost of a large whitelist returned by the deprecated
+//! `whitelisted_keys` with one backed by a `HashSet` through `is_whitelisted`.
+//!
+//! Run with `cargo run --release --example whitelist_bench`.
+
+use std::collections::HashSet;
+use std::time::Instant;
+
+use actix_governor::{Governor, GovernorConfigBuilder, KeyExtractor, SimpleKeyExtractionError};
+use actix_web::dev::ServiceRequest;
+use actix_web::{test, web, App, HttpResponse};
+
+const WHITELIST_SIZE: usize = 10_000;
+const REQUESTS: usize = 20_000;
+
+fn api_key(req: &ServiceRequest) -> Result<String, SimpleKeyExtractionError<&'static str>> {
+    req.headers()
+        .get("x-api-key")
+        .and_then(|key| key.to_str().ok())
+        .map(str::to_owned)
+        .ok_or_else(|| SimpleKeyExtractionError::new("Missing x-api-key header"))
+}
+
+fn whitelist() -> impl Iterator<Item = String> {
+    (0..WHITELIST_SIZE).map(|i| format!("partner-{i}"))
+}
+
+/// Returns the whitelist as a `Vec`, allocated for every request.
+#[derive(Clone)]
+struct VecWhitelist;
+
+impl KeyExtractor for VecWhitelist {
+    type Key = String;
+    type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        "API key"
+    }
+
+    fn extract(&self, req: &ServiceRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+        api_key(req)
+    }
+
+    fn whitelisted_keys(&self) -> Vec<Self::Key> {
+        whitelist().collect()
+    }
+}
+
+/// Looks keys up in a `HashSet` that is built once.
+#[derive(Clone)]
+struct SetWhitelist(std::sync::Arc<HashSet<String>>);
+
+impl KeyExtractor for SetWhitelist {
+    type Key = String;
+    type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        "API key"
+    }
+
+    fn extract(&self, req: &ServiceRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+        api_key(req)
+    }
+
+    fn is_whitelisted(&self, key: &Self::Key) -> bool {
+        self.0.contains(key)
+    }
+}
+
+async fn run<K>(name: &str, key_extractor: K)
+where
+    K: KeyExtractor + Clone + Send + Sync + 'static,
+    K::Key: Send + Sync,
+{
+    let config = GovernorConfigBuilder::default()
+        .burst_size(u32::MAX)
+        .key_extractor(key_extractor)
+        .finish()
+        .unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(HttpResponse::Ok)),
+    )
+    .await;
+
+    let start = Instant::now();
+    for i in 0..REQUESTS {
+        // Every other request comes from a whitelisted partner
+        let key = match i % 2 {
+            0 => format!("partner-{}", i % WHITELIST_SIZE),
+            _ => format!("user-{}", i % 100),
+        };
+        let req = test::TestRequest::get()
+            .insert_header(("x-api-key", key))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{name}: {REQUESTS} requests in {elapsed:?} ({:?} per request)",
+        elapsed / REQUESTS as u32
+    );
+}
+
+#[actix_web::main]
+async fn main() {
+    run("whitelisted_keys (Vec)", VecWhitelist).await;
+    run(
+        "is_whitelisted (HashSet)",
+        SetWhitelist(std::sync::Arc::new(whitelist().collect())),
+    )
+    .await;
+}
@@ -0,0 +1,88 @@
+//! Rate limiting events for audit pipelines.
+
+use crate::{Decision, GovernorConfig, KeyExtractor};
+
+use actix_web::dev::ServiceRequest;
+use governor::{clock::QuantaInstant, middleware::RateLimitingMiddleware};
+use tokio::sync::mpsc::Sender;
+
+use std::fmt;
+use std::time::SystemTime;
+
+/// The outcome of a request in a [`RateLimitEvent`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateLimitOutcome {
+    /// The request is not covered by the configuration or its key is whitelisted.
+    Whitelisted,
+    /// The request is within the rate limit.
+    Allowed,
+    /// The request exceeds the rate limit.
+    RateLimited,
+    /// The request is rejected before rate limiting, for example because it carries
+    /// forwarded headers, but doesn't come from a trusted proxy.
+    Forbidden,
+    /// The key couldn't be extracted from the request.
+    ExtractionFailed,
+}
+
+/// A structured event about a request that was evaluated by the middleware,
+/// see [`audit_sink`](crate::GovernorConfigBuilder::audit_sink).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimitEvent<Key> {
+    /// The extracted key, if the request got that far.
+    pub key: Option<Key>,
+    /// The outcome of the rate limiting decision.
+    pub outcome: RateLimitOutcome,
+    /// The time the request was evaluated.
+    pub timestamp: SystemTime,
+    /// The path of the request.
+    pub path: String,
+}
+
+/// The sender of an audit channel that keeps the builder comparable.
+///
+/// Two sinks are equal if they send to the same channel.
+pub(crate) struct AuditSink<Key>(pub(crate) Sender<RateLimitEvent<Key>>);
+
+impl<Key> Clone for AuditSink<Key> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<Key> fmt::Debug for AuditSink<Key> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AuditSink")
+    }
+}
+
+impl<Key> PartialEq for AuditSink<Key> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.same_channel(&other.0)
+    }
+}
+
+impl<Key> Eq for AuditSink<Key> {}
+
+impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> GovernorConfig<K, M> {
+    /// Sends the event of a `decision` to the audit sink without waiting.
+    /// The event is dropped if the channel is full or closed.
+    pub(crate) fn audit<P>(&self, req: &ServiceRequest, decision: &Decision<K, P>) {
+        let Some(sink) = &self.audit_sink else {
+            return;
+        };
+        let (key, outcome) = match decision {
+            Decision::Whitelisted => (None, RateLimitOutcome::Whitelisted),
+            Decision::Allowed { key, .. } => (Some(key.clone()), RateLimitOutcome::Allowed),
+            Decision::RateLimited { key, .. } => (Some(key.clone()), RateLimitOutcome::RateLimited),
+            Decision::Forbidden(_) => (None, RateLimitOutcome::Forbidden),
+            Decision::ExtractionFailed { .. } => (None, RateLimitOutcome::ExtractionFailed),
+        };
+        let _ = sink.0.try_send(RateLimitEvent {
+            key,
+            outcome,
+            timestamp: SystemTime::now(),
+            path: req.path().to_owned(),
+        });
+    }
+}
@@ -0,0 +1,102 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// How a rate limited request should be logged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BlockLogLevel {
+    /// The key was blocked for the first time in a while.
+    First,
+    /// The key is still blocked, `suppressed` blocks weren't logged since the last message.
+    Repeated { suppressed: u64 },
+    /// The block shouldn't be logged.
+    Suppressed,
+}
+
+#[derive(Debug)]
+struct Block {
+    last_blocked: Instant,
+    last_logged: Instant,
+    suppressed: u64,
+}
+
+#[derive(Debug)]
+struct Blocks<K> {
+    blocks: HashMap<K, Block>,
+    last_cleanup: Instant,
+}
+
+/// Tracks the blocked keys to deduplicate the log output for repeated blocks.
+#[derive(Debug)]
+pub(crate) struct BlockLog<K> {
+    interval: Duration,
+    blocks: Arc<Mutex<Blocks<K>>>,
+}
+
+impl<K> Clone for BlockLog<K> {
+    fn clone(&self) -> Self {
+        Self {
+            interval: self.interval,
+            blocks: self.blocks.clone(),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone> BlockLog<K> {
+    pub(crate) fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            blocks: Arc::new(Mutex::new(Blocks {
+                blocks: HashMap::new(),
+                last_cleanup: Instant::now(),
+            })),
+        }
+    }
+
+    /// Records a block of `key` and returns how it should be logged.
+    ///
+    /// A block is logged as [`BlockLogLevel::First`] if the key wasn't blocked during the
+    /// last interval. Further blocks are summarized at most once per interval.
+    pub(crate) fn record(&self, key: &K) -> BlockLogLevel {
+        let now = Instant::now();
+        let interval = self.interval;
+        let mut blocks = self.blocks.lock().unwrap_or_else(PoisonError::into_inner);
+
+        // Forget keys that aren't blocked anymore so the map doesn't grow without bounds.
+        if now.duration_since(blocks.last_cleanup) >= interval {
+            blocks
+                .blocks
+                .retain(|_, block| now.duration_since(block.last_blocked) < interval);
+            blocks.last_cleanup = now;
+        }
+
+        match blocks.blocks.get_mut(key) {
+            Some(block) if now.duration_since(block.last_blocked) < interval => {
+                block.last_blocked = now;
+                if now.duration_since(block.last_logged) >= interval {
+                    let suppressed = block.suppressed;
+                    block.last_logged = now;
+                    block.suppressed = 0;
+                    BlockLogLevel::Repeated { suppressed }
+                } else {
+                    block.suppressed += 1;
+                    BlockLogLevel::Suppressed
+                }
+            }
+            _ => {
+                blocks.blocks.insert(
+                    key.clone(),
+                    Block {
+                        last_blocked: now,
+                        last_logged: now,
+                        suppressed: 0,
+                    },
+                );
+                BlockLogLevel::First
+            }
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Read-only introspection of a [`GovernorConfig`] for debug endpoints.
+
+use crate::{ChargeTiming, GovernorConfig, KeyExtractor};
+
+use actix_web::http::header::ContentType;
+use actix_web::HttpResponse;
+use governor::{clock::QuantaInstant, middleware::RateLimitingMiddleware};
+
+use std::fmt::Display;
+
+/// Renders an optional value as JSON, using `null` for `None`.
+fn json_option<T: Display>(value: Option<T>) -> String {
+    value.map_or_else(|| "null".to_owned(), |value| value.to_string())
+}
+
+/// Renders the items as a JSON array, quoting each item if `quoted` is set.
+fn json_array<T: Display>(items: impl IntoIterator<Item = T>, quoted: bool) -> String {
+    let items: Vec<String> = items
+        .into_iter()
+        .map(|item| match quoted {
+            true => format!(r#""{}""#, item),
+            false => item.to_string(),
+        })
+        .collect();
+    format!("[{}]", items.join(","))
+}
+
+impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> GovernorConfig<K, M> {
+    /// Renders the quota, the number of tracked keys and the active policy flags
+    /// of this configuration as JSON.
+    #[must_use]
+    pub fn debug_json(&self) -> String {
+        let methods = self
+            .methods
+            .as_ref()
+            .map(|methods| json_array(methods, true));
+        let skip_header = self
+            .skip_header
+            .as_ref()
+            .map(|(name, _)| format!(r#""{}""#, name));
+        let granularity = self
+            .retry_after_granularity
+            .map(|granularity| granularity.as_millis());
+        let charge_timing = match self.charge_timing {
+            ChargeTiming::Before => "before",
+            ChargeTiming::After => "after",
+        };
+        format!(
+            concat!(
+                r#"{{"quota":{{"burst_size":{},"period_ms":{}}},"tracked_keys":{},"#,
+                r#""methods":{},"permissive":{},"json_response":{},"#,
+                r#""trusted_forwarders":{},"penalize_extraction_errors":{},"guard":{},"#,
+                r#""unlimited_whitelist_headers":{},"skip_header":{},"#,
+                r#""retry_after_granularity_ms":{},"refund_statuses":{},"#,
+                r#""adaptive":{},"global_cap":{},"sample_rate":{},"draining":{},"#,
+                r#""brute_force_protection":{},"charge_timing":"{}"}}"#
+            ),
+            self.quota.burst_size(),
+            self.quota.replenish_interval().as_millis(),
+            self.tracked_keys(),
+            json_option(methods),
+            self.permissive,
+            self.json_response,
+            json_option(self.trusted_forwarders.as_ref().map(Vec::len)),
+            self.penalty.is_some(),
+            self.guard.is_some(),
+            self.unlimited_whitelist_headers,
+            json_option(skip_header),
+            json_option(granularity),
+            json_array(
+                self.refund_statuses.iter().map(|status| status.as_u16()),
+                false
+            ),
+            self.adaptive.is_some(),
+            self.global_cap.is_some(),
+            json_option(self.sampler.as_ref().map(|sampler| sampler.rate)),
+            self.is_draining(),
+            self.brute_force.is_some(),
+            charge_timing,
+        )
+    }
+
+    /// Returns a `200 OK` response with the [`debug_json`](Self::debug_json) of this
+    /// configuration, for example for an `/admin/ratelimit/config` endpoint.
+    ///
+    /// The response reveals how clients are limited, so the endpoint should be protected
+    /// by authentication and not be reachable by the public.
+    ///
+    /// ```rust
+    /// use actix_governor::governor::middleware::NoOpMiddleware;
+    /// use actix_governor::{GovernorConfig, GovernorConfigBuilder, PeerIpKeyExtractor};
+    /// use actix_web::{web, App, HttpResponse};
+    ///
+    /// type Config = GovernorConfig<PeerIpKeyExtractor, NoOpMiddleware>;
+    ///
+    /// // Make sure that this route is only reachable by authenticated operators
+    /// async fn ratelimit_config(config: web::Data<Config>) -> HttpResponse {
+    ///     config.debug_response()
+    /// }
+    ///
+    /// let config = GovernorConfigBuilder::default().finish().unwrap();
+    /// let app = App::new()
+    ///     .app_data(web::Data::new(config))
+    ///     .route("/admin/ratelimit/config", web::get().to(ratelimit_config));
+    /// ```
+    #[must_use]
+    pub fn debug_response(&self) -> HttpResponse {
+        HttpResponse::Ok()
+            .content_type(ContentType::json())
+            .body(self.debug_json())
+    }
+}
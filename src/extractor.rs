@@ -3,6 +3,7 @@ use std::fmt::{Debug, Display, Formatter};
 use actix_http::{HttpMessage, Payload};
 use actix_web::{FromRequest, HttpRequest, ResponseError};
 use futures::future::Ready;
+use std::convert::Infallible;
 
 use crate::{GovernorResult, KeyExtractor, PeerIpKeyExtractor};
 
@@ -39,3 +40,25 @@ impl<K: KeyExtractor> FromRequest for GovernorExtractor<K> {
             )
     }
 }
+
+/// Extractor for the remaining burst capacity of the current request.
+///
+/// The value is only present if the middleware uses
+/// [`use_headers`](crate::GovernorConfigBuilder::use_headers),
+/// it is `Some(0)` for requests that exceed the rate limit in permissive mode.
+/// Unlike [`GovernorExtractor`], this extractor never fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RemainingQuota(pub Option<u32>);
+
+impl FromRequest for RemainingQuota {
+    type Error = Infallible;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let remaining = req
+            .extensions()
+            .get::<RemainingQuota>()
+            .and_then(|remaining| remaining.0);
+        futures::future::ok(RemainingQuota(remaining))
+    }
+}
@@ -1,10 +1,19 @@
-use actix_http::StatusCode;
-use actix_web::{dev::ServiceRequest, http::header::ContentType};
-use actix_web::{HttpResponse, HttpResponseBuilder, ResponseError};
+use actix_http::{HttpMessage, Method, StatusCode, Version};
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header::{
+    ContentType, HeaderName, ACCEPT_LANGUAGE, USER_AGENT, X_FORWARDED_FOR,
+};
+use actix_web::web::Bytes;
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder, ResponseError};
+use futures::future::LocalBoxFuture;
 use governor::clock::{Clock, DefaultClock, QuantaInstant};
-use governor::NotUntil;
+use governor::{NotUntil, Quota};
 
 use std::fmt::{Debug, Display};
+use std::future::Future;
+use std::hash::{DefaultHasher, Hasher};
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{hash::Hash, net::IpAddr};
 
 /// Generic structure of what is needed to extract a rate-limiting key from an incoming request.
@@ -32,12 +41,18 @@ use std::{hash::Hash, net::IpAddr};
 /// For more see [`custom_key_bearer`](https://github.com/AaronErhardt/actix-governor/blob/main/examples/custom_key_bearer.rs) example
 pub trait KeyExtractor: Clone {
     /// The type of the key.
+    ///
+    /// The middleware clones the key at most once per request, when the rate limiter
+    /// stores the state of a new key. Options that track keys separately, like
+    /// [`audit_sink`](crate::GovernorConfigBuilder::audit_sink), clone it as well.
+    /// For large keys, consider wrapping them in an [`Arc`](std::sync::Arc),
+    /// so cloning only increments a reference count.
     type Key: Clone + Hash + Eq;
 
     /// The type of the error that can occur if key extraction from the request fails.
     type KeyExtractionError: ResponseError + 'static;
 
-    #[cfg(feature = "log")]
+    #[cfg(any(feature = "log", feature = "tracing"))]
     /// Name of this extractor (only used in logs).
     fn name(&self) -> &'static str;
 
@@ -90,26 +105,271 @@ pub trait KeyExtractor: Clone {
         negative: &NotUntil<QuantaInstant>,
         mut response: HttpResponseBuilder,
     ) -> HttpResponse {
-        let wait_time = negative
-            .wait_time_from(DefaultClock::default().now())
-            .as_secs();
+        let wait_time = negative.wait_time_from(DefaultClock::default().now());
         response
             .content_type(ContentType::plaintext())
-            .body(format!("Too many requests, retry in {}s", wait_time))
+            .body(format!(
+                "Too many requests, retry in {}",
+                self.format_retry_after(wait_time)
+            ))
+    }
+
+    /// Formats the wait time for the body of the default
+    /// [`exceed_rate_limit_response`](KeyExtractor::exceed_rate_limit_response),
+    /// for example to show "about a minute" instead of exact seconds.
+    ///
+    /// Defaults to the number of whole seconds, like `42s`.
+    fn format_retry_after(&self, wait: Duration) -> String {
+        format!("{}s", wait.as_secs())
     }
 
     /// Returns a list of whitelisted keys. If a key is in this list, it will never be rate-limited.
+    ///
+    /// The whitelist takes precedence over all quotas: requests with a whitelisted key are
+    /// passed through before any rate limiter, including the
+    /// [`global_cap`](crate::GovernorConfigBuilder::global_cap), is checked, so they never
+    /// consume quota and their keys aren't tracked.
+    ///
+    /// Replaced by [`is_whitelisted`](KeyExtractor::is_whitelisted), which is used by
+    /// the middleware and falls back to this list by default.
+    #[deprecated(
+        since = "0.9.0",
+        note = "Allocates on every request. Implement `is_whitelisted` instead."
+    )]
     fn whitelisted_keys(&self) -> Vec<Self::Key> {
         Vec::new()
     }
 
-    #[cfg(feature = "log")]
+    /// Returns whether `key` is whitelisted, so it will never be rate-limited.
+    ///
+    /// This is checked for every request. The default looks the key up in
+    /// [`whitelisted_keys`](KeyExtractor::whitelisted_keys), which allocates the list
+    /// every time. Extractors with large whitelists can back them by a `HashSet` instead.
+    #[allow(deprecated)]
+    fn is_whitelisted(&self, key: &Self::Key) -> bool {
+        self.whitelisted_keys().contains(key)
+    }
+
+    /// Returns the quota for the given key if it should differ from the configured quota.
+    ///
+    /// A separate rate limiter is created for each distinct quota and shared by all keys
+    /// with that quota. The information the quota is derived from should therefore be part
+    /// of the key, otherwise different quotas would still be applied to the same key.
+    fn quota(&self, _key: &Self::Key) -> Option<Quota> {
+        None
+    }
+
+    /// Returns the maximum size of the request body in bytes if the key depends on the body.
+    ///
+    /// If this returns `Some`, the middleware buffers the whole request body before calling
+    /// [`extract`](KeyExtractor::extract) and stores it as [`Bytes`] in the request extensions.
+    /// The body is still passed on to the service. Requests with larger bodies are rejected
+    /// with `413 Payload Too Large`.
+    ///
+    /// Buffering holds the whole body in memory and delays the request until it was
+    /// received completely, so only use this for endpoints with small bodies.
+    fn body_limit(&self) -> Option<usize> {
+        None
+    }
+
+    /// Returns a future that resolves the key before [`extract`](KeyExtractor::extract) is
+    /// called, for keys that need asynchronous lookups. The future has to store everything
+    /// `extract` needs in the request extensions.
+    ///
+    /// This is implemented by the [`AsyncKeyExtractorAdapter`],
+    /// which is usually easier than implementing it directly.
+    fn resolve(&self, _req: &HttpRequest) -> Option<LocalBoxFuture<'static, ()>> {
+        None
+    }
+
+    /// Returns a factor the burst size is scaled with for this request,
+    /// for example based on a plan level header set by an upstream service.
+    ///
+    /// As the quota of a governor rate limiter can't be changed, a separate rate limiter is
+    /// used for each multiplier. To keep their number small, the multiplier is rounded to the
+    /// nearest power of two between `0.125` and `32`, so this is only an approximation.
+    /// Keys that change their multiplier start with a fresh state in the other rate limiter.
+    fn burst_multiplier(&self, _req: &ServiceRequest) -> f64 {
+        1.0
+    }
+
+    /// Returns whether the key is only soft-limited, for example because it belongs
+    /// to an internal service that should never be blocked.
+    ///
+    /// Requests of soft-limited keys that exceed the quota are logged and passed on like in
+    /// [`permissive`](crate::GovernorConfigBuilder::permissive) mode, so the service can
+    /// still read the [`GovernorResult`](crate::GovernorResult) from the request extensions.
+    fn is_soft_limited(&self, _key: &Self::Key) -> bool {
+        false
+    }
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    /// Value of the extracted key (only used in logs).
+    fn key_name(&self, _key: &Self::Key) -> Option<String> {
+        None
+    }
+
+    /// Combine this extractor with `other` into a [`CompositeKeyExtractor`],
+    /// whose key is the tuple of both keys.
+    fn and<B>(self, other: B) -> CompositeKeyExtractor<Self, B>
+    where
+        B: KeyExtractor<KeyExtractionError = Self::KeyExtractionError>,
+    {
+        CompositeKeyExtractor::new(self, other)
+    }
+}
+
+/// Like a [KeyExtractor], but extracts the key asynchronously, for example to look up
+/// the user of a session cookie in a database or in Redis.
+///
+/// Use it with [`GovernorConfigBuilder::async_key_extractor`](crate::GovernorConfigBuilder::async_key_extractor),
+/// which wraps it in an [`AsyncKeyExtractorAdapter`]. The middleware awaits the key before
+/// the request is rate limited, so keep the lookup fast, because it delays every request.
+///
+/// ## Example
+/// ```rust
+/// use actix_governor::{AsyncKeyExtractor, GovernorConfigBuilder, SimpleKeyExtractionError};
+/// use actix_web::HttpRequest;
+///
+/// #[derive(Clone)]
+/// struct SessionUser;
+///
+/// impl AsyncKeyExtractor for SessionUser {
+///     type Key = u64;
+///     type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
+///
+///     # #[cfg(any(feature = "log", feature = "tracing"))]
+///     # fn name(&self) -> &'static str { "session user" }
+///     async fn extract(&self, req: &HttpRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+///         let session = req
+///             .cookie("session")
+///             .ok_or_else(|| SimpleKeyExtractionError::new("Missing session"))?;
+///         // Look up the user of the session, for example in Redis
+///         Ok(session.value().len() as u64)
+///     }
+/// }
+///
+/// let config = GovernorConfigBuilder::default()
+///     .async_key_extractor(SessionUser)
+///     .finish()
+///     .unwrap();
+/// ```
+pub trait AsyncKeyExtractor: Clone {
+    /// The type of the key.
+    type Key: Clone + Hash + Eq + 'static;
+
+    /// The type of the error that can occur if key extraction from the request fails.
+    type KeyExtractionError: ResponseError + 'static;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    /// Name of this extractor (only used in logs).
+    fn name(&self) -> &'static str;
+
+    /// Extraction method, the error is turned into a response like the
+    /// [`KeyExtractionError`](KeyExtractor::KeyExtractionError) of a [KeyExtractor].
+    fn extract(
+        &self,
+        req: &HttpRequest,
+    ) -> impl Future<Output = Result<Self::Key, Self::KeyExtractionError>>;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
     /// Value of the extracted key (only used in logs).
     fn key_name(&self, _key: &Self::Key) -> Option<String> {
         None
     }
 }
 
+/// The key of an [`AsyncKeyExtractor`] in the request extensions.
+struct ResolvedKey<K, E>(Result<K, E>);
+
+#[derive(Debug)]
+/// The error of an [`AsyncKeyExtractorAdapter`].
+pub enum AsyncKeyExtractionError<E> {
+    /// The [`AsyncKeyExtractor`] failed.
+    Extraction(E),
+    /// The key wasn't resolved, because the request didn't pass through the middleware,
+    /// for example when it was passed to [`GovernorConfig::decide`](crate::GovernorConfig::decide).
+    Unresolved,
+}
+
+impl<E: Display> Display for AsyncKeyExtractionError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Extraction(error) => error.fmt(f),
+            Self::Unresolved => f.write_str("The rate limiting key was not resolved"),
+        }
+    }
+}
+
+impl<E: ResponseError> ResponseError for AsyncKeyExtractionError<E> {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Extraction(error) => error.status_code(),
+            Self::Unresolved => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            Self::Extraction(error) => error.error_response(),
+            Self::Unresolved => HttpResponse::build(self.status_code())
+                .content_type(ContentType::plaintext())
+                .body(self.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A [KeyExtractor] that resolves the key with an [`AsyncKeyExtractor`] before the
+/// request is rate limited, see
+/// [`GovernorConfigBuilder::async_key_extractor`](crate::GovernorConfigBuilder::async_key_extractor).
+pub struct AsyncKeyExtractorAdapter<A: AsyncKeyExtractor> {
+    inner: A,
+}
+
+impl<A: AsyncKeyExtractor> AsyncKeyExtractorAdapter<A> {
+    /// Create a new extractor that resolves the key with `inner`.
+    #[must_use]
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+}
+
+impl<A: AsyncKeyExtractor + 'static> KeyExtractor for AsyncKeyExtractorAdapter<A> {
+    type Key = A::Key;
+    type KeyExtractionError = AsyncKeyExtractionError<A::KeyExtractionError>;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn extract(&self, req: &ServiceRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+        match req
+            .extensions_mut()
+            .remove::<ResolvedKey<A::Key, A::KeyExtractionError>>()
+        {
+            Some(ResolvedKey(key)) => key.map_err(AsyncKeyExtractionError::Extraction),
+            None => Err(AsyncKeyExtractionError::Unresolved),
+        }
+    }
+
+    fn resolve(&self, req: &HttpRequest) -> Option<LocalBoxFuture<'static, ()>> {
+        let inner = self.inner.clone();
+        let req = req.clone();
+        Some(Box::pin(async move {
+            let key = inner.extract(&req).await;
+            req.extensions_mut()
+                .insert(ResolvedKey::<A::Key, A::KeyExtractionError>(key));
+        }))
+    }
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn key_name(&self, key: &Self::Key) -> Option<String> {
+        self.inner.key_name(key)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 /// A [KeyExtractor] that allow to do rate limiting for all incoming requests. This is useful if you want to hard-limit the HTTP load your app can handle.
 pub struct GlobalKeyExtractor;
@@ -211,7 +471,7 @@ impl KeyExtractor for GlobalKeyExtractor {
     type Key = ();
     type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
 
-    #[cfg(feature = "log")]
+    #[cfg(any(feature = "log", feature = "tracing"))]
     fn name(&self) -> &'static str {
         "global"
     }
@@ -220,7 +480,7 @@ impl KeyExtractor for GlobalKeyExtractor {
         Ok(())
     }
 
-    #[cfg(feature = "log")]
+    #[cfg(any(feature = "log", feature = "tracing"))]
     fn key_name(&self, _key: &Self::Key) -> Option<String> {
         None
     }
@@ -240,31 +500,1941 @@ impl KeyExtractor for GlobalKeyExtractor {
 /// - make absolutely sure that you only trust these headers when the peer IP is the IP of your reverse proxy (otherwise any user could set them to fake its IP)
 pub struct PeerIpKeyExtractor;
 
+fn peer_ip(req: &ServiceRequest) -> Result<IpAddr, SimpleKeyExtractionError<&'static str>> {
+    req.peer_addr().map(|socket| socket.ip()).ok_or_else(|| {
+        SimpleKeyExtractionError::new("Could not extract peer IP address from request")
+    })
+}
+
+fn ip_key(mut ip: IpAddr) -> IpAddr {
+    // customers often get their own /56 prefix, apply rate-limiting per prefix instead of per
+    // address for IPv6
+    if let IpAddr::V6(ipv6) = ip {
+        let mut octets = ipv6.octets();
+        octets[7..16].fill(0);
+        ip = IpAddr::V6(octets.into());
+    }
+    ip
+}
+
 impl KeyExtractor for PeerIpKeyExtractor {
     type Key = IpAddr;
     type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
 
-    #[cfg(feature = "log")]
+    #[cfg(any(feature = "log", feature = "tracing"))]
     fn name(&self) -> &'static str {
         "peer IP"
     }
 
     fn extract(&self, req: &ServiceRequest) -> Result<Self::Key, Self::KeyExtractionError> {
-        let mut ip = req.peer_addr().map(|socket| socket.ip()).ok_or_else(|| {
-            SimpleKeyExtractionError::new("Could not extract peer IP address from request")
-        })?;
-        // customers often get their own /56 prefix, apply rate-limiting per prefix instead of per
-        // address for IPv6
-        if let IpAddr::V6(ipv6) = ip {
-            let mut octets = ipv6.octets();
-            octets[7..16].fill(0);
-            ip = IpAddr::V6(octets.into());
-        }
-        Ok(ip)
+        peer_ip(req).map(ip_key)
+    }
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn key_name(&self, key: &Self::Key) -> Option<String> {
+        Some(key.to_string())
+    }
+}
+
+impl PeerIpKeyExtractor {
+    /// Use `unknown_key` for requests without a peer IP address instead of
+    /// responding with `500 Internal Server Error`.
+    ///
+    /// All such requests share a single bucket, so they are still limited collectively.
+    #[must_use]
+    pub const fn with_unknown_key(unknown_key: IpAddr) -> PeerIpOrUnknownKeyExtractor {
+        PeerIpOrUnknownKeyExtractor { unknown_key }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A [KeyExtractor] that works like the [PeerIpKeyExtractor], but uses a fixed key
+/// for requests without a peer IP address instead of failing.
+///
+/// It is created by [`PeerIpKeyExtractor::with_unknown_key`].
+pub struct PeerIpOrUnknownKeyExtractor {
+    unknown_key: IpAddr,
+}
+
+impl KeyExtractor for PeerIpOrUnknownKeyExtractor {
+    type Key = IpAddr;
+    type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        "peer IP"
+    }
+
+    fn extract(&self, req: &ServiceRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+        Ok(peer_ip(req).map(ip_key).unwrap_or(self.unknown_key))
     }
 
-    #[cfg(feature = "log")]
+    #[cfg(any(feature = "log", feature = "tracing"))]
     fn key_name(&self, key: &Self::Key) -> Option<String> {
         Some(key.to_string())
     }
 }
+
+/// Indicates whether a request is authenticated, see [`AnonymousKeyExtractor`].
+#[derive(Debug, Clone)]
+pub enum AuthIndicator {
+    /// The request is authenticated if it carries this header.
+    Header(HeaderName),
+    /// The request is authenticated if this function returns `true`.
+    /// Use [`AuthIndicator::extension`] to check for a request extension.
+    Custom(fn(&ServiceRequest) -> bool),
+}
+
+impl AuthIndicator {
+    /// The request is authenticated if it carries an extension of type `T`,
+    /// for example one inserted by an authentication middleware.
+    #[must_use]
+    pub fn extension<T: 'static>() -> Self {
+        Self::Custom(|req| req.extensions().contains::<T>())
+    }
+
+    fn is_present(&self, req: &ServiceRequest) -> bool {
+        match self {
+            Self::Header(name) => req.headers().contains_key(name),
+            Self::Custom(check) => check(req),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A [KeyExtractor] that only limits anonymous requests by their peer IP
+/// (the same way as [`PeerIpKeyExtractor`]) while authenticated requests are whitelisted.
+///
+/// Only the presence of the [`AuthIndicator`] is checked, validating the credentials
+/// is still the job of your application. If you use [`AuthIndicator::extension`],
+/// make sure the middleware inserting the extension runs before the governor middleware.
+///
+/// Usually this extractor is set with
+/// [`GovernorConfigBuilder::limit_anonymous_by_ip`](crate::GovernorConfigBuilder::limit_anonymous_by_ip).
+pub struct AnonymousKeyExtractor {
+    auth_indicator: AuthIndicator,
+}
+
+impl AnonymousKeyExtractor {
+    /// Create a new extractor that whitelists requests with the given `auth_indicator`.
+    pub fn new(auth_indicator: AuthIndicator) -> Self {
+        Self { auth_indicator }
+    }
+}
+
+impl KeyExtractor for AnonymousKeyExtractor {
+    /// `None` for authenticated requests.
+    type Key = Option<IpAddr>;
+    type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        "anonymous peer IP"
+    }
+
+    fn extract(&self, req: &ServiceRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+        if self.auth_indicator.is_present(req) {
+            Ok(None)
+        } else {
+            PeerIpKeyExtractor.extract(req).map(Some)
+        }
+    }
+
+    fn whitelisted_keys(&self) -> Vec<Self::Key> {
+        vec![None]
+    }
+
+    fn is_whitelisted(&self, key: &Self::Key) -> bool {
+        key.is_none()
+    }
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn key_name(&self, key: &Self::Key) -> Option<String> {
+        key.map(|ip| ip.to_string())
+    }
+}
+
+/// The source of the user identity, see [`UserKeyExtractor`].
+#[derive(Debug, Clone)]
+pub enum UserSource {
+    /// The user identity is the value of this header.
+    Header(HeaderName),
+    /// The user identity is returned by this function, for example a claim like
+    /// `preferred_username` from a token that was decoded by an authentication middleware.
+    Custom(fn(&ServiceRequest) -> Option<String>),
+}
+
+#[derive(Debug, Clone)]
+/// A [KeyExtractor] that uses the identity of the user, for example the
+/// email address or username, as key to apply per-user limits.
+///
+/// The identity is trimmed and lowercased, so the same user with a different
+/// casing doesn't get a separate bucket. Requests without an identity are rejected
+/// with `401 Unauthorized`.
+///
+/// ```rust
+/// use actix_governor::{GovernorConfigBuilder, UserKeyExtractor, UserSource};
+/// use actix_web::http::header::HeaderName;
+///
+/// let config = GovernorConfigBuilder::default()
+///     .key_extractor(UserKeyExtractor::new(UserSource::Header(
+///         HeaderName::from_static("x-user-email"),
+///     )))
+///     .finish()
+///     .unwrap();
+/// ```
+pub struct UserKeyExtractor {
+    source: UserSource,
+}
+
+impl UserKeyExtractor {
+    /// Create a new extractor that reads the user identity from `source`.
+    #[must_use]
+    pub const fn new(source: UserSource) -> Self {
+        Self { source }
+    }
+}
+
+impl KeyExtractor for UserKeyExtractor {
+    type Key = String;
+    type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        "user"
+    }
+
+    fn extract(&self, req: &ServiceRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+        let user = match &self.source {
+            UserSource::Header(name) => req
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned),
+            UserSource::Custom(extract) => extract(req),
+        };
+        user.map(|user| user.trim().to_lowercase())
+            .filter(|user| !user.is_empty())
+            .ok_or_else(|| {
+                SimpleKeyExtractionError::new("Missing user identity")
+                    .set_status_code(StatusCode::UNAUTHORIZED)
+            })
+    }
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn key_name(&self, key: &Self::Key) -> Option<String> {
+        Some(key.clone())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A [KeyExtractor] that uses the value of a request header as key,
+/// for example an API key in the `X-Api-Key` header.
+///
+/// Requests without the header or with a value that isn't valid UTF-8
+/// are rejected with `401 Unauthorized`.
+///
+/// ```rust
+/// use actix_governor::{GovernorConfigBuilder, HeaderKeyExtractor};
+///
+/// let config = GovernorConfigBuilder::default()
+///     .key_extractor(HeaderKeyExtractor::new("x-api-key"))
+///     .finish()
+///     .unwrap();
+/// ```
+pub struct HeaderKeyExtractor {
+    name: HeaderName,
+}
+
+impl HeaderKeyExtractor {
+    /// Create a new extractor that uses the value of the header `name`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` isn't a valid lowercase header name.
+    #[must_use]
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name: HeaderName::from_static(name),
+        }
+    }
+}
+
+impl KeyExtractor for HeaderKeyExtractor {
+    type Key = String;
+    type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        "header"
+    }
+
+    fn extract(&self, req: &ServiceRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+        req.headers()
+            .get(&self.name)
+            .and_then(|value| std::str::from_utf8(value.as_bytes()).ok())
+            .map(str::to_owned)
+            .ok_or_else(|| {
+                SimpleKeyExtractionError::new("Missing or invalid rate limiting header")
+                    .set_status_code(StatusCode::UNAUTHORIZED)
+            })
+    }
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn key_name(&self, key: &Self::Key) -> Option<String> {
+        Some(key.clone())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A [KeyExtractor] that uses the client IP from the `X-Forwarded-For` header as key,
+/// applying the same IPv6 prefix handling as [`PeerIpKeyExtractor`].
+///
+/// There are two ways to find the client IP in the forwarded chain:
+///
+/// + **Trusted proxy set** ([`new`](Self::new)): the header is only honored if the peer is
+///   one of the trusted proxies. The chain is then walked from the right, skipping trusted
+///   proxies, and the first untrusted address is used. This works as long as you know the
+///   addresses of all of your proxies. Whole networks of proxies can be trusted with
+///   [`trusted_ranges`](Self::trusted_ranges).
+/// + **Trusted hops** ([`trusted_hops`](Self::trusted_hops)): exactly `hops` addresses are
+///   stripped from the right end of the chain and the next one is used. This is the robust
+///   approach if each of your proxy layers appends an address, but the addresses of the
+///   proxies are not known upfront (e.g. autoscaling load balancers).
+///
+/// Addresses left of the client IP are set by the client and are never used.
+/// If the chain is too short or malformed, the peer IP is used instead.
+///
+/// If another proxy tier sets the `X-Real-IP` header, its addresses can be configured
+/// separately with [`real_ip_from`](Self::real_ip_from). Each header is only honored if
+/// the peer is one of the proxies that set this header, so a client that can reach
+/// one tier directly can't spoof the header of the other tier.
+/// `X-Real-IP` takes precedence if the peer is trusted for both headers.
+pub struct XForwardedForKeyExtractor {
+    trusted_proxies: Vec<IpAddr>,
+    /// CIDR ranges of trusted proxies as network address and prefix length.
+    trusted_ranges: Vec<(IpAddr, u8)>,
+    trusted_hops: Option<usize>,
+    real_ip_proxies: Vec<IpAddr>,
+}
+
+impl XForwardedForKeyExtractor {
+    /// Create a new extractor that only honors `X-Forwarded-For` for requests
+    /// coming from one of the `trusted_proxies`.
+    #[must_use]
+    pub fn new(trusted_proxies: Vec<IpAddr>) -> Self {
+        Self {
+            trusted_proxies,
+            trusted_ranges: Vec::new(),
+            trusted_hops: None,
+            real_ip_proxies: Vec::new(),
+        }
+    }
+
+    /// Also trust all proxies in the CIDR `ranges`, given as network address and prefix
+    /// length, for example `(10.0.0.0, 8)` for `10.0.0.0/8`.
+    ///
+    /// An IPv4 range never contains IPv6 addresses and vice versa, and prefix lengths
+    /// longer than the address are treated as the full address.
+    #[must_use]
+    pub fn trusted_ranges(mut self, ranges: Vec<(IpAddr, u8)>) -> Self {
+        self.trusted_ranges = ranges;
+        self
+    }
+
+    /// Strip exactly `hops` trailing addresses from the forwarded chain
+    /// instead of skipping the trusted proxies.
+    ///
+    /// If no trusted proxies are configured, every peer is assumed to be one of your proxies.
+    #[must_use]
+    pub fn trusted_hops(mut self, hops: usize) -> Self {
+        self.trusted_hops = Some(hops);
+        self
+    }
+
+    /// Honor the `X-Real-IP` header for requests coming from one of the `real_ip_proxies`.
+    ///
+    /// These proxies are trusted independently of the `X-Forwarded-For` proxies.
+    #[must_use]
+    pub fn real_ip_from(mut self, real_ip_proxies: Vec<IpAddr>) -> Self {
+        self.real_ip_proxies = real_ip_proxies;
+        self
+    }
+
+    fn real_ip(&self, req: &ServiceRequest, peer: IpAddr) -> Option<IpAddr> {
+        if !self.real_ip_proxies.contains(&peer) {
+            return None;
+        }
+        req.headers()
+            .get(X_REAL_IP)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_forwarded_ip)
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.contains(&ip)
+            || self
+                .trusted_ranges
+                .iter()
+                .any(|&(network, prefix)| in_range(ip, network, prefix))
+    }
+
+    fn client_ip(&self, req: &ServiceRequest, peer: IpAddr) -> Option<IpAddr> {
+        let peer_trusted = self.is_trusted(peer)
+            || (self.trusted_proxies.is_empty()
+                && self.trusted_ranges.is_empty()
+                && self.trusted_hops.is_some());
+        if !peer_trusted {
+            return None;
+        }
+
+        let chain: Vec<Option<IpAddr>> = req
+            .headers()
+            .get_all(X_FORWARDED_FOR)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(parse_forwarded_ip)
+            .collect();
+
+        match self.trusted_hops {
+            Some(hops) => chain
+                .len()
+                .checked_sub(hops + 1)
+                .and_then(|index| chain[index]),
+            None => chain
+                .into_iter()
+                .rev()
+                .find(|ip| !ip.is_some_and(|ip| self.is_trusted(ip)))
+                .flatten(),
+        }
+    }
+}
+
+/// Returns whether `ip` is in the CIDR range of `network` with the `prefix` length.
+pub(crate) fn in_range(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            let mask = u32::MAX
+                .checked_shl(32 - u32::from(prefix.min(32)))
+                .unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            let mask = u128::MAX
+                .checked_shl(128 - u32::from(prefix.min(128)))
+                .unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+const X_REAL_IP: HeaderName = HeaderName::from_static("x-real-ip");
+
+fn parse_forwarded_ip(value: &str) -> Option<IpAddr> {
+    let value = value.trim();
+    value
+        .parse::<IpAddr>()
+        .or_else(|_| value.parse::<SocketAddr>().map(|socket| socket.ip()))
+        .ok()
+}
+
+impl KeyExtractor for XForwardedForKeyExtractor {
+    type Key = IpAddr;
+    type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        "X-Forwarded-For IP"
+    }
+
+    fn extract(&self, req: &ServiceRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+        let peer = peer_ip(req)?;
+        let ip = self
+            .real_ip(req, peer)
+            .or_else(|| self.client_ip(req, peer))
+            .unwrap_or(peer);
+        Ok(ip_key(ip))
+    }
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn key_name(&self, key: &Self::Key) -> Option<String> {
+        Some(key.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A [KeyExtractor] that combines the request scheme (`http` or `https`) with the key of
+/// another extractor, so that different quotas can be applied to plain HTTP and HTTPS requests.
+///
+/// This is useful during a migration to HTTPS, to aggressively limit plain HTTP requests
+/// while leaving HTTPS requests with a generous quota.
+///
+/// The scheme is read from [`ConnectionInfo::scheme`](actix_web::dev::ConnectionInfo::scheme),
+/// which honors the `Forwarded` and `X-Forwarded-Proto` headers.
+///
+/// # Example
+/// ```rust
+/// use actix_governor::{GovernorConfigBuilder, PeerIpKeyExtractor, SchemeKeyExtractor};
+/// use governor::Quota;
+/// use std::num::NonZeroU32;
+///
+/// let config = GovernorConfigBuilder::default()
+///     .key_extractor(
+///         SchemeKeyExtractor::new(PeerIpKeyExtractor)
+///             .scheme_quota("http", Quota::per_minute(NonZeroU32::new(10).unwrap())),
+///     )
+///     .finish()
+///     .unwrap();
+/// ```
+pub struct SchemeKeyExtractor<K: KeyExtractor = PeerIpKeyExtractor> {
+    inner: K,
+    quotas: Vec<(String, Quota)>,
+}
+
+impl<K: KeyExtractor> SchemeKeyExtractor<K> {
+    /// Create a new extractor that combines the scheme with the key of `inner`.
+    pub fn new(inner: K) -> Self {
+        Self {
+            inner,
+            quotas: Vec::new(),
+        }
+    }
+
+    /// Use `quota` for requests with the given `scheme` instead of the configured quota.
+    #[must_use]
+    pub fn scheme_quota(mut self, scheme: &str, quota: Quota) -> Self {
+        self.quotas.push((scheme.to_ascii_lowercase(), quota));
+        self
+    }
+}
+
+impl<K: KeyExtractor> KeyExtractor for SchemeKeyExtractor<K> {
+    type Key = (String, K::Key);
+    type KeyExtractionError = K::KeyExtractionError;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn extract(&self, req: &ServiceRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+        let key = self.inner.extract(req)?;
+        let scheme = req.connection_info().scheme().to_ascii_lowercase();
+        Ok((scheme, key))
+    }
+
+    fn body_limit(&self) -> Option<usize> {
+        self.inner.body_limit()
+    }
+
+    fn resolve(&self, req: &HttpRequest) -> Option<LocalBoxFuture<'static, ()>> {
+        self.inner.resolve(req)
+    }
+
+    fn exceed_rate_limit_response(
+        &self,
+        negative: &NotUntil<QuantaInstant>,
+        response: HttpResponseBuilder,
+    ) -> HttpResponse {
+        self.inner.exceed_rate_limit_response(negative, response)
+    }
+
+    #[allow(deprecated)]
+    fn whitelisted_keys(&self) -> Vec<Self::Key> {
+        let keys = self.inner.whitelisted_keys();
+        ["http", "https"]
+            .iter()
+            .flat_map(|scheme| keys.iter().map(|key| (scheme.to_string(), key.clone())))
+            .collect()
+    }
+
+    fn is_whitelisted(&self, (_, key): &Self::Key) -> bool {
+        self.inner.is_whitelisted(key)
+    }
+
+    fn quota(&self, (scheme, key): &Self::Key) -> Option<Quota> {
+        self.quotas
+            .iter()
+            .find(|(s, _)| s == scheme)
+            .map(|(_, quota)| *quota)
+            .or_else(|| self.inner.quota(key))
+    }
+
+    fn is_soft_limited(&self, (_, key): &Self::Key) -> bool {
+        self.inner.is_soft_limited(key)
+    }
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn key_name(&self, (scheme, key): &Self::Key) -> Option<String> {
+        Some(match self.inner.key_name(key) {
+            Some(name) => format!("{} ({})", name, scheme),
+            None => scheme.clone(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A [KeyExtractor] that combines the HTTP version of the request with the key of
+/// another extractor, so that HTTP/1.x and HTTP/2 clients are limited independently
+/// and can get different quotas (e.g. to account for multiplexing).
+///
+/// # Example
+/// ```rust
+/// use actix_governor::{GovernorConfigBuilder, PeerIpKeyExtractor, VersionKeyExtractor};
+/// use actix_web::http::Version;
+/// use governor::Quota;
+/// use std::num::NonZeroU32;
+///
+/// let config = GovernorConfigBuilder::default()
+///     .key_extractor(
+///         VersionKeyExtractor::new(PeerIpKeyExtractor)
+///             .version_quota(Version::HTTP_2, Quota::per_second(NonZeroU32::new(50).unwrap())),
+///     )
+///     .finish()
+///     .unwrap();
+/// ```
+pub struct VersionKeyExtractor<K: KeyExtractor = PeerIpKeyExtractor> {
+    inner: K,
+    quotas: Vec<(Version, Quota)>,
+}
+
+impl<K: KeyExtractor> VersionKeyExtractor<K> {
+    /// Create a new extractor that combines the HTTP version with the key of `inner`.
+    pub fn new(inner: K) -> Self {
+        Self {
+            inner,
+            quotas: Vec::new(),
+        }
+    }
+
+    /// Use `quota` for requests with the given HTTP `version` instead of the configured quota.
+    #[must_use]
+    pub fn version_quota(mut self, version: Version, quota: Quota) -> Self {
+        self.quotas.push((version, quota));
+        self
+    }
+}
+
+impl<K: KeyExtractor> KeyExtractor for VersionKeyExtractor<K> {
+    type Key = (Version, K::Key);
+    type KeyExtractionError = K::KeyExtractionError;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn extract(&self, req: &ServiceRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+        Ok((req.version(), self.inner.extract(req)?))
+    }
+
+    fn body_limit(&self) -> Option<usize> {
+        self.inner.body_limit()
+    }
+
+    fn resolve(&self, req: &HttpRequest) -> Option<LocalBoxFuture<'static, ()>> {
+        self.inner.resolve(req)
+    }
+
+    fn exceed_rate_limit_response(
+        &self,
+        negative: &NotUntil<QuantaInstant>,
+        response: HttpResponseBuilder,
+    ) -> HttpResponse {
+        self.inner.exceed_rate_limit_response(negative, response)
+    }
+
+    #[allow(deprecated)]
+    fn whitelisted_keys(&self) -> Vec<Self::Key> {
+        let keys = self.inner.whitelisted_keys();
+        [
+            Version::HTTP_09,
+            Version::HTTP_10,
+            Version::HTTP_11,
+            Version::HTTP_2,
+            Version::HTTP_3,
+        ]
+        .iter()
+        .flat_map(|version| keys.iter().map(|key| (*version, key.clone())))
+        .collect()
+    }
+
+    fn is_whitelisted(&self, (_, key): &Self::Key) -> bool {
+        self.inner.is_whitelisted(key)
+    }
+
+    fn quota(&self, (version, key): &Self::Key) -> Option<Quota> {
+        self.quotas
+            .iter()
+            .find(|(v, _)| v == version)
+            .map(|(_, quota)| *quota)
+            .or_else(|| self.inner.quota(key))
+    }
+
+    fn is_soft_limited(&self, (_, key): &Self::Key) -> bool {
+        self.inner.is_soft_limited(key)
+    }
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn key_name(&self, (version, key): &Self::Key) -> Option<String> {
+        Some(match self.inner.key_name(key) {
+            Some(name) => format!("{} ({:?})", name, version),
+            None => format!("{:?}", version),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A [KeyExtractor] that uses a hash of the request body as key, regardless of the client.
+///
+/// This collapses identical payloads within the quota window, for example
+/// duplicate deliveries to a webhook endpoint.
+///
+/// The middleware has to buffer the whole body to compute the key, see
+/// [`KeyExtractor::body_limit`]. Requests with bodies larger than `max_body_size`
+/// are rejected with `413 Payload Too Large`.
+///
+/// ```rust
+/// use actix_governor::{BodyHashKeyExtractor, GovernorConfigBuilder};
+///
+/// let config = GovernorConfigBuilder::default()
+///     .key_extractor(BodyHashKeyExtractor::new(64 * 1024))
+///     .finish()
+///     .unwrap();
+/// ```
+pub struct BodyHashKeyExtractor {
+    max_body_size: usize,
+}
+
+impl BodyHashKeyExtractor {
+    /// Create a new extractor that buffers request bodies of up to `max_body_size` bytes.
+    #[must_use]
+    pub const fn new(max_body_size: usize) -> Self {
+        Self { max_body_size }
+    }
+}
+
+impl KeyExtractor for BodyHashKeyExtractor {
+    type Key = u64;
+    type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        "body hash"
+    }
+
+    fn extract(&self, req: &ServiceRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+        let extensions = req.extensions();
+        let body = extensions
+            .get::<Bytes>()
+            .ok_or_else(|| SimpleKeyExtractionError::new("Request body was not buffered"))?;
+        let mut hasher = DefaultHasher::new();
+        body.hash(&mut hasher);
+        Ok(hasher.finish())
+    }
+
+    fn body_limit(&self) -> Option<usize> {
+        Some(self.max_body_size)
+    }
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn key_name(&self, key: &Self::Key) -> Option<String> {
+        Some(format!("{:016x}", key))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A [KeyExtractor] that uses a hash of the peer IP and a few stable client headers as key,
+/// by default `User-Agent` and `Accept-Language`, to tell apart anonymous clients behind
+/// the same IP without cookies.
+///
+/// This is a heuristic: clients that send the same headers from the same IP share a
+/// bucket, and a client that varies the headers gets a new bucket for every variation.
+/// Combine it with a limit on the peer IP, for example a second [`Governor`](crate::Governor)
+/// with the [PeerIpKeyExtractor], so a single host can't multiply its quota this way.
+///
+/// Only the hash is stored, not the raw IP and header values. The hash isn't salted,
+/// so it shouldn't be treated as anonymized, and it should be logged or exported
+/// with the same care as the IP address itself.
+///
+/// The peer IP is extracted like with the [PeerIpKeyExtractor], including its IPv6
+/// prefix handling, so requests without a peer address are rejected.
+///
+/// ```rust
+/// use actix_governor::{FingerprintKeyExtractor, GovernorConfigBuilder};
+///
+/// let config = GovernorConfigBuilder::default()
+///     .key_extractor(FingerprintKeyExtractor::new())
+///     .finish()
+///     .unwrap();
+/// ```
+pub struct FingerprintKeyExtractor {
+    headers: Vec<HeaderName>,
+}
+
+impl FingerprintKeyExtractor {
+    /// Create a new extractor that hashes the peer IP with the `User-Agent`
+    /// and `Accept-Language` headers.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            headers: vec![ACCEPT_LANGUAGE, USER_AGENT],
+        }
+    }
+
+    /// Hash the peer IP with the `headers` instead of the default ones.
+    /// The order of the headers doesn't matter.
+    #[must_use]
+    pub fn headers(mut self, mut headers: Vec<HeaderName>) -> Self {
+        headers.sort_unstable_by(|a, b| a.as_str().cmp(b.as_str()));
+        headers.dedup();
+        self.headers = headers;
+        self
+    }
+}
+
+impl Default for FingerprintKeyExtractor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyExtractor for FingerprintKeyExtractor {
+    type Key = u64;
+    type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        "fingerprint"
+    }
+
+    fn extract(&self, req: &ServiceRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+        let mut hasher = DefaultHasher::new();
+        PeerIpKeyExtractor.extract(req)?.hash(&mut hasher);
+        for name in &self.headers {
+            for value in req.headers().get_all(name) {
+                value.as_bytes().hash(&mut hasher);
+            }
+            // Separate the values of different headers
+            hasher.write_u8(0xff);
+        }
+        Ok(hasher.finish())
+    }
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn key_name(&self, key: &Self::Key) -> Option<String> {
+        Some(format!("{:016x}", key))
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A [KeyExtractor] that combines the key of another extractor with the current
+/// fixed time window, for example the calendar hour or day.
+///
+/// Each window uses new keys, so the quota effectively resets at every window boundary,
+/// like a fixed window counter. To allow `N` requests per window, set the burst size to
+/// `N` and the period to the window length, so the quota isn't replenished within a window.
+///
+/// Windows are aligned to UTC. For daily caps that should reset at midnight in
+/// another time zone, set its offset with [`utc_offset`](Self::utc_offset).
+///
+/// **Memory:** every window creates new keys for all active clients. Set a
+/// [`retain_interval`](crate::GovernorConfigBuilder::retain_interval) to drop the keys of
+/// past windows once their quota is replenished, otherwise memory usage grows with every window.
+///
+/// ```rust
+/// use actix_governor::{FixedWindowKeyExtractor, GovernorConfigBuilder, PeerIpKeyExtractor};
+///
+/// // 100 requests per IP and calendar hour
+/// let config = GovernorConfigBuilder::default()
+///     .seconds_per_request(3600)
+///     .burst_size(100)
+///     .key_extractor(FixedWindowKeyExtractor::hourly(PeerIpKeyExtractor))
+///     .finish()
+///     .unwrap();
+/// ```
+pub struct FixedWindowKeyExtractor<K: KeyExtractor = PeerIpKeyExtractor> {
+    inner: K,
+    window: Duration,
+    offset: i64,
+    now: fn() -> SystemTime,
+}
+
+impl<K: KeyExtractor> FixedWindowKeyExtractor<K> {
+    /// Create a new extractor that combines the key of `inner` with the current `window`.
+    /// Windows are aligned to the unix epoch.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window` is shorter than a second.
+    pub fn new(inner: K, window: Duration) -> Self {
+        assert!(
+            window.as_secs() > 0,
+            "the window must be at least one second"
+        );
+        Self {
+            inner,
+            window,
+            offset: 0,
+            now: SystemTime::now,
+        }
+    }
+
+    /// Create a new extractor that combines the key of `inner` with the current UTC hour.
+    pub fn hourly(inner: K) -> Self {
+        Self::new(inner, Duration::from_secs(3600))
+    }
+
+    /// Create a new extractor that combines the key of `inner` with the current UTC date,
+    /// for example for billing-style daily quotas.
+    ///
+    /// **Memory:** all active clients get new keys every day. Set a
+    /// [`retain_interval`](crate::GovernorConfigBuilder::retain_interval) to drop the keys
+    /// of past days.
+    ///
+    /// ```rust
+    /// use actix_governor::{FixedWindowKeyExtractor, GovernorConfigBuilder, PeerIpKeyExtractor};
+    ///
+    /// // 1000 requests per IP and day, resetting at midnight UTC+1
+    /// let config = GovernorConfigBuilder::default()
+    ///     .seconds_per_request(86400)
+    ///     .burst_size(1000)
+    ///     .key_extractor(FixedWindowKeyExtractor::daily(PeerIpKeyExtractor).utc_offset(3600))
+    ///     .finish()
+    ///     .unwrap();
+    /// ```
+    pub fn daily(inner: K) -> Self {
+        Self::new(inner, Duration::from_secs(86400))
+    }
+
+    /// Shift the windows by `seconds` east of UTC, for example `-5 * 3600` for UTC-5.
+    #[must_use]
+    pub fn utc_offset(mut self, seconds: i32) -> Self {
+        self.offset = i64::from(seconds);
+        self
+    }
+
+    /// Use `now` as time source instead of the system time, for example in tests.
+    #[must_use]
+    pub fn with_time_source(mut self, now: fn() -> SystemTime) -> Self {
+        self.now = now;
+        self
+    }
+
+    fn current_window(&self) -> u64 {
+        let elapsed = (self.now)().duration_since(UNIX_EPOCH).unwrap_or_default();
+        elapsed.as_secs().saturating_add_signed(self.offset) / self.window.as_secs()
+    }
+}
+
+impl<K: KeyExtractor> KeyExtractor for FixedWindowKeyExtractor<K> {
+    type Key = (u64, K::Key);
+    type KeyExtractionError = K::KeyExtractionError;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn extract(&self, req: &ServiceRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+        Ok((self.current_window(), self.inner.extract(req)?))
+    }
+
+    fn body_limit(&self) -> Option<usize> {
+        self.inner.body_limit()
+    }
+
+    fn resolve(&self, req: &HttpRequest) -> Option<LocalBoxFuture<'static, ()>> {
+        self.inner.resolve(req)
+    }
+
+    fn exceed_rate_limit_response(
+        &self,
+        negative: &NotUntil<QuantaInstant>,
+        response: HttpResponseBuilder,
+    ) -> HttpResponse {
+        self.inner.exceed_rate_limit_response(negative, response)
+    }
+
+    #[allow(deprecated)]
+    fn whitelisted_keys(&self) -> Vec<Self::Key> {
+        let window = self.current_window();
+        self.inner
+            .whitelisted_keys()
+            .into_iter()
+            .map(|key| (window, key))
+            .collect()
+    }
+
+    /// Keys whitelisted by `inner` are whitelisted in every window.
+    fn is_whitelisted(&self, (_, key): &Self::Key) -> bool {
+        self.inner.is_whitelisted(key)
+    }
+
+    fn quota(&self, (_, key): &Self::Key) -> Option<Quota> {
+        self.inner.quota(key)
+    }
+
+    fn burst_multiplier(&self, req: &ServiceRequest) -> f64 {
+        self.inner.burst_multiplier(req)
+    }
+
+    fn is_soft_limited(&self, (_, key): &Self::Key) -> bool {
+        self.inner.is_soft_limited(key)
+    }
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn key_name(&self, (window, key): &Self::Key) -> Option<String> {
+        Some(match self.inner.key_name(key) {
+            Some(name) => format!("{} (window {})", name, window),
+            None => format!("window {}", window),
+        })
+    }
+}
+
+/// The class of an HTTP method, see [`ReadWriteKeyExtractor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MethodClass {
+    /// Safe methods that don't modify state: `GET`, `HEAD` and `OPTIONS`.
+    Read,
+    /// All other methods.
+    Write,
+}
+
+impl MethodClass {
+    /// Classifies the `method` of a request.
+    #[must_use]
+    pub fn of(method: &Method) -> Self {
+        if [Method::GET, Method::HEAD, Method::OPTIONS].contains(method) {
+            Self::Read
+        } else {
+            Self::Write
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A [KeyExtractor] that combines the [`MethodClass`] of the request with the key of
+/// another extractor, so reads and writes use independent limiters with separate quotas.
+///
+/// Usually this extractor is set with [`presets::read_write`](crate::presets::read_write).
+pub struct ReadWriteKeyExtractor<K: KeyExtractor = PeerIpKeyExtractor> {
+    inner: K,
+    read_quota: Quota,
+    write_quota: Quota,
+}
+
+impl<K: KeyExtractor> ReadWriteKeyExtractor<K> {
+    /// Create a new extractor that applies `read_quota` to reads
+    /// and `write_quota` to writes of each key of `inner`.
+    pub const fn new(inner: K, read_quota: Quota, write_quota: Quota) -> Self {
+        Self {
+            inner,
+            read_quota,
+            write_quota,
+        }
+    }
+}
+
+impl<K: KeyExtractor> KeyExtractor for ReadWriteKeyExtractor<K> {
+    type Key = (MethodClass, K::Key);
+    type KeyExtractionError = K::KeyExtractionError;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn extract(&self, req: &ServiceRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+        Ok((MethodClass::of(req.method()), self.inner.extract(req)?))
+    }
+
+    fn body_limit(&self) -> Option<usize> {
+        self.inner.body_limit()
+    }
+
+    fn resolve(&self, req: &HttpRequest) -> Option<LocalBoxFuture<'static, ()>> {
+        self.inner.resolve(req)
+    }
+
+    fn exceed_rate_limit_response(
+        &self,
+        negative: &NotUntil<QuantaInstant>,
+        response: HttpResponseBuilder,
+    ) -> HttpResponse {
+        self.inner.exceed_rate_limit_response(negative, response)
+    }
+
+    #[allow(deprecated)]
+    fn whitelisted_keys(&self) -> Vec<Self::Key> {
+        self.inner
+            .whitelisted_keys()
+            .into_iter()
+            .flat_map(|key| [(MethodClass::Read, key.clone()), (MethodClass::Write, key)])
+            .collect()
+    }
+
+    fn is_whitelisted(&self, (_, key): &Self::Key) -> bool {
+        self.inner.is_whitelisted(key)
+    }
+
+    fn quota(&self, (class, _): &Self::Key) -> Option<Quota> {
+        Some(match class {
+            MethodClass::Read => self.read_quota,
+            MethodClass::Write => self.write_quota,
+        })
+    }
+
+    fn burst_multiplier(&self, req: &ServiceRequest) -> f64 {
+        self.inner.burst_multiplier(req)
+    }
+
+    fn is_soft_limited(&self, (_, key): &Self::Key) -> bool {
+        self.inner.is_soft_limited(key)
+    }
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn key_name(&self, (class, key): &Self::Key) -> Option<String> {
+        Some(match self.inner.key_name(key) {
+            Some(name) => format!("{} ({:?})", name, class),
+            None => format!("{:?}", class),
+        })
+    }
+}
+
+/// The coarse class of a client, see [`UserAgentClassKeyExtractor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UserAgentClass {
+    /// Crawlers and other automated clients.
+    Bot,
+    /// Interactive clients used by humans.
+    Browser,
+    /// Clients that couldn't be classified, including requests without a `User-Agent` header.
+    Unknown,
+}
+
+#[derive(Debug, Clone)]
+/// A [KeyExtractor] that combines the [`UserAgentClass`] of the request with the key of
+/// another extractor, so bots and browsers behind the same IP have separate buckets.
+///
+/// The class is derived from the `User-Agent` header by a user-provided classifier.
+/// Requests without a valid `User-Agent` header are classified as [`UserAgentClass::Unknown`]
+/// without calling the classifier.
+///
+/// ```rust
+/// use actix_governor::{GovernorConfigBuilder, PeerIpKeyExtractor, UserAgentClass, UserAgentClassKeyExtractor};
+///
+/// let config = GovernorConfigBuilder::default()
+///     .key_extractor(UserAgentClassKeyExtractor::new(PeerIpKeyExtractor, |user_agent| {
+///         if user_agent.to_lowercase().contains("bot") {
+///             UserAgentClass::Bot
+///         } else if user_agent.starts_with("Mozilla/") {
+///             UserAgentClass::Browser
+///         } else {
+///             UserAgentClass::Unknown
+///         }
+///     }))
+///     .finish()
+///     .unwrap();
+/// ```
+pub struct UserAgentClassKeyExtractor<K: KeyExtractor = PeerIpKeyExtractor> {
+    inner: K,
+    classify: fn(&str) -> UserAgentClass,
+}
+
+impl<K: KeyExtractor> UserAgentClassKeyExtractor<K> {
+    /// Create a new extractor that separates each key of `inner`
+    /// by the class that `classify` returns for the `User-Agent` header.
+    #[must_use]
+    pub const fn new(inner: K, classify: fn(&str) -> UserAgentClass) -> Self {
+        Self { inner, classify }
+    }
+}
+
+impl<K: KeyExtractor> KeyExtractor for UserAgentClassKeyExtractor<K> {
+    type Key = (UserAgentClass, K::Key);
+    type KeyExtractionError = K::KeyExtractionError;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn extract(&self, req: &ServiceRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+        let class = req
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map_or(UserAgentClass::Unknown, self.classify);
+        Ok((class, self.inner.extract(req)?))
+    }
+
+    fn body_limit(&self) -> Option<usize> {
+        self.inner.body_limit()
+    }
+
+    fn resolve(&self, req: &HttpRequest) -> Option<LocalBoxFuture<'static, ()>> {
+        self.inner.resolve(req)
+    }
+
+    fn exceed_rate_limit_response(
+        &self,
+        negative: &NotUntil<QuantaInstant>,
+        response: HttpResponseBuilder,
+    ) -> HttpResponse {
+        self.inner.exceed_rate_limit_response(negative, response)
+    }
+
+    #[allow(deprecated)]
+    fn whitelisted_keys(&self) -> Vec<Self::Key> {
+        self.inner
+            .whitelisted_keys()
+            .into_iter()
+            .flat_map(|key| {
+                [
+                    (UserAgentClass::Bot, key.clone()),
+                    (UserAgentClass::Browser, key.clone()),
+                    (UserAgentClass::Unknown, key),
+                ]
+            })
+            .collect()
+    }
+
+    fn is_whitelisted(&self, (_, key): &Self::Key) -> bool {
+        self.inner.is_whitelisted(key)
+    }
+
+    fn quota(&self, (_, key): &Self::Key) -> Option<Quota> {
+        self.inner.quota(key)
+    }
+
+    fn burst_multiplier(&self, req: &ServiceRequest) -> f64 {
+        self.inner.burst_multiplier(req)
+    }
+
+    fn is_soft_limited(&self, (_, key): &Self::Key) -> bool {
+        self.inner.is_soft_limited(key)
+    }
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn key_name(&self, (class, key): &Self::Key) -> Option<String> {
+        Some(match self.inner.key_name(key) {
+            Some(name) => format!("{} ({:?})", name, class),
+            None => format!("{:?}", class),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A [KeyExtractor] that combines the key of another extractor with the route pattern
+/// of the request, so each client has an independent budget for every endpoint.
+///
+/// The route pattern is the one registered with the app, like `/users/{id}`, so all users
+/// share a single endpoint. Requests that don't match any route share one budget per key.
+///
+/// The [whitelisted keys](KeyExtractor::is_whitelisted) of `inner` are not carried over,
+/// because the route patterns aren't known when the configuration is built.
+///
+/// Usually this extractor is set with
+/// [`GovernorConfigBuilder::per_endpoint`](crate::GovernorConfigBuilder::per_endpoint).
+pub struct EndpointKeyExtractor<K: KeyExtractor = PeerIpKeyExtractor> {
+    inner: K,
+}
+
+impl<K: KeyExtractor> EndpointKeyExtractor<K> {
+    /// Create a new extractor that separates each key of `inner` by the route pattern.
+    #[must_use]
+    pub const fn new(inner: K) -> Self {
+        Self { inner }
+    }
+}
+
+impl<K: KeyExtractor> KeyExtractor for EndpointKeyExtractor<K> {
+    /// The key of the inner extractor and the route pattern,
+    /// or `None` for requests that don't match any route.
+    type Key = (K::Key, Option<String>);
+    type KeyExtractionError = K::KeyExtractionError;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn extract(&self, req: &ServiceRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+        Ok((self.inner.extract(req)?, req.match_pattern()))
+    }
+
+    fn body_limit(&self) -> Option<usize> {
+        self.inner.body_limit()
+    }
+
+    fn resolve(&self, req: &HttpRequest) -> Option<LocalBoxFuture<'static, ()>> {
+        self.inner.resolve(req)
+    }
+
+    fn exceed_rate_limit_response(
+        &self,
+        negative: &NotUntil<QuantaInstant>,
+        response: HttpResponseBuilder,
+    ) -> HttpResponse {
+        self.inner.exceed_rate_limit_response(negative, response)
+    }
+
+    fn quota(&self, (key, _): &Self::Key) -> Option<Quota> {
+        self.inner.quota(key)
+    }
+
+    fn burst_multiplier(&self, req: &ServiceRequest) -> f64 {
+        self.inner.burst_multiplier(req)
+    }
+
+    fn is_soft_limited(&self, (key, _): &Self::Key) -> bool {
+        self.inner.is_soft_limited(key)
+    }
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn key_name(&self, (key, pattern): &Self::Key) -> Option<String> {
+        let pattern = pattern.as_deref().unwrap_or("unmatched route");
+        Some(match self.inner.key_name(key) {
+            Some(name) => format!("{} ({})", name, pattern),
+            None => pattern.to_owned(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A [KeyExtractor] that uses the route pattern of the request as key, like `/users/{id}`,
+/// so a single middleware enforces an independent quota for each endpoint that is shared
+/// by all clients. Requests that don't match any route use their raw path instead.
+///
+/// **Warning:** every raw path of an unmatched request is tracked as its own key, so clients
+/// can create an unbounded number of keys by requesting random paths. Consider wrapping the
+/// service in a default route or setting a
+/// [`retain_interval`](crate::GovernorConfigBuilder::retain_interval) to drop stale keys.
+///
+/// To limit each client per endpoint instead, use an [`EndpointKeyExtractor`] or
+/// [`per_endpoint`](crate::GovernorConfigBuilder::per_endpoint).
+///
+/// ```rust
+/// use actix_governor::{GovernorConfigBuilder, PathKeyExtractor};
+///
+/// let config = GovernorConfigBuilder::default()
+///     .key_extractor(PathKeyExtractor)
+///     .finish()
+///     .unwrap();
+/// ```
+pub struct PathKeyExtractor;
+
+impl KeyExtractor for PathKeyExtractor {
+    type Key = String;
+    type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        "path"
+    }
+
+    fn extract(&self, req: &ServiceRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+        Ok(req.match_pattern().unwrap_or_else(|| req.path().to_owned()))
+    }
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn key_name(&self, key: &Self::Key) -> Option<String> {
+        Some(key.clone())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A [KeyExtractor] that combines the key of another extractor with the route pattern
+/// and the `Idempotency-Key` header of the request, so retries of the same operation on
+/// an endpoint share one budget, while different operations are independent.
+///
+/// This is useful for webhook and payment APIs, to limit how often a client retries
+/// a single operation without limiting how many different operations it starts.
+/// The key of `inner` is part of the key, so clients can't deplete the budget of an
+/// operation of another client by reusing its idempotency key.
+///
+/// Requests without the header, or with a header that isn't valid UTF-8, fall back to
+/// the key of `inner` on the endpoint, so they share one budget per client and endpoint.
+///
+/// The [whitelisted keys](KeyExtractor::is_whitelisted) of `inner` are not carried over,
+/// because the route patterns aren't known when the configuration is built.
+///
+/// ```rust
+/// use actix_governor::{GovernorConfigBuilder, IdempotencyKeyExtractor, PeerIpKeyExtractor};
+///
+/// let config = GovernorConfigBuilder::default()
+///     .key_extractor(IdempotencyKeyExtractor::new(PeerIpKeyExtractor))
+///     .finish()
+///     .unwrap();
+/// ```
+pub struct IdempotencyKeyExtractor<K: KeyExtractor = PeerIpKeyExtractor> {
+    inner: K,
+    header: HeaderName,
+}
+
+impl<K: KeyExtractor> IdempotencyKeyExtractor<K> {
+    /// Create a new extractor that separates each key of `inner`
+    /// by the route pattern and the `Idempotency-Key` header.
+    #[must_use]
+    pub const fn new(inner: K) -> Self {
+        Self {
+            inner,
+            header: HeaderName::from_static("idempotency-key"),
+        }
+    }
+
+    /// Read the idempotency key from the header `name` instead of `Idempotency-Key`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` isn't a valid lowercase header name.
+    #[must_use]
+    pub fn header(mut self, name: &'static str) -> Self {
+        self.header = HeaderName::from_static(name);
+        self
+    }
+}
+
+impl<K: KeyExtractor> KeyExtractor for IdempotencyKeyExtractor<K> {
+    /// The key of the inner extractor, the route pattern, or `None` for requests
+    /// that don't match any route, and the idempotency key, if any.
+    type Key = (K::Key, Option<String>, Option<String>);
+    type KeyExtractionError = K::KeyExtractionError;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn extract(&self, req: &ServiceRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+        let idempotency_key = req
+            .headers()
+            .get(&self.header)
+            .and_then(|value| std::str::from_utf8(value.as_bytes()).ok())
+            .map(str::to_owned);
+        Ok((
+            self.inner.extract(req)?,
+            req.match_pattern(),
+            idempotency_key,
+        ))
+    }
+
+    fn body_limit(&self) -> Option<usize> {
+        self.inner.body_limit()
+    }
+
+    fn resolve(&self, req: &HttpRequest) -> Option<LocalBoxFuture<'static, ()>> {
+        self.inner.resolve(req)
+    }
+
+    fn exceed_rate_limit_response(
+        &self,
+        negative: &NotUntil<QuantaInstant>,
+        response: HttpResponseBuilder,
+    ) -> HttpResponse {
+        self.inner.exceed_rate_limit_response(negative, response)
+    }
+
+    fn quota(&self, (key, _, _): &Self::Key) -> Option<Quota> {
+        self.inner.quota(key)
+    }
+
+    fn burst_multiplier(&self, req: &ServiceRequest) -> f64 {
+        self.inner.burst_multiplier(req)
+    }
+
+    fn is_soft_limited(&self, (key, _, _): &Self::Key) -> bool {
+        self.inner.is_soft_limited(key)
+    }
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn key_name(&self, (key, pattern, idempotency_key): &Self::Key) -> Option<String> {
+        let pattern = pattern.as_deref().unwrap_or("unmatched route");
+        let operation = match idempotency_key {
+            Some(idempotency_key) => format!("{} {}", pattern, idempotency_key),
+            None => pattern.to_owned(),
+        };
+        Some(match self.inner.key_name(key) {
+            Some(name) => format!("{} ({})", name, operation),
+            None => operation,
+        })
+    }
+}
+
+/// The key of an [`EitherKeyExtractor`], tagged with the extractor it came from.
+///
+/// Keys of different sides never collide, even if their values are equal,
+/// because the side is part of the [`Hash`] and [`Eq`] implementations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EitherKey<L, R> {
+    /// A key of the left extractor.
+    Left(L),
+    /// A key of the right extractor.
+    Right(R),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A [KeyExtractor] that uses the key of `left`, or the key of `right` if `left` fails,
+/// so a single limiter serves both key types, for example API keys and client IPs.
+///
+/// The keys are tagged with an [`EitherKey`], so a key of one side never shares a bucket
+/// with an equal key of the other side. Errors of `left` are discarded, only errors of
+/// `right` are returned.
+///
+/// ```rust
+/// use actix_governor::{EitherKeyExtractor, GovernorConfigBuilder, PeerIpKeyExtractor, UserKeyExtractor, UserSource};
+/// use actix_web::http::header::HeaderName;
+///
+/// let config = GovernorConfigBuilder::default()
+///     .key_extractor(EitherKeyExtractor::new(
+///         UserKeyExtractor::new(UserSource::Header(HeaderName::from_static("x-api-key"))),
+///         PeerIpKeyExtractor,
+///     ))
+///     .finish()
+///     .unwrap();
+/// ```
+pub struct EitherKeyExtractor<L: KeyExtractor, R: KeyExtractor> {
+    left: L,
+    right: R,
+}
+
+impl<L: KeyExtractor, R: KeyExtractor> EitherKeyExtractor<L, R> {
+    /// Create a new extractor that uses `left` and falls back to `right`.
+    #[must_use]
+    pub const fn new(left: L, right: R) -> Self {
+        Self { left, right }
+    }
+}
+
+impl<L: KeyExtractor, R: KeyExtractor> KeyExtractor for EitherKeyExtractor<L, R> {
+    type Key = EitherKey<L::Key, R::Key>;
+    type KeyExtractionError = R::KeyExtractionError;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        self.left.name()
+    }
+
+    fn extract(&self, req: &ServiceRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+        match self.left.extract(req) {
+            Ok(key) => Ok(EitherKey::Left(key)),
+            Err(_) => self.right.extract(req).map(EitherKey::Right),
+        }
+    }
+
+    fn body_limit(&self) -> Option<usize> {
+        max_body_limit(self.left.body_limit(), self.right.body_limit())
+    }
+
+    fn resolve(&self, req: &HttpRequest) -> Option<LocalBoxFuture<'static, ()>> {
+        join_resolve(self.left.resolve(req), self.right.resolve(req))
+    }
+
+    #[allow(deprecated)]
+    fn whitelisted_keys(&self) -> Vec<Self::Key> {
+        let left = self
+            .left
+            .whitelisted_keys()
+            .into_iter()
+            .map(EitherKey::Left);
+        let right = self
+            .right
+            .whitelisted_keys()
+            .into_iter()
+            .map(EitherKey::Right);
+        left.chain(right).collect()
+    }
+
+    fn is_whitelisted(&self, key: &Self::Key) -> bool {
+        match key {
+            EitherKey::Left(key) => self.left.is_whitelisted(key),
+            EitherKey::Right(key) => self.right.is_whitelisted(key),
+        }
+    }
+
+    fn quota(&self, key: &Self::Key) -> Option<Quota> {
+        match key {
+            EitherKey::Left(key) => self.left.quota(key),
+            EitherKey::Right(key) => self.right.quota(key),
+        }
+    }
+
+    fn is_soft_limited(&self, key: &Self::Key) -> bool {
+        match key {
+            EitherKey::Left(key) => self.left.is_soft_limited(key),
+            EitherKey::Right(key) => self.right.is_soft_limited(key),
+        }
+    }
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn key_name(&self, key: &Self::Key) -> Option<String> {
+        match key {
+            EitherKey::Left(key) => self.left.key_name(key),
+            EitherKey::Right(key) => self.right.key_name(key),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A [KeyExtractor] that namespaces the key of another extractor by a tenant ID,
+/// so one process can serve many tenants whose limits never interfere.
+///
+/// The tenant ID is extracted by its own key extractor, for example a
+/// [`HeaderKeyExtractor`]. Tenants can be given their own quota with
+/// [`tenant_quota`](Self::tenant_quota), all other tenants use the quota of `inner`.
+///
+/// The [whitelisted keys](KeyExtractor::is_whitelisted) of `inner` are not carried over,
+/// because the tenants aren't known when the configuration is built.
+///
+/// ```rust
+/// use actix_governor::{GovernorConfigBuilder, HeaderKeyExtractor, PeerIpKeyExtractor, TenantKeyExtractor};
+/// use governor::Quota;
+/// use std::num::NonZeroU32;
+///
+/// let config = GovernorConfigBuilder::default()
+///     .key_extractor(
+///         TenantKeyExtractor::new(HeaderKeyExtractor::new("x-tenant"), PeerIpKeyExtractor)
+///             .tenant_quota("premium".to_owned(), Quota::per_second(NonZeroU32::new(100).unwrap())),
+///     )
+///     .finish()
+///     .unwrap();
+/// ```
+pub struct TenantKeyExtractor<T, K = PeerIpKeyExtractor>
+where
+    T: KeyExtractor,
+    K: KeyExtractor<KeyExtractionError = T::KeyExtractionError>,
+{
+    tenant: T,
+    inner: K,
+    quotas: Vec<(T::Key, Quota)>,
+}
+
+impl<T, K> TenantKeyExtractor<T, K>
+where
+    T: KeyExtractor,
+    K: KeyExtractor<KeyExtractionError = T::KeyExtractionError>,
+{
+    /// Create a new extractor that namespaces each key of `inner`
+    /// by the tenant ID that `tenant` extracts.
+    #[must_use]
+    pub fn new(tenant: T, inner: K) -> Self {
+        Self {
+            tenant,
+            inner,
+            quotas: Vec::new(),
+        }
+    }
+
+    /// Apply `quota` to the clients of `tenant` instead of the quota of the inner extractor.
+    #[must_use]
+    pub fn tenant_quota(mut self, tenant: T::Key, quota: Quota) -> Self {
+        self.quotas.push((tenant, quota));
+        self
+    }
+}
+
+impl<T, K> KeyExtractor for TenantKeyExtractor<T, K>
+where
+    T: KeyExtractor,
+    K: KeyExtractor<KeyExtractionError = T::KeyExtractionError>,
+{
+    type Key = (T::Key, K::Key);
+    type KeyExtractionError = K::KeyExtractionError;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn extract(&self, req: &ServiceRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+        Ok((self.tenant.extract(req)?, self.inner.extract(req)?))
+    }
+
+    fn body_limit(&self) -> Option<usize> {
+        max_body_limit(self.tenant.body_limit(), self.inner.body_limit())
+    }
+
+    fn resolve(&self, req: &HttpRequest) -> Option<LocalBoxFuture<'static, ()>> {
+        join_resolve(self.tenant.resolve(req), self.inner.resolve(req))
+    }
+
+    fn exceed_rate_limit_response(
+        &self,
+        negative: &NotUntil<QuantaInstant>,
+        response: HttpResponseBuilder,
+    ) -> HttpResponse {
+        self.inner.exceed_rate_limit_response(negative, response)
+    }
+
+    fn quota(&self, (tenant, key): &Self::Key) -> Option<Quota> {
+        self.quotas
+            .iter()
+            .find(|(t, _)| t == tenant)
+            .map(|(_, quota)| *quota)
+            .or_else(|| self.inner.quota(key))
+    }
+
+    fn burst_multiplier(&self, req: &ServiceRequest) -> f64 {
+        self.inner.burst_multiplier(req)
+    }
+
+    fn is_soft_limited(&self, (_, key): &Self::Key) -> bool {
+        self.inner.is_soft_limited(key)
+    }
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn key_name(&self, (tenant, key): &Self::Key) -> Option<String> {
+        match (self.inner.key_name(key), self.tenant.key_name(tenant)) {
+            (Some(name), Some(tenant)) => Some(format!("{} (tenant {})", name, tenant)),
+            (None, Some(tenant)) => Some(format!("tenant {}", tenant)),
+            (name, None) => name,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A [KeyExtractor] that combines the keys of two extractors into a tuple, for example
+/// to limit each API key per client IP, so a single API key can't be shared across
+/// an unlimited number of clients.
+///
+/// Both extractors must succeed, the error of `first` is returned before the error of
+/// `second`. The quota of `first` takes precedence over the quota of `second`, and the key
+/// is soft-limited if either part is. The [whitelisted keys](KeyExtractor::is_whitelisted)
+/// are not carried over, because the combinations aren't known when the configuration is built.
+///
+/// The extractor can also be created with [`KeyExtractor::and`]:
+///
+/// ```rust
+/// use actix_governor::{GovernorConfigBuilder, HeaderKeyExtractor, KeyExtractor, PeerIpKeyExtractor};
+///
+/// let config = GovernorConfigBuilder::default()
+///     .key_extractor(PeerIpKeyExtractor.and(HeaderKeyExtractor::new("x-api-key")))
+///     .finish()
+///     .unwrap();
+/// ```
+pub struct CompositeKeyExtractor<A, B>
+where
+    A: KeyExtractor,
+    B: KeyExtractor<KeyExtractionError = A::KeyExtractionError>,
+{
+    first: A,
+    second: B,
+}
+
+impl<A, B> CompositeKeyExtractor<A, B>
+where
+    A: KeyExtractor,
+    B: KeyExtractor<KeyExtractionError = A::KeyExtractionError>,
+{
+    /// Create a new extractor that combines the keys of `first` and `second`.
+    #[must_use]
+    pub const fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+}
+
+/// Returns the larger body limit of two extractors.
+fn max_body_limit(first: Option<usize>, second: Option<usize>) -> Option<usize> {
+    match (first, second) {
+        (Some(first), Some(second)) => Some(first.max(second)),
+        (first, second) => first.or(second),
+    }
+}
+
+/// Resolves the keys of two extractors one after another.
+fn join_resolve(
+    first: Option<LocalBoxFuture<'static, ()>>,
+    second: Option<LocalBoxFuture<'static, ()>>,
+) -> Option<LocalBoxFuture<'static, ()>> {
+    match (first, second) {
+        (Some(first), Some(second)) => Some(Box::pin(async move {
+            first.await;
+            second.await;
+        })),
+        (first, second) => first.or(second),
+    }
+}
+
+impl<A, B> KeyExtractor for CompositeKeyExtractor<A, B>
+where
+    A: KeyExtractor,
+    B: KeyExtractor<KeyExtractionError = A::KeyExtractionError>,
+{
+    type Key = (A::Key, B::Key);
+    type KeyExtractionError = A::KeyExtractionError;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        self.first.name()
+    }
+
+    fn extract(&self, req: &ServiceRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+        Ok((self.first.extract(req)?, self.second.extract(req)?))
+    }
+
+    fn exceed_rate_limit_response(
+        &self,
+        negative: &NotUntil<QuantaInstant>,
+        response: HttpResponseBuilder,
+    ) -> HttpResponse {
+        self.first.exceed_rate_limit_response(negative, response)
+    }
+
+    fn quota(&self, (first, second): &Self::Key) -> Option<Quota> {
+        self.first
+            .quota(first)
+            .or_else(|| self.second.quota(second))
+    }
+
+    fn body_limit(&self) -> Option<usize> {
+        max_body_limit(self.first.body_limit(), self.second.body_limit())
+    }
+
+    fn resolve(&self, req: &HttpRequest) -> Option<LocalBoxFuture<'static, ()>> {
+        join_resolve(self.first.resolve(req), self.second.resolve(req))
+    }
+
+    fn is_soft_limited(&self, (first, second): &Self::Key) -> bool {
+        self.first.is_soft_limited(first) || self.second.is_soft_limited(second)
+    }
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn key_name(&self, (first, second): &Self::Key) -> Option<String> {
+        match (self.first.key_name(first), self.second.key_name(second)) {
+            (Some(first), Some(second)) => Some(format!("{} / {}", first, second)),
+            (first, second) => first.or(second),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A [KeyExtractor] that groups routes into named categories with their own quotas
+/// and combines the category with the key of another extractor, to enforce plans like
+/// "100 search requests and 10 writes per API key and minute".
+///
+/// Categories are defined with [`category`](Self::category) by the route patterns they
+/// cover, as they were registered with the app, like `/users/{id}`. Requests to routes
+/// without a category, including unmatched paths, share one bucket per key with the
+/// quota of `inner`. Each key is tracked once per category it used.
+///
+/// ```rust
+/// use actix_governor::{CategoryKeyExtractor, GovernorConfigBuilder, HeaderKeyExtractor};
+/// use governor::Quota;
+/// use std::num::NonZeroU32;
+///
+/// let config = GovernorConfigBuilder::default()
+///     .key_extractor(
+///         CategoryKeyExtractor::new(HeaderKeyExtractor::new("x-api-key"))
+///             .category("search", &["/search", "/search/{index}"], Quota::per_minute(NonZeroU32::new(100).unwrap()))
+///             .category("write", &["/items", "/items/{id}"], Quota::per_minute(NonZeroU32::new(10).unwrap())),
+///     )
+///     .finish()
+///     .unwrap();
+/// ```
+pub struct CategoryKeyExtractor<K: KeyExtractor = PeerIpKeyExtractor> {
+    inner: K,
+    categories: Vec<(&'static str, Vec<String>, Quota)>,
+}
+
+impl<K: KeyExtractor> CategoryKeyExtractor<K> {
+    /// Create a new extractor that combines the category of the route with the key of `inner`.
+    #[must_use]
+    pub fn new(inner: K) -> Self {
+        Self {
+            inner,
+            categories: Vec::new(),
+        }
+    }
+
+    /// Add the category `name` that covers the route `patterns` and applies `quota`.
+    /// If a pattern is part of several categories, the first one is used.
+    #[must_use]
+    pub fn category(mut self, name: &'static str, patterns: &[&str], quota: Quota) -> Self {
+        let patterns = patterns.iter().map(|&pattern| pattern.to_owned()).collect();
+        self.categories.push((name, patterns, quota));
+        self
+    }
+}
+
+impl<K: KeyExtractor> KeyExtractor for CategoryKeyExtractor<K> {
+    /// The category of the route, or `None` for routes without a category,
+    /// and the key of the inner extractor.
+    type Key = (Option<&'static str>, K::Key);
+    type KeyExtractionError = K::KeyExtractionError;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn extract(&self, req: &ServiceRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+        let category = req.match_pattern().and_then(|route| {
+            self.categories
+                .iter()
+                .find(|(_, patterns, _)| patterns.contains(&route))
+                .map(|(name, _, _)| *name)
+        });
+        Ok((category, self.inner.extract(req)?))
+    }
+
+    fn body_limit(&self) -> Option<usize> {
+        self.inner.body_limit()
+    }
+
+    fn resolve(&self, req: &HttpRequest) -> Option<LocalBoxFuture<'static, ()>> {
+        self.inner.resolve(req)
+    }
+
+    fn exceed_rate_limit_response(
+        &self,
+        negative: &NotUntil<QuantaInstant>,
+        response: HttpResponseBuilder,
+    ) -> HttpResponse {
+        self.inner.exceed_rate_limit_response(negative, response)
+    }
+
+    #[allow(deprecated)]
+    fn whitelisted_keys(&self) -> Vec<Self::Key> {
+        let keys = self.inner.whitelisted_keys();
+        self.categories
+            .iter()
+            .map(|(name, _, _)| Some(*name))
+            .chain([None])
+            .flat_map(|category| keys.iter().map(move |key| (category, key.clone())))
+            .collect()
+    }
+
+    fn is_whitelisted(&self, (_, key): &Self::Key) -> bool {
+        self.inner.is_whitelisted(key)
+    }
+
+    fn quota(&self, (category, key): &Self::Key) -> Option<Quota> {
+        match category {
+            Some(category) => self
+                .categories
+                .iter()
+                .find(|(name, _, _)| name == category)
+                .map(|(_, _, quota)| *quota),
+            None => self.inner.quota(key),
+        }
+    }
+
+    fn burst_multiplier(&self, req: &ServiceRequest) -> f64 {
+        self.inner.burst_multiplier(req)
+    }
+
+    fn is_soft_limited(&self, (_, key): &Self::Key) -> bool {
+        self.inner.is_soft_limited(key)
+    }
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn key_name(&self, (category, key): &Self::Key) -> Option<String> {
+        let category = category.unwrap_or("uncategorized");
+        Some(match self.inner.key_name(key) {
+            Some(name) => format!("{} ({})", name, category),
+            None => category.to_owned(),
+        })
+    }
+}
@@ -91,9 +91,24 @@
 //! 2. allows you to setup multiple instances of this middleware based on different keys (for example, if you want to apply rate limiting with different rates on IP and API keys at the same time)
 //!
 //! This is achieved by defining a [KeyExtractor] and giving it to a [Governor] instance.
-//! Two ready-to-use key extractors are provided:
+//! The following ready-to-use key extractors are provided:
 //! - [PeerIpKeyExtractor]: this is the default
+//! - [PeerIpOrUnknownKeyExtractor]: like the default, but limits requests without a peer IP under a shared key
 //! - [GlobalKeyExtractor]: uses the same key for all incoming requests
+//! - [AnonymousKeyExtractor]: limits anonymous requests by peer IP and whitelists authenticated ones
+//! - [UserKeyExtractor]: uses the normalized user identity, for example an email address, for per-user limits
+//! - [XForwardedForKeyExtractor]: uses the client IP from the `X-Forwarded-For` header set by trusted proxies
+//! - [FixedWindowKeyExtractor]: combines another key with the current time window, for example for per-hour or daily quotas
+//! - [ReadWriteKeyExtractor]: applies separate quotas to reads and writes, see [`presets::read_write`]
+//! - [UserAgentClassKeyExtractor]: separates bots from browsers behind the same key with a user-provided classifier
+//! - [BodyHashKeyExtractor]: uses a hash of the request body to collapse identical payloads
+//! - [FingerprintKeyExtractor]: uses a hash of the peer IP and stable client headers to tell apart anonymous clients
+//! - [IdempotencyKeyExtractor]: limits retries of the same operation on an endpoint by the `Idempotency-Key` header
+//! - [PathKeyExtractor]: uses the route pattern for an independent quota per endpoint shared by all clients
+//! - [CompositeKeyExtractor]: combines the keys of two extractors, see [`KeyExtractor::and`]
+//!
+//! Keys that need an asynchronous lookup, for example of a session in a database or in Redis,
+//! can be extracted with an [AsyncKeyExtractor], see [`GovernorConfigBuilder::async_key_extractor`].
 //!
 //! Check out the [custom_key](https://github.com/AaronErhardt/actix-governor/blob/main/examples/custom_key.rs) example to see how a custom key extractor can be implemented.
 //!
@@ -135,8 +150,30 @@
 //!
 //! By default, `retry-after` and `x-ratelimit-after` are enabled but if you want to enable `x-ratelimit-limit`, `x-ratelimit-whitelisted` and `x-ratelimit-remaining` use [`use_headers`] method
 //!
+//! With these headers enabled, handlers can read the remaining burst capacity with the [`RemainingQuota`] extractor.
+//!
 //! [`use_headers`]: crate::GovernorConfigBuilder::use_headers()
 //!
+//! # Debug endpoint
+//!
+//! With the `debug-endpoint` feature, `GovernorConfig::debug_response` renders the quota,
+//! the number of tracked keys and the active policy flags of a configuration as JSON.
+//! Only expose this on routes that are protected by authentication.
+//!
+//! # Metrics
+//!
+//! With the `metrics` feature, `GovernorConfig::metrics` returns a handle to the counters of
+//! allowed and blocked requests and the number of tracked keys, which can be rendered by
+//! your own metrics exporter, for example in the Prometheus text format.
+//!
+//! # Tracing
+//!
+//! With the `tracing` feature, `GovernorConfigBuilder::key_span` enters a span with the
+//! key extractor and key name while the service handles a request, so downstream
+//! events carry the rate limiting identity. Rejected requests emit an event with the
+//! `extractor`, `key_name` and `wait_seconds` fields instead of the log message of the
+//! `log` feature.
+//!
 //! # Common pitfalls
 //!
 //! Do not construct the same configuration multiple times, unless explicitly wanted!
@@ -152,42 +189,287 @@
     clippy::must_use_candidate,
     clippy::cargo
 )]
+// governor and actix-http depend on different versions of rand, getrandom and hashbrown,
+// which can't be unified by this crate.
+#![allow(clippy::multiple_crate_versions)]
 
 #[cfg(test)]
 mod tests;
 
 use governor::{
-    clock::{DefaultClock, QuantaInstant},
+    clock::{Clock, DefaultClock, QuantaInstant},
     middleware::{NoOpMiddleware, RateLimitingMiddleware, StateInformationMiddleware},
-    state::keyed::DefaultKeyedStateStore,
-    Quota, RateLimiter,
+    Quota,
 };
 
 use actix_http::body::EitherBody;
-use std::{cell::RefCell, marker::PhantomData, num::NonZeroU32, rc::Rc, sync::Arc, time::Duration};
+use actix_http::HttpMessage;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    future::Future,
+    hash::{DefaultHasher, Hash, Hasher},
+    marker::PhantomData,
+    net::IpAddr,
+    num::NonZeroU32,
+    rc::Rc,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
-use actix_web::http::Method;
-use actix_web::{body::MessageBody, Error};
-use futures::future;
+use actix_web::guard::Guard;
+use actix_web::http::header::{HeaderName, HeaderValue, CONTENT_LENGTH, LINK};
+use actix_web::http::{Method, StatusCode};
+use actix_web::{body::MessageBody, Error, HttpResponse, HttpResponseBuilder};
+use futures::future::{self, LocalBoxFuture};
+use governor::{Jitter, NotUntil};
+use tokio::sync::mpsc::Sender;
 
+mod audit;
+#[cfg(any(feature = "log", feature = "tracing"))]
+mod block_log;
+#[cfg(feature = "debug-endpoint")]
+mod debug_endpoint;
 mod extractor;
 mod key_extractor;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod new_keys;
+pub mod presets;
+mod quota_provider;
+mod retain;
 mod service;
+mod simulation;
+mod state;
+
+type SharedRateLimiter<Key, M> = Arc<state::Limiter<Key, M>>;
+
+/// Rate limiters for quotas that differ from the configured one, created on demand.
+type QuotaLimiters<Key, M> = Arc<Mutex<HashMap<(NonZeroU32, Duration), SharedRateLimiter<Key, M>>>>;
+
+/// Counters of individual keys, for example the remaining new key bonus.
+type KeyCounters<Key> = Arc<Mutex<HashMap<Key, u32>>>;
 
-type SharedRateLimiter<Key, M> =
-    Arc<RateLimiter<Key, DefaultKeyedStateStore<Key>, DefaultClock, M>>;
+/// The burst multipliers that are supported, see [`KeyExtractor::burst_multiplier`].
+const BURST_MULTIPLIERS: [f64; 9] = [0.125, 0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 16.0, 32.0];
+
+/// Scales the burst size of `quota` by the supported multiplier that is closest to `multiplier`.
+fn scale_burst(quota: Quota, multiplier: f64) -> Quota {
+    if multiplier == 1.0 || !multiplier.is_finite() || multiplier <= 0.0 {
+        return quota;
+    }
+    let level = BURST_MULTIPLIERS
+        .iter()
+        .copied()
+        .min_by(|a, b| {
+            let distance = |level: f64| (level.log2() - multiplier.log2()).abs();
+            distance(*a).total_cmp(&distance(*b))
+        })
+        .unwrap_or(1.0);
+    let burst_size = (f64::from(quota.burst_size().get()) * level).round() as u32;
+    quota.allow_burst(NonZeroU32::new(burst_size).unwrap_or(NonZeroU32::MIN))
+}
+
+fn new_limiter<Key: Clone + Hash + Eq, M: RateLimitingMiddleware<QuantaInstant>>(
+    quota: Quota,
+) -> SharedRateLimiter<Key, M> {
+    Arc::new(state::Limiter::new(quota))
+}
+
+/// Returns the hash of `key`, for rate limiters that track keys of other key extractors.
+fn hash_key<Key: Hash>(key: &Key) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Returns the number of keys that are tracked by `limiter` and `quota_limiters`.
+fn tracked_keys<Key: Clone + Hash + Eq, M: RateLimitingMiddleware<QuantaInstant>>(
+    limiter: &SharedRateLimiter<Key, M>,
+    quota_limiters: &QuotaLimiters<Key, M>,
+) -> usize {
+    limiter.len()
+        + quota_limiters
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .map(|limiter| limiter.len())
+            .sum::<usize>()
+}
 
 /// Re-export governor
 pub use governor;
 
-pub use extractor::GovernorExtractor;
+pub use audit::{RateLimitEvent, RateLimitOutcome};
+pub use extractor::{GovernorExtractor, RemainingQuota};
 pub use key_extractor::{
-    GlobalKeyExtractor, KeyExtractor, PeerIpKeyExtractor, SimpleKeyExtractionError,
+    AnonymousKeyExtractor, AsyncKeyExtractionError, AsyncKeyExtractor, AsyncKeyExtractorAdapter,
+    AuthIndicator, BodyHashKeyExtractor, CategoryKeyExtractor, CompositeKeyExtractor, EitherKey,
+    EitherKeyExtractor, EndpointKeyExtractor, FingerprintKeyExtractor, FixedWindowKeyExtractor,
+    GlobalKeyExtractor, HeaderKeyExtractor, IdempotencyKeyExtractor, KeyExtractor, MethodClass,
+    PathKeyExtractor, PeerIpKeyExtractor, PeerIpOrUnknownKeyExtractor, ReadWriteKeyExtractor,
+    SchemeKeyExtractor, SimpleKeyExtractionError, TenantKeyExtractor, UserAgentClass,
+    UserAgentClassKeyExtractor, UserKeyExtractor, UserSource, VersionKeyExtractor,
+    XForwardedForKeyExtractor,
 };
+#[cfg(feature = "metrics")]
+pub use metrics::GovernorMetrics;
+pub use service::Decision;
+pub use simulation::{DecisionTester, SimulationReport, SimulationTally, TestDecision};
+
+/// A shared callback that keeps the configuration `Clone`, `Debug` and comparable.
+///
+/// Two callbacks are equal if they share the same function.
+struct Callback<F: ?Sized>(Arc<F>);
+
+impl<F: ?Sized> Clone for Callback<F> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<F: ?Sized> fmt::Debug for Callback<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Callback")
+    }
+}
+
+impl<F: ?Sized> PartialEq for Callback<F> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<F: ?Sized> Eq for Callback<F> {}
+
+type IpCallback = Callback<dyn Fn(&ServiceRequest) -> Option<IpAddr> + Send + Sync>;
+type AttemptCallback = Callback<dyn Fn(&ServiceRequest) -> Option<u64> + Send + Sync>;
+type GuardCallback = Callback<dyn Guard + Send + Sync>;
+
+type PredicateCallback = Callback<dyn Fn(&ServiceRequest) -> bool + Send + Sync>;
+type ResponseCallback = Callback<dyn Fn(HttpResponse) -> HttpResponse + Send + Sync>;
+type LimitedCallback = Callback<dyn Fn(&ServiceRequest, &NotUntil<QuantaInstant>) + Send + Sync>;
+type LoadCallback = Callback<dyn Fn() -> f64 + Send + Sync>;
+type QuotaCallback<Key> = Callback<dyn Fn(&Key) -> Quota + Send + Sync>;
+type QuotaProviderCallback<Key> =
+    Callback<dyn Fn(&Key) -> LocalBoxFuture<'static, Quota> + Send + Sync>;
+
+/// Rate limiter that is charged for failed key extractions.
+#[derive(Debug)]
+struct Penalty {
+    key: IpCallback,
+    /// Tracks the hash of the IP of the failed extractions.
+    limiter: SharedRateLimiter<u64, NoOpMiddleware>,
+}
+
+impl Clone for Penalty {
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key.clone(),
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+/// Rate limiter that is only charged for failed attempts,
+/// see [`GovernorConfigBuilder::brute_force_protection`].
+#[derive(Debug, Clone)]
+struct BruteForce {
+    /// Returns the hash of the key of an attempt.
+    key: AttemptCallback,
+    limiter: SharedRateLimiter<u64, NoOpMiddleware>,
+    /// The statuses of failed attempts, all other responses are refunded.
+    statuses: Vec<StatusCode>,
+}
+
+/// The factor the adaptive fraction is multiplied with while the server is overloaded.
+const ADAPTIVE_DECREASE: f64 = 0.5;
+/// The amount the adaptive fraction is increased by while the server isn't overloaded.
+const ADAPTIVE_INCREASE: f64 = 0.05;
+/// The adaptive fraction is updated at most once per interval.
+const ADAPTIVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Shared state of the adaptive mode, see [`GovernorConfigBuilder::adaptive`].
+#[derive(Debug, Clone)]
+struct Adaptive {
+    load: LoadCallback,
+    min_fraction: f64,
+    /// The available fraction of the quota and when it was last updated.
+    fraction: Arc<Mutex<(f64, Option<Instant>)>>,
+}
+
+impl Adaptive {
+    /// Updates the fraction of the quota that is available with the current load,
+    /// if the last update is at least [`ADAPTIVE_INTERVAL`] ago,
+    /// and returns the number of cells a request costs.
+    fn cost(&self) -> u32 {
+        let mut state = self.fraction.lock().unwrap_or_else(PoisonError::into_inner);
+        let (fraction, updated) = &mut *state;
+        let now = Instant::now();
+        if updated.is_none_or(|updated| now.duration_since(updated) >= ADAPTIVE_INTERVAL) {
+            *updated = Some(now);
+            *fraction = if (self.load.0)() > 1.0 {
+                (*fraction * ADAPTIVE_DECREASE).max(self.min_fraction)
+            } else {
+                (*fraction + ADAPTIVE_INCREASE).min(1.0)
+            };
+        }
+        (1.0 / *fraction).round() as u32
+    }
+}
+
+/// Selects the keys of the [`GovernorConfigBuilder::sample_rate`].
+#[derive(Debug, Clone)]
+struct Sampler {
+    rate: f64,
+}
+
+impl Sampler {
+    /// Returns whether the requests of `key` are sampled. The decision only depends
+    /// on the hash of the key, so a client is either always or never sampled.
+    fn sample<Key: Hash>(&self, key: &Key) -> bool {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        self.rate >= 1.0 || (hasher.finish() as f64) < self.rate * u64::MAX as f64
+    }
+}
+
+/// Shared state of the [`GovernorConfigBuilder::key_memory_warn_threshold`].
+#[cfg(feature = "log")]
+#[derive(Debug, Clone)]
+struct KeyMemoryWarning {
+    threshold: usize,
+    warned: Arc<AtomicBool>,
+}
+
+/// When the quota of a request is consumed, see [`GovernorConfigBuilder::charge_timing`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ChargeTiming {
+    /// Consume the quota before the request is passed to the service.
+    #[default]
+    Before,
+    /// Consume the quota after the service responded.
+    After,
+}
+
+/// Marks a request that was sent over a resumed TLS session,
+/// see [`GovernorConfigBuilder::resumed_session_quota`].
+///
+/// actix-web doesn't expose the handshake of a connection, so the TLS layer has to
+/// insert this marker, either into the connection data with
+/// [`HttpServer::on_connect`](https://docs.rs/actix-web/latest/actix_web/struct.HttpServer.html#method.on_connect)
+/// or into the request extensions with a middleware that runs before the governor middleware.
+/// For example, rustls reports resumed sessions with `ServerConnection::handshake_kind` and
+/// OpenSSL with `SslRef::session_reused`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TlsSessionResumed;
 
 const DEFAULT_PERIOD: Duration = Duration::from_millis(500);
 const DEFAULT_BURST_SIZE: u32 = 8;
+const DEFAULT_HEADER_PREFIX: &str = "x-ratelimit-";
 
 /// Helper struct for building a configuration for the governor middleware.
 ///
@@ -223,10 +505,68 @@ const DEFAULT_BURST_SIZE: u32 = 8;
 pub struct GovernorConfigBuilder<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> {
     period: Duration,
     burst_size: u32,
+    quota: Option<Quota>,
     methods: Option<Vec<Method>>,
     key_extractor: K,
     middleware: PhantomData<M>,
     permissive: bool,
+    trusted_forwarders: Option<Vec<IpAddr>>,
+    json_response: bool,
+    penalty: Option<(IpCallback, Quota)>,
+    guard: Option<GuardCallback>,
+    exempt: Option<PredicateCallback>,
+    allowlist: Vec<(IpAddr, u8)>,
+    rejection_mapper: Option<ResponseCallback>,
+    on_limited: Option<LimitedCallback>,
+    unlimited_whitelist_headers: bool,
+    quota_whitelist_headers: bool,
+    rejection_headers: Vec<(HeaderName, HeaderValue)>,
+    skip_header: Option<(HeaderName, HeaderValue)>,
+    retry_after_granularity: Option<Duration>,
+    reset_alignment: Option<Duration>,
+    refund_statuses: Vec<StatusCode>,
+    brute_force: Option<(AttemptCallback, Quota, Vec<StatusCode>)>,
+    charge_timing: ChargeTiming,
+    refund_window: Option<Duration>,
+    new_key_burst_bonus: u32,
+    close_after_blocks: Option<u32>,
+    /// The load signal and the bits of the minimum fraction, so the builder stays `Eq`.
+    adaptive: Option<(LoadCallback, u64)>,
+    sample_rate: Option<u64>,
+    audit_sink: Option<audit::AuditSink<K::Key>>,
+    quota_extractor: Option<QuotaCallback<K::Key>>,
+    quota_provider: Option<(QuotaProviderCallback<K::Key>, Duration)>,
+    /// Set if options that take the keys of a previous key extractor were dropped
+    /// by [`key_extractor`](Self::key_extractor), so [`finish`](Self::finish) fails.
+    key_options_dropped: bool,
+    retain_interval: Option<Duration>,
+    content_length_threshold: Option<(u64, bool)>,
+    treat_head_as_get: bool,
+    max_new_keys_per_ip: Option<(usize, Duration)>,
+    combined_header: Option<bool>,
+    ietf_headers: bool,
+    full_reset_header: bool,
+    info_header: bool,
+    layer_headers: bool,
+    violation_grace: u32,
+    help_link: Option<HeaderValue>,
+    rejection_status: StatusCode,
+    long_wait: Option<(Duration, StatusCode)>,
+    rejection_delay: Option<Jitter>,
+    policy_names: Vec<(Quota, HeaderValue)>,
+    global_cap: Option<Quota>,
+    uncacheable: Option<(PredicateCallback, Quota)>,
+    header_prefix: Option<String>,
+    resumed_session_quota: Option<Quota>,
+    probe_header: Option<(HeaderName, HeaderValue)>,
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    log_dedup_interval: Option<Duration>,
+    #[cfg(feature = "log")]
+    key_memory_warn_threshold: Option<usize>,
+    #[cfg(feature = "log")]
+    log_allowed: bool,
+    #[cfg(feature = "tracing")]
+    key_span: bool,
 }
 
 impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> Clone
@@ -236,10 +576,65 @@ impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> Clone
         Self {
             period: self.period,
             burst_size: self.burst_size,
+            quota: self.quota,
             methods: self.methods.clone(),
             key_extractor: self.key_extractor.clone(),
             middleware: self.middleware,
             permissive: self.permissive,
+            trusted_forwarders: self.trusted_forwarders.clone(),
+            json_response: self.json_response,
+            penalty: self.penalty.clone(),
+            guard: self.guard.clone(),
+            exempt: self.exempt.clone(),
+            allowlist: self.allowlist.clone(),
+            rejection_mapper: self.rejection_mapper.clone(),
+            on_limited: self.on_limited.clone(),
+            unlimited_whitelist_headers: self.unlimited_whitelist_headers,
+            quota_whitelist_headers: self.quota_whitelist_headers,
+            rejection_headers: self.rejection_headers.clone(),
+            skip_header: self.skip_header.clone(),
+            retry_after_granularity: self.retry_after_granularity,
+            reset_alignment: self.reset_alignment,
+            refund_statuses: self.refund_statuses.clone(),
+            brute_force: self.brute_force.clone(),
+            charge_timing: self.charge_timing,
+            refund_window: self.refund_window,
+            new_key_burst_bonus: self.new_key_burst_bonus,
+            close_after_blocks: self.close_after_blocks,
+            adaptive: self.adaptive.clone(),
+            sample_rate: self.sample_rate,
+            audit_sink: self.audit_sink.clone(),
+            quota_extractor: self.quota_extractor.clone(),
+            quota_provider: self.quota_provider.clone(),
+            key_options_dropped: self.key_options_dropped,
+            retain_interval: self.retain_interval,
+            content_length_threshold: self.content_length_threshold,
+            treat_head_as_get: self.treat_head_as_get,
+            max_new_keys_per_ip: self.max_new_keys_per_ip,
+            combined_header: self.combined_header,
+            ietf_headers: self.ietf_headers,
+            full_reset_header: self.full_reset_header,
+            info_header: self.info_header,
+            layer_headers: self.layer_headers,
+            violation_grace: self.violation_grace,
+            help_link: self.help_link.clone(),
+            rejection_status: self.rejection_status,
+            long_wait: self.long_wait,
+            rejection_delay: self.rejection_delay,
+            policy_names: self.policy_names.clone(),
+            global_cap: self.global_cap,
+            uncacheable: self.uncacheable.clone(),
+            header_prefix: self.header_prefix.clone(),
+            resumed_session_quota: self.resumed_session_quota,
+            probe_header: self.probe_header.clone(),
+            #[cfg(any(feature = "log", feature = "tracing"))]
+            log_dedup_interval: self.log_dedup_interval,
+            #[cfg(feature = "log")]
+            key_memory_warn_threshold: self.key_memory_warn_threshold,
+            #[cfg(feature = "log")]
+            log_allowed: self.log_allowed,
+            #[cfg(feature = "tracing")]
+            key_span: self.key_span,
         }
     }
 }
@@ -248,11 +643,72 @@ impl<K: KeyExtractor + PartialEq, M: RateLimitingMiddleware<QuantaInstant>> Part
     for GovernorConfigBuilder<K, M>
 {
     fn eq(&self, other: &Self) -> bool {
+        #[cfg(any(feature = "log", feature = "tracing"))]
+        if self.log_dedup_interval != other.log_dedup_interval {
+            return false;
+        }
+        #[cfg(feature = "log")]
+        if self.key_memory_warn_threshold != other.key_memory_warn_threshold
+            || self.log_allowed != other.log_allowed
+        {
+            return false;
+        }
+        #[cfg(feature = "tracing")]
+        if self.key_span != other.key_span {
+            return false;
+        }
         self.period == other.period
             && self.burst_size == other.burst_size
+            && self.quota == other.quota
             && self.methods == other.methods
             && self.key_extractor == other.key_extractor
             && self.permissive == other.permissive
+            && self.trusted_forwarders == other.trusted_forwarders
+            && self.json_response == other.json_response
+            && self.penalty == other.penalty
+            && self.guard == other.guard
+            && self.exempt == other.exempt
+            && self.allowlist == other.allowlist
+            && self.rejection_mapper == other.rejection_mapper
+            && self.on_limited == other.on_limited
+            && self.unlimited_whitelist_headers == other.unlimited_whitelist_headers
+            && self.quota_whitelist_headers == other.quota_whitelist_headers
+            && self.rejection_headers == other.rejection_headers
+            && self.skip_header == other.skip_header
+            && self.retry_after_granularity == other.retry_after_granularity
+            && self.reset_alignment == other.reset_alignment
+            && self.refund_statuses == other.refund_statuses
+            && self.brute_force == other.brute_force
+            && self.charge_timing == other.charge_timing
+            && self.refund_window == other.refund_window
+            && self.new_key_burst_bonus == other.new_key_burst_bonus
+            && self.close_after_blocks == other.close_after_blocks
+            && self.adaptive == other.adaptive
+            && self.sample_rate == other.sample_rate
+            && self.audit_sink == other.audit_sink
+            && self.quota_extractor == other.quota_extractor
+            && self.quota_provider == other.quota_provider
+            && self.key_options_dropped == other.key_options_dropped
+            && self.retain_interval == other.retain_interval
+            && self.content_length_threshold == other.content_length_threshold
+            && self.treat_head_as_get == other.treat_head_as_get
+            && self.max_new_keys_per_ip == other.max_new_keys_per_ip
+            && self.combined_header == other.combined_header
+            && self.ietf_headers == other.ietf_headers
+            && self.full_reset_header == other.full_reset_header
+            && self.info_header == other.info_header
+            && self.layer_headers == other.layer_headers
+            && self.violation_grace == other.violation_grace
+            && self.help_link == other.help_link
+            && self.rejection_status == other.rejection_status
+            && self.long_wait == other.long_wait
+            && self.rejection_delay == other.rejection_delay
+            && self.policy_names == other.policy_names
+            && self.global_cap == other.global_cap
+            && self.uncacheable == other.uncacheable
+            && self.header_prefix == other.header_prefix
+            && self.resumed_session_quota == other.resumed_session_quota
+            && self.probe_header == other.probe_header
     }
 }
 
@@ -271,10 +727,65 @@ impl<M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBuilder<PeerIpKeyEx
         GovernorConfigBuilder {
             period: DEFAULT_PERIOD,
             burst_size: DEFAULT_BURST_SIZE,
+            quota: None,
             methods: None,
             key_extractor: PeerIpKeyExtractor,
             middleware: PhantomData,
             permissive: false,
+            trusted_forwarders: None,
+            json_response: false,
+            penalty: None,
+            guard: None,
+            exempt: None,
+            allowlist: Vec::new(),
+            rejection_mapper: None,
+            on_limited: None,
+            unlimited_whitelist_headers: false,
+            quota_whitelist_headers: false,
+            rejection_headers: Vec::new(),
+            skip_header: None,
+            retry_after_granularity: None,
+            reset_alignment: None,
+            refund_statuses: Vec::new(),
+            brute_force: None,
+            charge_timing: ChargeTiming::Before,
+            refund_window: None,
+            new_key_burst_bonus: 0,
+            close_after_blocks: None,
+            adaptive: None,
+            sample_rate: None,
+            audit_sink: None,
+            quota_extractor: None,
+            quota_provider: None,
+            key_options_dropped: false,
+            retain_interval: None,
+            content_length_threshold: None,
+            treat_head_as_get: false,
+            max_new_keys_per_ip: None,
+            combined_header: None,
+            ietf_headers: false,
+            full_reset_header: false,
+            info_header: false,
+            layer_headers: false,
+            violation_grace: 0,
+            help_link: None,
+            rejection_status: StatusCode::TOO_MANY_REQUESTS,
+            long_wait: None,
+            rejection_delay: None,
+            policy_names: Vec::new(),
+            global_cap: None,
+            uncacheable: None,
+            header_prefix: None,
+            resumed_session_quota: None,
+            probe_header: None,
+            #[cfg(any(feature = "log", feature = "tracing"))]
+            log_dedup_interval: None,
+            #[cfg(feature = "log")]
+            key_memory_warn_threshold: None,
+            #[cfg(feature = "log")]
+            log_allowed: false,
+            #[cfg(feature = "tracing")]
+            key_span: false,
         }
     }
     /// Set the interval after which one element of the quota is replenished.
@@ -282,6 +793,7 @@ impl<M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBuilder<PeerIpKeyEx
     /// **The interval must not be zero.**
     pub const fn const_period(mut self, duration: Duration) -> Self {
         self.period = duration;
+        self.quota = None;
         self
     }
     /// Set the number of quota elements to replenish per second.
@@ -308,6 +820,7 @@ impl<M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBuilder<PeerIpKeyEx
     )]
     pub const fn const_per_second(mut self, seconds: u64) -> Self {
         self.period = Duration::from_secs(seconds);
+        self.quota = None;
         self
     }
     /// Set the interval after which one element of the quota is replenished in seconds.
@@ -315,6 +828,7 @@ impl<M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBuilder<PeerIpKeyEx
     /// **The interval must not be zero.**
     pub const fn const_second_per_request(mut self, seconds: u64) -> Self {
         self.period = Duration::from_secs(seconds);
+        self.quota = None;
         self
     }
     /// Renamed to `const_milliseconds_per_request`.
@@ -326,6 +840,7 @@ impl<M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBuilder<PeerIpKeyEx
     )]
     pub const fn const_per_millisecond(mut self, milliseconds: u64) -> Self {
         self.period = Duration::from_millis(milliseconds);
+        self.quota = None;
         self
     }
     /// Set the interval after which one element of the quota is replenished in milliseconds.
@@ -333,6 +848,7 @@ impl<M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBuilder<PeerIpKeyEx
     /// **The interval must not be zero.**
     pub const fn const_milliseconds_per_request(mut self, milliseconds: u64) -> Self {
         self.period = Duration::from_millis(milliseconds);
+        self.quota = None;
         self
     }
     /// Renamed to `const_nanoseconds_per_request`.
@@ -344,6 +860,7 @@ impl<M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBuilder<PeerIpKeyEx
     )]
     pub const fn const_per_nanosecond(mut self, nanoseconds: u64) -> Self {
         self.period = Duration::from_nanos(nanoseconds);
+        self.quota = None;
         self
     }
     /// Set the interval after which one element of the quota is replenished in nanoseconds.
@@ -351,6 +868,7 @@ impl<M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBuilder<PeerIpKeyEx
     /// **The interval must not be zero.**
     pub const fn const_nanoseconds_per_request(mut self, nanoseconds: u64) -> Self {
         self.period = Duration::from_nanos(nanoseconds);
+        self.quota = None;
         self
     }
     /// Set quota size that defines how many requests can occur
@@ -360,6 +878,7 @@ impl<M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBuilder<PeerIpKeyEx
     /// **The burst_size must not be zero.**
     pub const fn const_burst_size(mut self, burst_size: u32) -> Self {
         self.burst_size = burst_size;
+        self.quota = None;
         self
     }
     /// Set the mode of the governor middleware.
@@ -372,12 +891,118 @@ impl<M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBuilder<PeerIpKeyEx
     }
 }
 
+impl<M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBuilder<PeerIpKeyExtractor, M> {
+    /// Reject requests that carry a `Forwarded` or `X-Forwarded-For` header
+    /// unless the peer is one of the `trusted` proxies.
+    ///
+    /// [`PeerIpKeyExtractor`] ignores these headers, so they can't be used to
+    /// spoof the rate limiting key. Still, a client that sends them directly
+    /// is most likely trying to do exactly that, so this option allows to
+    /// reject such requests with `403 Forbidden` as a defense-in-depth measure.
+    pub fn reject_forwarded_from_untrusted(&mut self, trusted: Vec<IpAddr>) -> &mut Self {
+        self.trusted_forwarders = Some(trusted);
+        self
+    }
+}
+
+impl<K: KeyExtractor> GovernorConfigBuilder<K, StateInformationMiddleware> {
+    /// Emit the rate limit state as a single `x-ratelimit` header in the format
+    /// `limit=100, remaining=42, reset=30`, with the burst size, the remaining burst
+    /// capacity and the seconds until the quota is fully replenished.
+    ///
+    /// All values are unsigned integers, so the header never needs escaping. If `replace`
+    /// is set, the combined header replaces the `x-ratelimit-limit`, `x-ratelimit-remaining`
+    /// and `x-ratelimit-reset` headers, otherwise it is emitted alongside them.
+    pub fn combined_header(&mut self, replace: bool) -> &mut Self {
+        self.combined_header = Some(replace);
+        self
+    }
+
+    /// Emit the rate limit state with the `RateLimit` and `RateLimit-Policy` headers of the
+    /// IETF draft [RateLimit header fields for HTTP](https://datatracker.ietf.org/doc/draft-ietf-httpapi-ratelimit-headers/)
+    /// instead of the `x-ratelimit-*` headers:
+    ///
+    /// + `RateLimit: limit=100, remaining=42, reset=30` with the burst size, the remaining
+    ///   burst capacity and the seconds until the quota is fully replenished.
+    /// + `RateLimit-Policy: 100;w=60` with the burst size and the seconds it takes
+    ///   to replenish the whole burst.
+    ///
+    /// This takes precedence over [`combined_header`](Self::combined_header). The `retry-after`
+    /// and `x-ratelimit-after` headers of rejected requests are still emitted.
+    pub fn use_ietf_headers(&mut self) -> &mut Self {
+        self.ietf_headers = true;
+        self
+    }
+
+    /// Add the `x-ratelimit-full-reset` header with the seconds until the whole burst is
+    /// restored, so clients can tell how depleted the quota is, unlike the `retry-after`
+    /// header of rejected requests, which only reports when the next request is allowed.
+    ///
+    /// The header is emitted alongside the `x-ratelimit-*` headers, but not
+    /// with [`use_ietf_headers`](Self::use_ietf_headers), whose `RateLimit`
+    /// header already reports the same value as `reset`. It carries the same value
+    /// as the `x-ratelimit-reset` header, but isn't replaced by the
+    /// [`combined_header`](Self::combined_header).
+    pub fn full_reset_header(&mut self) -> &mut Self {
+        self.full_reset_header = true;
+        self
+    }
+
+    /// Emit the rate limit state as a JSON object in the `x-ratelimit-info` header, for
+    /// example `{"limit":100,"remaining":42,"reset":30}`, with the burst size, the remaining
+    /// burst capacity and the seconds until the quota is fully replenished, so single-page
+    /// apps can read the whole state with one `JSON.parse`.
+    ///
+    /// All values are unsigned integers, so the value never contains characters that need
+    /// escaping. The header is emitted alongside the `x-ratelimit-*` headers, but not
+    /// with [`use_ietf_headers`](Self::use_ietf_headers).
+    pub fn info_header(&mut self) -> &mut Self {
+        self.info_header = true;
+        self
+    }
+
+    /// Add separate headers for each layer if a [`global_cap`](Self::global_cap) is set,
+    /// so clients can see how much of both their own and the global quota is left:
+    ///
+    /// + `x-ratelimit-limit-key` and `x-ratelimit-remaining-key` for the quota of the key,
+    ///   for example of the peer IP with the default key extractor
+    /// + `x-ratelimit-limit-global` and `x-ratelimit-remaining-global` for the global quota
+    ///
+    /// The headers are added to allowed responses alongside the `x-ratelimit-*` headers,
+    /// which keep reporting the quota of the key, but not
+    /// with [`use_ietf_headers`](Self::use_ietf_headers).
+    pub fn layer_headers(&mut self) -> &mut Self {
+        self.layer_headers = true;
+        self
+    }
+
+    /// Answer requests that carry the header `name` with `value`, for example
+    /// `x-ratelimit-probe: true`, with the current quota of their key instead of passing them
+    /// to the service, so clients can check their quota before an expensive operation.
+    /// The value is compared case-insensitively.
+    ///
+    /// Probes are answered with `200 OK` and the `x-ratelimit-*` headers, or the
+    /// `x-ratelimit-whitelisted` header for whitelisted requests. The `x-ratelimit-remaining`
+    /// header reports how many requests are still allowed. If the quota is exhausted, the
+    /// `retry-after` and `x-ratelimit-after` headers are added. Probes of requests whose key
+    /// can't be extracted are handled like regular requests.
+    ///
+    /// **Probes don't consume the quota, so they are not rate limited themselves.**
+    /// If clients could abuse them, limit them with a second middleware that only applies to
+    /// probes, for example with a [`guard`](Self::guard) on the probe header.
+    pub fn probe_header(&mut self, name: HeaderName, value: HeaderValue) -> &mut Self {
+        self.probe_header = Some((name, value));
+        self
+    }
+}
+
 impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBuilder<K, M> {
     /// Set the interval after which one element of the quota is replenished.
     ///
     /// **The interval must not be zero.**
     pub fn period(&mut self, duration: Duration) -> &mut Self {
         self.period = duration;
+        self.quota = None;
         self
     }
     /// Set the number of quota elements to replenish per second.
@@ -404,6 +1029,7 @@ impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBu
     )]
     pub fn per_second(&mut self, seconds: u64) -> &mut Self {
         self.period = Duration::from_secs(seconds);
+        self.quota = None;
         self
     }
     /// Set the interval after which one element of the quota is replenished in seconds.
@@ -411,6 +1037,7 @@ impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBu
     /// **The interval must not be zero.**
     pub fn seconds_per_request(&mut self, seconds: u64) -> &mut Self {
         self.period = Duration::from_secs(seconds);
+        self.quota = None;
         self
     }
     /// Renamed to `milliseconds_per_request`.
@@ -422,6 +1049,7 @@ impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBu
     )]
     pub fn per_millisecond(&mut self, milliseconds: u64) -> &mut Self {
         self.period = Duration::from_millis(milliseconds);
+        self.quota = None;
         self
     }
     /// Set the interval after which one element of the quota is replenished in milliseconds.
@@ -429,6 +1057,7 @@ impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBu
     /// **The interval must not be zero.**
     pub fn milliseconds_per_request(&mut self, milliseconds: u64) -> &mut Self {
         self.period = Duration::from_millis(milliseconds);
+        self.quota = None;
         self
     }
     /// Renamed to `nanoseconds_per_request`.
@@ -440,6 +1069,7 @@ impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBu
     )]
     pub fn per_nanosecond(&mut self, nanoseconds: u64) -> &mut Self {
         self.period = Duration::from_nanos(nanoseconds);
+        self.quota = None;
         self
     }
     /// Set the interval after which one element of the quota is replenished in nanoseconds.
@@ -447,15 +1077,43 @@ impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBu
     /// **The interval must not be zero.**
     pub fn nanoseconds_per_request(&mut self, nanoseconds: u64) -> &mut Self {
         self.period = Duration::from_nanos(nanoseconds);
+        self.quota = None;
         self
     }
     /// Set quota size that defines how many requests can occur
     /// before the governor middleware starts blocking requests from an IP address and
     /// clients have to wait until the elements of the quota are replenished.
     ///
+    /// The burst size is independent of the replenish rate, for example
+    /// `requests_per_minute(100).burst_size(20)` sustains 100 requests per minute,
+    /// but allows bursts of at most 20 requests.
+    ///
     /// **The burst_size must not be zero.**
     pub fn burst_size(&mut self, burst_size: u32) -> &mut Self {
         self.burst_size = burst_size;
+        self.quota = None;
+        self
+    }
+    /// Use a fully-formed governor [`Quota`] verbatim, for example `Quota::per_hour`,
+    /// which avoids lossy conversions of exotic rates.
+    ///
+    /// The quota replaces the [`period`](Self::period) and the [`burst_size`](Self::burst_size).
+    /// The last call wins: setting the period, a rate or the burst size afterwards
+    /// drops the quota again and builds it from the period and burst size.
+    ///
+    /// ```rust
+    /// use actix_governor::GovernorConfigBuilder;
+    /// use governor::Quota;
+    /// use std::num::NonZeroU32;
+    ///
+    /// // Sustain 100 requests per minute with bursts of up to 20 requests
+    /// let config = GovernorConfigBuilder::default()
+    ///     .quota(Quota::per_minute(NonZeroU32::new(100).unwrap()).allow_burst(NonZeroU32::new(20).unwrap()))
+    ///     .finish()
+    ///     .unwrap();
+    /// ```
+    pub fn quota(&mut self, quota: Quota) -> &mut Self {
+        self.quota = Some(quota);
         self
     }
     /// Set the mode of the governor middleware.
@@ -474,8 +1132,125 @@ impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBu
         self
     }
 
+    /// Only apply this configuration to requests that match the `guard`,
+    /// in addition to the [`methods`](Self::methods) filter.
+    ///
+    /// This allows to reuse the [guards](actix_web::guard) of your routes,
+    /// for example to only limit requests with a certain header.
+    /// Requests that don't match are passed through like whitelisted requests.
+    /// Multiple guards can be combined with [`All`](actix_web::guard::All)
+    /// and [`Any`](actix_web::guard::Any).
+    pub fn guard<G: Guard + Send + Sync + 'static>(&mut self, guard: G) -> &mut Self {
+        self.guard = Some(Callback(Arc::new(guard)));
+        self
+    }
+
+    /// Exempt requests for which `f` returns `true` from rate limiting, for example
+    /// health checks and metrics endpoints:
+    ///
+    /// ```rust
+    /// use actix_governor::GovernorConfigBuilder;
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .exempt(|req| matches!(req.path(), "/health" | "/metrics"))
+    ///     .finish()
+    ///     .unwrap();
+    /// ```
+    ///
+    /// `f` is called before the key is extracted, and exempted requests are passed
+    /// through like whitelisted requests.
+    pub fn exempt<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&ServiceRequest) -> bool + Send + Sync + 'static,
+    {
+        self.exempt = Some(Callback(Arc::new(f)));
+        self
+    }
+
+    /// Never limit requests from peers in the CIDR `ranges`, for example monitoring
+    /// servers or an office network. The ranges are given as network address and prefix
+    /// length, for example `(10.0.0.0, 8)` for `10.0.0.0/8`.
+    ///
+    /// The peer IP is checked before the key is extracted, so this works with every key
+    /// extractor, and allowlisted requests are passed through like whitelisted requests.
+    /// Behind a reverse proxy, the peer IP is the address of the proxy.
+    ///
+    /// An IPv4 range never contains IPv6 addresses and vice versa, and prefix lengths
+    /// longer than the address are treated as the full address.
+    pub fn allowlist(&mut self, ranges: Vec<(IpAddr, u8)>) -> &mut Self {
+        self.allowlist = ranges;
+        self
+    }
+
+    /// Only limit requests with a `Content-Length` above `threshold` bytes, for example
+    /// to limit large uploads while small requests stay unlimited. Smaller requests are
+    /// treated like whitelisted ones.
+    ///
+    /// Requests without a valid `Content-Length`, like chunked uploads, are limited
+    /// if `limit_unknown` is set and pass through otherwise.
+    pub fn limit_only_above_content_length(
+        &mut self,
+        threshold: u64,
+        limit_unknown: bool,
+    ) -> &mut Self {
+        self.content_length_threshold = Some((threshold, limit_unknown));
+        self
+    }
+
+    /// Treat `HEAD` requests like `GET` requests in the middleware, as they are semantically
+    /// the same. This matters if the [`methods`](Self::methods) filter, the guard or the key
+    /// extractor distinguish the methods, for example with per-method quotas.
+    ///
+    /// The method is only normalized while the middleware decides about the request,
+    /// the service still receives a `HEAD` request. [`GovernorConfig::decide`] is not affected.
+    pub fn treat_head_as_get(&mut self) -> &mut Self {
+        self.treat_head_as_get = true;
+        self
+    }
+
+    /// Reject requests with keys that their peer IP didn't use before, once the IP
+    /// introduced `max_keys` distinct keys within `window`.
+    ///
+    /// This hardens key extractors like API keys against clients that rotate their keys
+    /// to enumerate them or to flood the state store with new keys. Rejected requests get
+    /// `429 Too Many Requests`, while the keys the IP already introduced keep working.
+    /// Requests without a peer IP and whitelisted keys are not tracked.
+    ///
+    /// This keeps the set of introduced keys for each IP of the current window.
+    pub fn max_new_keys_per_ip(&mut self, max_keys: usize, window: Duration) -> &mut Self {
+        self.max_new_keys_per_ip = Some((max_keys, window));
+        self
+    }
+
+    /// Let the first `violations` requests of a key that exceed the quota through with an
+    /// `x-ratelimit-warning` header, and only reject further ones with `429 Too Many Requests`.
+    ///
+    /// The violations of a key are counted until one of its requests is within the quota
+    /// again, so **this weakens the limit**: after every pause, a client can exceed its quota
+    /// by `violations` requests. This keeps a counter for each key that exceeded its quota.
+    pub fn violation_grace(&mut self, violations: u32) -> &mut Self {
+        self.violation_grace = violations;
+        self
+    }
+
+    /// Skip rate limiting for requests that carry the header `name` with `value`,
+    /// for example `CDN-Cache-Status: HIT` for requests that an upstream CDN marked as cache hits.
+    /// The value is compared case-insensitively.
+    ///
+    /// Skipped requests are passed through like whitelisted requests.
+    /// **Only use this if the header is always set or overwritten by your CDN**,
+    /// otherwise clients can bypass the rate limit by sending the header themselves.
+    pub fn skip_if_header(&mut self, name: HeaderName, value: HeaderValue) -> &mut Self {
+        self.skip_header = Some((name, value));
+        self
+    }
+
     /// Set the key extractor this configuration should use.
     /// By default this is using the [PeerIpKeyExtractor].
+    ///
+    /// An [`audit_sink`](Self::audit_sink), a [`quota_extractor`](Self::quota_extractor)
+    /// and a [`quota_provider`](Self::quota_provider) take the keys of the previous key
+    /// extractor, so if one of them was already set, [`finish`](Self::finish) returns `None`.
     pub fn key_extractor<K2: KeyExtractor>(
         &mut self,
         key_extractor: K2,
@@ -483,21 +1258,115 @@ impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBu
         GovernorConfigBuilder {
             period: self.period,
             burst_size: self.burst_size,
+            quota: self.quota,
             methods: self.methods.to_owned(),
             key_extractor,
             middleware: PhantomData,
             permissive: self.permissive,
+            trusted_forwarders: self.trusted_forwarders.to_owned(),
+            json_response: self.json_response,
+            penalty: self.penalty.to_owned(),
+            guard: self.guard.to_owned(),
+            exempt: self.exempt.clone(),
+            allowlist: self.allowlist.clone(),
+            rejection_mapper: self.rejection_mapper.to_owned(),
+            on_limited: self.on_limited.clone(),
+            unlimited_whitelist_headers: self.unlimited_whitelist_headers,
+            quota_whitelist_headers: self.quota_whitelist_headers,
+            rejection_headers: self.rejection_headers.clone(),
+            skip_header: self.skip_header.clone(),
+            retry_after_granularity: self.retry_after_granularity,
+            reset_alignment: self.reset_alignment,
+            refund_statuses: self.refund_statuses.clone(),
+            brute_force: self.brute_force.clone(),
+            charge_timing: self.charge_timing,
+            refund_window: self.refund_window,
+            new_key_burst_bonus: self.new_key_burst_bonus,
+            close_after_blocks: self.close_after_blocks,
+            adaptive: self.adaptive.clone(),
+            sample_rate: self.sample_rate,
+            audit_sink: None,
+            quota_extractor: None,
+            quota_provider: None,
+            key_options_dropped: self.key_options_dropped
+                || self.audit_sink.is_some()
+                || self.quota_extractor.is_some()
+                || self.quota_provider.is_some(),
+            retain_interval: self.retain_interval,
+            content_length_threshold: self.content_length_threshold,
+            treat_head_as_get: self.treat_head_as_get,
+            max_new_keys_per_ip: self.max_new_keys_per_ip,
+            combined_header: self.combined_header,
+            ietf_headers: self.ietf_headers,
+            full_reset_header: self.full_reset_header,
+            info_header: self.info_header,
+            layer_headers: self.layer_headers,
+            violation_grace: self.violation_grace,
+            help_link: self.help_link.clone(),
+            rejection_status: self.rejection_status,
+            long_wait: self.long_wait,
+            rejection_delay: self.rejection_delay,
+            policy_names: self.policy_names.clone(),
+            global_cap: self.global_cap,
+            uncacheable: self.uncacheable.clone(),
+            header_prefix: self.header_prefix.clone(),
+            resumed_session_quota: self.resumed_session_quota,
+            probe_header: self.probe_header.clone(),
+            #[cfg(any(feature = "log", feature = "tracing"))]
+            log_dedup_interval: self.log_dedup_interval,
+            #[cfg(feature = "log")]
+            key_memory_warn_threshold: self.key_memory_warn_threshold,
+            #[cfg(feature = "log")]
+            log_allowed: self.log_allowed,
+            #[cfg(feature = "tracing")]
+            key_span: self.key_span,
         }
     }
 
+    /// Only limit anonymous requests by their peer IP, requests carrying
+    /// the `auth_indicator` are whitelisted.
+    ///
+    /// This is a shortcut for using the [AnonymousKeyExtractor] as key extractor.
+    pub fn limit_anonymous_by_ip(
+        &mut self,
+        auth_indicator: AuthIndicator,
+    ) -> GovernorConfigBuilder<AnonymousKeyExtractor, M> {
+        self.key_extractor(AnonymousKeyExtractor::new(auth_indicator))
+    }
+
+    /// Limit each key separately for every endpoint, so a client's budget on one route
+    /// is independent of its budget on another. The effective key becomes the extracted key
+    /// combined with the route pattern, like `/users/{id}`. Requests that don't match any
+    /// route share one budget per key.
+    ///
+    /// This is a shortcut for wrapping the current key extractor in an [EndpointKeyExtractor].
+    /// Note that this multiplies the number of keys that are tracked by the number of routes.
+    pub fn per_endpoint(&mut self) -> GovernorConfigBuilder<EndpointKeyExtractor<K>, M> {
+        let key_extractor = EndpointKeyExtractor::new(self.key_extractor.clone());
+        self.key_extractor(key_extractor)
+    }
+
+    /// Set an [AsyncKeyExtractor], for keys that need an asynchronous lookup,
+    /// for example of a session in a database or in Redis.
+    /// The middleware awaits the key before the request is rate limited.
+    ///
+    /// This is a shortcut for using an [AsyncKeyExtractorAdapter] as key extractor.
+    pub fn async_key_extractor<A: AsyncKeyExtractor + 'static>(
+        &mut self,
+        key_extractor: A,
+    ) -> GovernorConfigBuilder<AsyncKeyExtractorAdapter<A>, M> {
+        self.key_extractor(AsyncKeyExtractorAdapter::new(key_extractor))
+    }
+
     /// Set x-ratelimit headers to response, the headers is
     /// - `retry-after`             - Number of seconds in which the API will become available after its rate limit has been exceeded
     /// - `x-ratelimit-after`       - Number of seconds in which the API will become available after its rate limit has been exceeded
     /// - `x-ratelimit-limit`       - Request limit
     /// - `x-ratelimit-remaining`   - The number of requests left for the time window
+    /// - `x-ratelimit-reset`       - Number of seconds until the whole quota is replenished, `0` if it is full
     /// - `x-ratelimit-whitelisted` - If the request method not in methods, this header will be add it, use [`methods`] to add methods
     ///
-    /// By default `retry-after` and `x-ratelimit-after` are enabled, with [`use_headers`] will enable `x-ratelimit-limit`, `x-ratelimit-whitelisted` and `x-ratelimit-remaining`
+    /// By default `retry-after` and `x-ratelimit-after` are enabled, with [`use_headers`] will enable `x-ratelimit-limit`, `x-ratelimit-whitelisted`, `x-ratelimit-remaining` and `x-ratelimit-reset`
     ///
     /// [`methods`]: crate::GovernorConfigBuilder::methods()
     /// [`use_headers`]: Self::use_headers
@@ -505,33 +1374,754 @@ impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBu
         GovernorConfigBuilder {
             period: self.period,
             burst_size: self.burst_size,
+            quota: self.quota,
             methods: self.methods.to_owned(),
             key_extractor: self.key_extractor.clone(),
             middleware: PhantomData,
             permissive: self.permissive,
+            trusted_forwarders: self.trusted_forwarders.to_owned(),
+            json_response: self.json_response,
+            penalty: self.penalty.to_owned(),
+            guard: self.guard.to_owned(),
+            exempt: self.exempt.clone(),
+            allowlist: self.allowlist.clone(),
+            rejection_mapper: self.rejection_mapper.to_owned(),
+            on_limited: self.on_limited.clone(),
+            unlimited_whitelist_headers: self.unlimited_whitelist_headers,
+            quota_whitelist_headers: self.quota_whitelist_headers,
+            rejection_headers: self.rejection_headers.clone(),
+            skip_header: self.skip_header.clone(),
+            retry_after_granularity: self.retry_after_granularity,
+            reset_alignment: self.reset_alignment,
+            refund_statuses: self.refund_statuses.clone(),
+            brute_force: self.brute_force.clone(),
+            charge_timing: self.charge_timing,
+            refund_window: self.refund_window,
+            new_key_burst_bonus: self.new_key_burst_bonus,
+            close_after_blocks: self.close_after_blocks,
+            adaptive: self.adaptive.clone(),
+            sample_rate: self.sample_rate,
+            audit_sink: self.audit_sink.clone(),
+            quota_extractor: self.quota_extractor.clone(),
+            quota_provider: self.quota_provider.clone(),
+            key_options_dropped: self.key_options_dropped,
+            retain_interval: self.retain_interval,
+            content_length_threshold: self.content_length_threshold,
+            treat_head_as_get: self.treat_head_as_get,
+            max_new_keys_per_ip: self.max_new_keys_per_ip,
+            combined_header: self.combined_header,
+            ietf_headers: self.ietf_headers,
+            full_reset_header: self.full_reset_header,
+            info_header: self.info_header,
+            layer_headers: self.layer_headers,
+            violation_grace: self.violation_grace,
+            help_link: self.help_link.clone(),
+            rejection_status: self.rejection_status,
+            long_wait: self.long_wait,
+            rejection_delay: self.rejection_delay,
+            policy_names: self.policy_names.clone(),
+            global_cap: self.global_cap,
+            uncacheable: self.uncacheable.clone(),
+            header_prefix: self.header_prefix.clone(),
+            resumed_session_quota: self.resumed_session_quota,
+            probe_header: self.probe_header.clone(),
+            #[cfg(any(feature = "log", feature = "tracing"))]
+            log_dedup_interval: self.log_dedup_interval,
+            #[cfg(feature = "log")]
+            key_memory_warn_threshold: self.key_memory_warn_threshold,
+            #[cfg(feature = "log")]
+            log_allowed: self.log_allowed,
+            #[cfg(feature = "tracing")]
+            key_span: self.key_span,
         }
     }
 
+    /// Respond to rate limited requests with a JSON body that mirrors the rate limit headers:
+    ///
+    /// ```json
+    /// {"limit":10,"remaining":0,"retry_after":3}
+    /// ```
+    ///
+    /// The response is built by the middleware, so [`KeyExtractor::exceed_rate_limit_response`]
+    /// is not called when this option is enabled.
+    pub fn use_json_response(&mut self) -> &mut Self {
+        self.json_response = true;
+        self
+    }
+
+    /// Charge failed key extractions against a separate rate limiter that uses
+    /// the `fallback` key extractor and `quota`.
+    ///
+    /// By default, the limiter isn't consulted if the key can't be extracted,
+    /// so clients can probe with malformed credentials as often as they want.
+    /// With this option, such requests are rejected with `429 Too Many Requests`
+    /// once the penalty quota of the client is exhausted.
+    pub fn penalize_extraction_errors<F>(&mut self, fallback: F, quota: Quota) -> &mut Self
+    where
+        F: KeyExtractor<Key = IpAddr> + Send + Sync + 'static,
+    {
+        let key = move |req: &ServiceRequest| fallback.extract(req).ok();
+        self.penalty = Some((Callback(Arc::new(key)), quota));
+        self
+    }
+
+    /// Deduplicate the log output for rate limited keys.
+    ///
+    /// The first block of a key is logged with the `warn` level. Further blocks of
+    /// the same key are logged with the `info` level at most once per `interval`,
+    /// together with the number of blocks that weren't logged in the meantime.
+    /// A key that wasn't blocked for `interval` is logged with the `warn` level again.
+    ///
+    /// By default, every block is logged with the `info` level.
+    /// With the `tracing` feature, this applies to the events of rate limited requests.
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    pub fn log_dedup_interval(&mut self, interval: Duration) -> &mut Self {
+        self.log_dedup_interval = Some(interval);
+        self
+    }
+
+    /// Log a warning once the number of [tracked keys](GovernorConfig::tracked_keys)
+    /// reaches `threshold`, so operators notice a growing rate limiting state before memory
+    /// runs out. Requests are never rejected because of this threshold.
+    ///
+    /// The keys are counted whenever a new key is allowed. The warning is logged once
+    /// with the `warn` level and again after the number of keys dropped below `threshold`.
+    #[cfg(feature = "log")]
+    pub fn key_memory_warn_threshold(&mut self, threshold: usize) -> &mut Self {
+        self.key_memory_warn_threshold = Some(threshold);
+        self
+    }
+
+    /// Log every allowed request with the `debug` level, together with the key and,
+    /// with [`use_headers`](Self::use_headers), the remaining quota, for example to
+    /// analyze how close clients get to their quota.
+    ///
+    /// This is off by default, because it logs a message for every request.
+    #[cfg(feature = "log")]
+    pub fn log_allowed(&mut self) -> &mut Self {
+        self.log_allowed = true;
+        self
+    }
+
+    /// Enter a `tracing` span named `rate_limit` while the service handles an allowed
+    /// request, so all events logged during the request carry the rate limiting identity
+    /// in the `extractor` and `key` fields, see [`KeyExtractor::name`] and
+    /// [`KeyExtractor::key_name`]. The `key` field is empty if the extractor doesn't
+    /// name its keys.
+    ///
+    /// Requests that are whitelisted or whose key couldn't be extracted get no span.
+    #[cfg(feature = "tracing")]
+    pub fn key_span(&mut self) -> &mut Self {
+        self.key_span = true;
+        self
+    }
+
+    /// Add `x-ratelimit-limit` and `x-ratelimit-remaining` headers with the value
+    /// `4294967295` ([`u32::MAX`]) to whitelisted responses, for clients that
+    /// expect numeric rate limit headers on every response.
+    ///
+    /// This only has an effect if [`use_headers`](Self::use_headers) is enabled.
+    pub fn unlimited_whitelist_headers(&mut self) -> &mut Self {
+        self.unlimited_whitelist_headers = true;
+        self
+    }
+
+    /// Add rate limit headers for an untouched quota to whitelisted responses, so clients
+    /// can parse the same numeric headers on every response: `x-ratelimit-limit` and
+    /// `x-ratelimit-remaining` are both the configured burst size.
+    /// The `x-ratelimit-whitelisted` header is still added to mark these responses.
+    ///
+    /// This takes precedence over [`unlimited_whitelist_headers`](Self::unlimited_whitelist_headers)
+    /// and only has an effect if [`use_headers`](Self::use_headers) is enabled.
+    pub fn quota_whitelist_headers(&mut self) -> &mut Self {
+        self.quota_whitelist_headers = true;
+        self
+    }
+
+    /// Round the reported wait time up to a multiple of `granularity`, for example to 10 seconds.
+    ///
+    /// This obscures the exact state of the rate limiter and makes rejection responses
+    /// more cacheable. The reported value never underestimates the actual wait time,
+    /// only the `retry-after` and `x-ratelimit-after` headers and the
+    /// [`GovernorResult::Wait`] value are affected.
+    ///
+    /// **The granularity must not be zero.**
+    pub fn retry_after_granularity(&mut self, granularity: Duration) -> &mut Self {
+        self.retry_after_granularity = Some(granularity);
+        self
+    }
+
+    /// Extend the reported wait time until the next wall-clock boundary that is a multiple
+    /// of `interval` since the Unix epoch, for example the start of the next 10 seconds.
+    ///
+    /// Unlike [`retry_after_granularity`](Self::retry_after_granularity), which rounds the
+    /// wait time itself, all clients that are blocked during the same interval are told to
+    /// retry at the same moment, so rejection responses served through a shared cache
+    /// stay identical for longer. This takes precedence over the granularity and affects
+    /// the same values.
+    ///
+    /// **The interval must not be zero.**
+    pub fn align_reset_to(&mut self, interval: Duration) -> &mut Self {
+        self.reset_alignment = Some(interval);
+        self
+    }
+
+    /// Don't count requests against the quota if the service responds with
+    /// one of the `statuses`, for example `304 Not Modified`.
+    ///
+    /// The cell is consumed when the request arrives and refunded after the response
+    /// was created, so concurrent requests of the same key may still be rejected
+    /// while the refunded request is processed.
+    pub fn refund_on_status(&mut self, statuses: Vec<StatusCode>) -> &mut Self {
+        self.refund_statuses = statuses;
+        self
+    }
+
+    /// Set when the quota of an allowed request is consumed.
+    ///
+    /// + [`ChargeTiming::Before`] (default): the cell is consumed before the request is
+    ///   passed to the service, so concurrent requests of a key can't exceed the quota.
+    ///   If the response isn't charged, the cell is refunded afterwards.
+    /// + [`ChargeTiming::After`]: requests are only rejected if the quota is already
+    ///   exhausted, and the cell is consumed once the service responded. This can't prevent
+    ///   expensive work, because concurrent requests of a key all pass until one of them
+    ///   finishes, but the quota is only held by requests that are actually charged.
+    ///
+    /// In both cases, a response is charged unless its status is one of the
+    /// [`refund_on_status`](Self::refund_on_status) statuses. The failed attempts of the
+    /// [`brute_force_protection`](Self::brute_force_protection) are charged when the
+    /// request arrives regardless of the timing. With [`ChargeTiming::After`], the
+    /// `x-ratelimit-remaining` header assumes that the request will be charged.
+    pub fn charge_timing(&mut self, timing: ChargeTiming) -> &mut Self {
+        self.charge_timing = timing;
+        self
+    }
+
+    /// Only refund a cell if the service responded within `window` after the request
+    /// arrived, see [`refund_on_status`](Self::refund_on_status) and
+    /// [`brute_force_protection`](Self::brute_force_protection).
+    ///
+    /// A cell is consumed when the request arrives, so if a slow handler responds
+    /// after the cell was already replenished, refunding it would credit the key with
+    /// a cell of a later period and allow more requests than the quota. Responses that
+    /// take longer than `window` are charged regardless of their status.
+    /// With [`ChargeTiming::After`], nothing is refunded and the window has no effect.
+    pub fn refund_window(&mut self, window: Duration) -> &mut Self {
+        self.refund_window = Some(window);
+        self
+    }
+
+    /// Protect against brute force attacks with a separate rate limiter for failed attempts:
+    /// requests are only counted against `quota` if the service responds with one of the
+    /// `statuses`, usually `401 Unauthorized` and `403 Forbidden`, so legitimate logins don't
+    /// deplete it, but repeated failed attempts of a key are blocked. The key of the attempts
+    /// is extracted by `key_extractor`, for example [PeerIpKeyExtractor] or a
+    /// [UserKeyExtractor] for the username.
+    ///
+    /// The configured quota and key extractor still apply to all requests, so a request is
+    /// rejected if either limiter is exhausted. Requests whose attempt key can't be extracted
+    /// are only limited by the configured quota.
+    ///
+    /// The cell of an attempt is consumed when the request arrives and refunded after the
+    /// response was created, so once `quota` is exhausted, all requests of the key are rejected
+    /// until it replenishes, including ones that would have succeeded. The attempts are
+    /// tracked by a hash of their key.
+    pub fn brute_force_protection<K2>(
+        &mut self,
+        key_extractor: K2,
+        quota: Quota,
+        statuses: Vec<StatusCode>,
+    ) -> &mut Self
+    where
+        K2: KeyExtractor + Send + Sync + 'static,
+    {
+        let key =
+            move |req: &ServiceRequest| key_extractor.extract(req).ok().map(|key| hash_key(&key));
+        self.brute_force = Some((Callback(Arc::new(key)), quota, statuses));
+        self
+    }
+
+    /// Grant keys that have no rate limiting state yet a one-time `bonus` of requests
+    /// on top of the burst size, for example for clients that send a few rapid
+    /// requests during an OAuth handshake.
+    ///
+    /// The first `bonus` allowed requests of a new key are refunded, afterwards the normal
+    /// quota applies. This keeps a counter for each key that hasn't used up its bonus yet,
+    /// which is dropped together with the rate limiting state by [`GovernorConfig::clear`].
+    /// A key whose state was dropped is treated as new again.
+    pub fn new_key_burst_bonus(&mut self, bonus: u32) -> &mut Self {
+        self.new_key_burst_bonus = bonus;
+        self
+    }
+
+    /// Close the connection after the `429 Too Many Requests` response once a key was blocked
+    /// more than `blocks` times in a row, so abusive clients have to reconnect instead of
+    /// hammering the service over a keep-alive connection.
+    ///
+    /// This keeps a counter for each key that is currently blocked, which is reset once
+    /// a request of the key is allowed again.
+    ///
+    /// The connection is only closed for HTTP/1.x. HTTP/2 doesn't support the `Connection`
+    /// header and multiplexes requests over the connection, so it stays open.
+    pub fn close_connection_after_blocks(&mut self, blocks: u32) -> &mut Self {
+        self.close_after_blocks = Some(blocks);
+        self
+    }
+
+    /// **Experimental:** tighten the quota of all keys while the server is overloaded.
+    ///
+    /// At most once per second, `load_signal` is called on a request to get the current load
+    /// of the server, where values above `1.0` mean that the server is overloaded. The
+    /// available fraction of the quota is halved for each such sample under overload, down to
+    /// `min_fraction`, and increased by `0.05` for each other sample (AIMD). Each request then
+    /// costs the inverse of the fraction in cells, at most the burst size, so the effective
+    /// rate shrinks without resetting the state of the keys. Refunds, for example of the
+    /// [`refund_on_status`](Self::refund_on_status) statuses, give back all cells of a request.
+    ///
+    /// `min_fraction` must be greater than zero and at most `1.0`,
+    /// otherwise [`finish`](Self::finish) returns `None`.
+    pub fn adaptive<F>(&mut self, load_signal: F, min_fraction: f64) -> &mut Self
+    where
+        F: Fn() -> f64 + Send + Sync + 'static,
+    {
+        self.adaptive = Some((Callback(Arc::new(load_signal)), min_fraction.to_bits()));
+        self
+    }
+
+    /// Only rate limit the fraction `rate` of the requests, for example `0.05` to roll out
+    /// rate limiting to 5% of the traffic first and gauge its impact.
+    ///
+    /// The keys are sampled by their hash, so the requests of a client are either always or
+    /// never rate limited, and with `0.05` about every 20th key is. The requests of all other
+    /// keys pass through like whitelisted requests. With [`use_headers`](Self::use_headers),
+    /// they carry the `whitelisted` header of the [`header_prefix`](Self::header_prefix),
+    /// which marks them as not sampled. Requests whose key can't be extracted are always
+    /// evaluated.
+    ///
+    /// `rate` must be within `0.0..=1.0`, otherwise [`finish`](Self::finish) returns `None`.
+    pub fn sample_rate(&mut self, rate: f64) -> &mut Self {
+        self.sample_rate = Some(rate.to_bits());
+        self
+    }
+
+    /// Send a [`RateLimitEvent`] for each evaluated request to `sink`,
+    /// for example to stream rate limiting decisions to an audit pipeline.
+    ///
+    /// Events are sent with [`try_send`](tokio::sync::mpsc::Sender::try_send), so the request
+    /// is never delayed and events are dropped if the channel is full or closed.
+    /// The events carry the extracted key, so **this must be set after the key extractor**,
+    /// otherwise [`finish`](Self::finish) returns `None`.
+    pub fn audit_sink(&mut self, sink: Sender<RateLimitEvent<K::Key>>) -> &mut Self {
+        self.audit_sink = Some(audit::AuditSink(sink));
+        self
+    }
+
+    /// Look up the quota of each key with `f` instead of applying the configured quota
+    /// to every key, for example to give premium users a larger burst than free users.
+    /// This takes precedence over [`KeyExtractor::quota`].
+    ///
+    /// A separate rate limiter is created for each distinct quota and shared by all keys
+    /// with that quota, so `f` should return one of a few quotas, and the same one for
+    /// a key as long as possible. `f` is called once for every request, so it should be cheap.
+    ///
+    /// The callback takes the extracted key, so **this must be set after the key extractor**,
+    /// otherwise [`finish`](Self::finish) returns `None`.
+    pub fn quota_extractor<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&K::Key) -> Quota + Send + Sync + 'static,
+    {
+        self.quota_extractor = Some(Callback(Arc::new(f)));
+        self
+    }
+
+    /// Fetch the quota of each key asynchronously with `f`, for example from the plans of
+    /// a SaaS product in a database. This takes precedence over
+    /// [`quota_extractor`](Self::quota_extractor) and [`KeyExtractor::quota`].
+    ///
+    /// ```rust
+    /// use actix_governor::GovernorConfigBuilder;
+    /// use governor::Quota;
+    /// use std::net::IpAddr;
+    /// use std::num::NonZeroU32;
+    /// use std::time::Duration;
+    ///
+    /// async fn plan_quota(ip: IpAddr) -> Quota {
+    ///     // Look up the plan of the client, for example in a database
+    ///     Quota::per_minute(NonZeroU32::new(if ip.is_loopback() { 100 } else { 10 }).unwrap())
+    /// }
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .quota_provider(|ip: &IpAddr| plan_quota(*ip), Duration::from_secs(300))
+    ///     .finish()
+    ///     .unwrap();
+    /// ```
+    ///
+    /// The key is extracted and its quota is awaited before the request is rate limited.
+    /// The quota of each key is cached for `ttl`, so `f` is only called for the first
+    /// request of a key and again for the first request after the cached quota expired.
+    /// Concurrent first requests of a key may call `f` more than once. A changed plan takes
+    /// effect after `ttl` at the latest, or immediately after its cached quota was dropped
+    /// with [`GovernorConfig::invalidate_quota`].
+    ///
+    /// Like with [`quota_extractor`](Self::quota_extractor), a separate rate limiter is created
+    /// for each distinct quota and shared by all keys with that quota, so `f` should return
+    /// one of a few quotas. Keys whose quota changed start with a fresh state.
+    ///
+    /// The callback takes the extracted key, so **this must be set after the key extractor**,
+    /// otherwise [`finish`](Self::finish) returns `None`.
+    pub fn quota_provider<F, Fut>(&mut self, f: F, ttl: Duration) -> &mut Self
+    where
+        F: Fn(&K::Key) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Quota> + 'static,
+    {
+        let provider = move |key: &K::Key| -> LocalBoxFuture<'static, Quota> { Box::pin(f(key)) };
+        self.quota_provider = Some((Callback(Arc::new(provider)), ttl));
+        self
+    }
+
+    /// Drop the rate limiting state of keys that have fully replenished every `interval`,
+    /// so the memory usage is bounded by the number of keys that were active recently
+    /// instead of all keys that were ever seen.
+    ///
+    /// The state is dropped by a task that is spawned on the actix runtime when the
+    /// middleware is constructed for the first time, so nothing is dropped while the
+    /// configuration isn't used by a [Governor]. The task is stopped once the configuration
+    /// and all its clones are dropped. Each run locks the state of every key, so short intervals trade
+    /// CPU time and lock contention for memory. Dropping a replenished key doesn't
+    /// change its quota, it just starts with a fresh state on its next request.
+    ///
+    /// **The interval must not be zero.**
+    pub fn retain_interval(&mut self, interval: Duration) -> &mut Self {
+        self.retain_interval = Some(interval);
+        self
+    }
+
+    /// Add static `headers` to every `429 Too Many Requests` response,
+    /// for example a link to the documentation of your rate limits.
+    ///
+    /// The headers are inserted before the response builder is passed to
+    /// [`KeyExtractor::exceed_rate_limit_response`], so the key extractor can still override them.
+    pub fn rejection_headers(&mut self, headers: Vec<(HeaderName, HeaderValue)>) -> &mut Self {
+        self.rejection_headers = headers;
+        self
+    }
+
+    /// Replace the `x-ratelimit-` prefix of all headers the middleware emits with `prefix`,
+    /// for example `x-acme-ratelimit-` for `x-acme-ratelimit-limit`. The combined header of
+    /// [`combined_header`](Self::combined_header) is named after the prefix without its
+    /// trailing `-`. The `retry-after` and IETF headers are not affected.
+    ///
+    /// Header names are case-insensitive and emitted in lowercase, so `X-RateLimit-` is
+    /// equivalent to the default. [`finish`](Self::finish) returns `None` if the prefix
+    /// doesn't produce legal header names.
+    pub fn header_prefix(&mut self, prefix: &str) -> &mut Self {
+        self.header_prefix = Some(prefix.to_owned());
+        self
+    }
+
+    /// Reject rate limited requests with `status` instead of `429 Too Many Requests`,
+    /// for example `503 Service Unavailable` for clients that back off on it.
+    ///
+    /// The builder passed to [`KeyExtractor::exceed_rate_limit_response`] already has this
+    /// status, and it also applies to the rejections of
+    /// [`penalize_extraction_errors`](Self::penalize_extraction_errors).
+    pub fn rejection_status(&mut self, status: StatusCode) -> &mut Self {
+        self.rejection_status = status;
+        self
+    }
+
+    /// Reject requests that have to wait at least `threshold` with `status` instead of the
+    /// [`rejection_status`](Self::rejection_status), to tell clients that exhausted their burst
+    /// and only have to slow down briefly apart from clients that are persistently over the
+    /// sustained rate, for example with `503 Service Unavailable` for waits of a minute or more.
+    ///
+    /// The wait time is the exact time until the next request is allowed, before the
+    /// [`retry_after_granularity`](Self::retry_after_granularity) or
+    /// [`align_reset_to`](Self::align_reset_to) are applied. The builder passed to
+    /// [`KeyExtractor::exceed_rate_limit_response`] already has the status, so the key
+    /// extractor or [`map_rejection_response`](Self::map_rejection_response) can use it to
+    /// pick a different message.
+    pub fn long_wait_threshold(&mut self, threshold: Duration, status: StatusCode) -> &mut Self {
+        self.long_wait = Some((threshold, status));
+        self
+    }
+
+    /// Delay the responses of rate limited requests by a random duration between `min`
+    /// and `max`, to slow down scrapers that retry right away, while clients that honor
+    /// the `retry-after` header barely notice it.
+    ///
+    /// Each delayed response keeps its connection and a timer until it is sent, so a
+    /// client that floods the service while it is blocked holds more resources than with
+    /// immediate rejections. Keep `max` short, for example a few hundred milliseconds,
+    /// and consider [`close_connection_after_blocks`](Self::close_connection_after_blocks)
+    /// for clients that keep hammering the service.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `min` is greater than `max`.
+    pub fn rejection_delay(&mut self, min: Duration, max: Duration) -> &mut Self {
+        assert!(min <= max, "minimum rejection delay exceeds the maximum");
+        self.rejection_delay = Some(Jitter::new(min, max - min));
+        self
+    }
+
+    /// Add a `Link: <url>; rel="help"` header to every `429 Too Many Requests` response,
+    /// so API consumers can find the documentation of your rate limits.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `url` contains characters that are not allowed in a header value.
+    pub fn help_link(&mut self, url: &str) -> &mut Self {
+        let link = format!("<{}>; rel=\"help\"", url);
+        self.help_link = Some(HeaderValue::try_from(link).expect("invalid help link"));
+        self
+    }
+
+    /// Label `quota` with the policy `name`, for example `premium-tier`. Responses of requests
+    /// that were limited with this quota carry the name in the `x-ratelimit-policy-name` header,
+    /// so clients can tell which of several policies applied.
+    ///
+    /// Both the configured quota and the quotas returned by [`KeyExtractor::quota`],
+    /// for example the tiers of a [ReadWriteKeyExtractor], can be named. Quotas are matched
+    /// after the [`KeyExtractor::burst_multiplier`] was applied.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` contains characters that are not allowed in a header value.
+    pub fn policy_name(&mut self, quota: Quota, name: &str) -> &mut Self {
+        let name = HeaderValue::try_from(name).expect("invalid policy name");
+        self.policy_names.push((quota, name));
+        self
+    }
+
+    /// Additionally limit all requests together to `quota`, for example to protect the
+    /// service from the combined load of many clients that each stay within their quota.
+    ///
+    /// The global quota is checked first. If it is exhausted, the request is rejected with
+    /// the wait time of the global quota, even if the quota of the key is exhausted as well,
+    /// and the key isn't charged. Otherwise the quota of the key is checked, and if it
+    /// rejects the request, the cell of the global quota is refunded, so keys that are
+    /// blocked don't deplete the global quota.
+    ///
+    /// Requests that are rejected by the global quota don't count towards the
+    /// [`violation_grace`](Self::violation_grace) and
+    /// [`close_connection_after_blocks`](Self::close_connection_after_blocks) of their key.
+    pub fn global_cap(&mut self, quota: Quota) -> &mut Self {
+        self.global_cap = Some(quota);
+        self
+    }
+
+    /// Apply the stricter `quota` to requests for which `predicate` returns `true`,
+    /// for example dynamic routes that can't be served from a cache and bear the real
+    /// load of the origin, while cacheable routes keep the configured quota:
+    ///
+    /// ```rust
+    /// use actix_governor::GovernorConfigBuilder;
+    /// use governor::Quota;
+    /// use std::num::NonZeroU32;
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .uncacheable_routes(
+    ///         |req| matches!(req.match_pattern().as_deref(), Some("/search" | "/cart/{id}")),
+    ///         Quota::per_minute(NonZeroU32::new(10).unwrap()),
+    ///     )
+    ///     .finish()
+    ///     .unwrap();
+    /// ```
+    ///
+    /// This takes precedence over [`quota_extractor`](Self::quota_extractor) and
+    /// [`KeyExtractor::quota`], but is still scaled by [`KeyExtractor::burst_multiplier`].
+    /// The uncacheable routes of a key share one bucket that is separate from the bucket of
+    /// its cacheable routes, unless `quota` equals the quota of the key.
+    pub fn uncacheable_routes<F>(&mut self, predicate: F, quota: Quota) -> &mut Self
+    where
+        F: Fn(&ServiceRequest) -> bool + Send + Sync + 'static,
+    {
+        self.uncacheable = Some((Callback(Arc::new(predicate)), quota));
+        self
+    }
+
+    /// Limit requests over resumed TLS sessions to `quota` instead of the configured quota.
+    /// Returning clients that resume their session are likely legitimate, while bots often
+    /// perform a full handshake for each connection, so `quota` is usually more generous.
+    ///
+    /// Requests are only recognized as resumed if the TLS layer marks them with
+    /// [`TlsSessionResumed`], see its documentation. Quotas from
+    /// [`uncacheable_routes`](Self::uncacheable_routes), the
+    /// [`quota_provider`](Self::quota_provider), the
+    /// [`quota_extractor`](Self::quota_extractor) and [`KeyExtractor::quota`] take precedence.
+    /// Resumed and full-handshake requests of a key use separate buckets.
+    pub fn resumed_session_quota(&mut self, quota: Quota) -> &mut Self {
+        self.resumed_session_quota = Some(quota);
+        self
+    }
+
+    /// Transform every `429 Too Many Requests` response with `f` after it was built,
+    /// for example to add headers or cookies.
+    ///
+    /// Unlike [`KeyExtractor::exceed_rate_limit_response`], this doesn't require
+    /// control over the key extractor, so it also works with third-party extractors.
+    pub fn map_rejection_response<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(HttpResponse) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.rejection_mapper = Some(Callback(Arc::new(f)));
+        self
+    }
+
+    /// Call `f` with the request and the outcome of the rate limiter whenever a request is
+    /// rejected because it exceeded its quota, right before the `429 Too Many Requests`
+    /// response is built, for example to count blocked requests for alerting.
+    ///
+    /// Unlike the logging of the `log` feature, `f` gets the whole request, so it can record
+    /// custom metrics. It isn't called for requests that are let through in permissive mode,
+    /// for soft-limited keys or because of the [`violation_grace`](Self::violation_grace).
+    pub fn on_limited<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&ServiceRequest, &NotUntil<QuantaInstant>) + Send + Sync + 'static,
+    {
+        self.on_limited = Some(Callback(Arc::new(f)));
+        self
+    }
+
     /// Finish building the configuration and return the configuration for the middleware.
-    /// Returns `None` if either burst size or period interval are zero.
-    pub fn finish(&mut self) -> Option<GovernorConfig<K, M>> {
-        if self.burst_size != 0 && self.period.as_nanos() != 0 {
-            Some(GovernorConfig {
-                key_extractor: self.key_extractor.clone(),
-                limiter: Arc::new(
-                    RateLimiter::keyed(
-                        Quota::with_period(self.period)
-                            .unwrap()
-                            .allow_burst(NonZeroU32::new(self.burst_size).unwrap()),
-                    )
-                    .with_middleware::<M>(),
-                ),
-                methods: self.methods.clone(),
-                permissive: self.permissive,
-            })
-        } else {
-            None
+    /// Returns `None` if no [`quota`](Self::quota) is set and either burst size or period
+    /// interval are zero, if the `min_fraction` of [`adaptive`](Self::adaptive) or the
+    /// [`sample_rate`](Self::sample_rate) is invalid, or if the [`key_extractor`](Self::key_extractor)
+    /// was set after an option that takes its keys.
+    pub fn finish(&mut self) -> Option<GovernorConfig<K, M>>
+    where
+        K::Key: 'static,
+        M: 'static,
+    {
+        let quota = match self.quota {
+            Some(quota) => quota,
+            None if self.burst_size != 0 && self.period.as_nanos() != 0 => {
+                Quota::with_period(self.period)
+                    .unwrap()
+                    .allow_burst(NonZeroU32::new(self.burst_size).unwrap())
+            }
+            None => return None,
+        };
+        if self.key_options_dropped {
+            return None;
+        }
+        if let Some((_, min_fraction)) = &self.adaptive {
+            let min_fraction = f64::from_bits(*min_fraction);
+            if !(min_fraction > 0.0 && min_fraction <= 1.0) {
+                return None;
+            }
+        }
+        if self
+            .sample_rate
+            .is_some_and(|rate| !(0.0..=1.0).contains(&f64::from_bits(rate)))
+        {
+            return None;
         }
+        let header_names = service::HeaderNames::new(
+            self.header_prefix
+                .as_deref()
+                .unwrap_or(DEFAULT_HEADER_PREFIX),
+        )?;
+        let limiter = new_limiter(quota);
+        let quota_limiters = Arc::default();
+        let retainer = self
+            .retain_interval
+            .map(|interval| Arc::new(retain::Retainer::new(interval)));
+        Some(GovernorConfig {
+            key_extractor: self.key_extractor.clone(),
+            limiter,
+            quota,
+            quota_limiters,
+            methods: self.methods.clone(),
+            permissive: self.permissive,
+            trusted_forwarders: self.trusted_forwarders.clone(),
+            json_response: self.json_response,
+            penalty: self.penalty.as_ref().map(|(key, quota)| Penalty {
+                key: key.clone(),
+                limiter: new_limiter(*quota),
+            }),
+            guard: self.guard.clone(),
+            exempt: self.exempt.clone(),
+            allowlist: self.allowlist.clone(),
+            rejection_mapper: self.rejection_mapper.clone(),
+            on_limited: self.on_limited.clone(),
+            unlimited_whitelist_headers: self.unlimited_whitelist_headers,
+            quota_whitelist_headers: self.quota_whitelist_headers,
+            rejection_headers: self.rejection_headers.clone(),
+            skip_header: self.skip_header.clone(),
+            retry_after_granularity: self.retry_after_granularity,
+            reset_alignment: self.reset_alignment,
+            refund_statuses: self.refund_statuses.clone(),
+            brute_force: self
+                .brute_force
+                .as_ref()
+                .map(|(key, quota, statuses)| BruteForce {
+                    key: key.clone(),
+                    limiter: new_limiter(*quota),
+                    statuses: statuses.clone(),
+                }),
+            charge_timing: self.charge_timing,
+            refund_window: self.refund_window,
+            new_key_burst_bonus: self.new_key_burst_bonus,
+            close_after_blocks: self.close_after_blocks,
+            adaptive: self.adaptive.as_ref().map(|(load, min_fraction)| Adaptive {
+                load: load.clone(),
+                min_fraction: f64::from_bits(*min_fraction),
+                fraction: Arc::new(Mutex::new((1.0, None))),
+            }),
+            sampler: self.sample_rate.map(|rate| Sampler {
+                rate: f64::from_bits(rate),
+            }),
+            new_key_bonuses: Arc::default(),
+            draining: Arc::default(),
+            consecutive_blocks: Arc::default(),
+            audit_sink: self.audit_sink.clone(),
+            quota_extractor: self.quota_extractor.clone(),
+            quota_provider: self
+                .quota_provider
+                .clone()
+                .map(|(provider, ttl)| quota_provider::QuotaProvider::new(provider, ttl)),
+            retainer,
+            content_length_threshold: self.content_length_threshold,
+            treat_head_as_get: self.treat_head_as_get,
+            new_key_limit: self
+                .max_new_keys_per_ip
+                .map(|(max_keys, window)| new_keys::NewKeyLimit::new(max_keys, window)),
+            combined_header: self.combined_header,
+            ietf_headers: self.ietf_headers,
+            full_reset_header: self.full_reset_header,
+            info_header: self.info_header,
+            layer_headers: self.layer_headers,
+            violation_grace: self.violation_grace,
+            help_link: self.help_link.clone(),
+            rejection_status: self.rejection_status,
+            long_wait: self.long_wait,
+            rejection_delay: self.rejection_delay,
+            policy_names: self.policy_names.clone(),
+            global_cap: self.global_cap.map(new_limiter),
+            uncacheable: self.uncacheable.clone(),
+            state_epoch: Arc::default(),
+            header_names: Arc::new(header_names),
+            resumed_session_quota: self.resumed_session_quota,
+            probe_header: self.probe_header.clone(),
+            violations: Arc::default(),
+            #[cfg(feature = "metrics")]
+            metrics: Arc::default(),
+            #[cfg(feature = "tracing")]
+            key_span: self.key_span,
+            #[cfg(any(feature = "log", feature = "tracing"))]
+            block_log: self.log_dedup_interval.map(block_log::BlockLog::new),
+            #[cfg(feature = "log")]
+            key_memory_warning: self
+                .key_memory_warn_threshold
+                .map(|threshold| KeyMemoryWarning {
+                    threshold,
+                    warned: Arc::default(),
+                }),
+            #[cfg(feature = "log")]
+            log_allowed: self.log_allowed,
+        })
     }
 }
 
@@ -541,8 +2131,70 @@ impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBu
 pub struct GovernorConfig<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> {
     key_extractor: K,
     limiter: SharedRateLimiter<K::Key, M>,
+    quota: Quota,
+    quota_limiters: QuotaLimiters<K::Key, M>,
     methods: Option<Vec<Method>>,
     permissive: bool,
+    trusted_forwarders: Option<Vec<IpAddr>>,
+    json_response: bool,
+    penalty: Option<Penalty>,
+    guard: Option<GuardCallback>,
+    exempt: Option<PredicateCallback>,
+    allowlist: Vec<(IpAddr, u8)>,
+    rejection_mapper: Option<ResponseCallback>,
+    on_limited: Option<LimitedCallback>,
+    unlimited_whitelist_headers: bool,
+    quota_whitelist_headers: bool,
+    rejection_headers: Vec<(HeaderName, HeaderValue)>,
+    skip_header: Option<(HeaderName, HeaderValue)>,
+    retry_after_granularity: Option<Duration>,
+    reset_alignment: Option<Duration>,
+    refund_statuses: Vec<StatusCode>,
+    brute_force: Option<BruteForce>,
+    charge_timing: ChargeTiming,
+    refund_window: Option<Duration>,
+    new_key_burst_bonus: u32,
+    close_after_blocks: Option<u32>,
+    adaptive: Option<Adaptive>,
+    sampler: Option<Sampler>,
+    audit_sink: Option<audit::AuditSink<K::Key>>,
+    quota_extractor: Option<QuotaCallback<K::Key>>,
+    quota_provider: Option<quota_provider::QuotaProvider<K::Key>>,
+    retainer: Option<Arc<retain::Retainer>>,
+    content_length_threshold: Option<(u64, bool)>,
+    treat_head_as_get: bool,
+    new_key_limit: Option<new_keys::NewKeyLimit<K::Key>>,
+    combined_header: Option<bool>,
+    ietf_headers: bool,
+    full_reset_header: bool,
+    info_header: bool,
+    layer_headers: bool,
+    violation_grace: u32,
+    help_link: Option<HeaderValue>,
+    rejection_status: StatusCode,
+    long_wait: Option<(Duration, StatusCode)>,
+    rejection_delay: Option<Jitter>,
+    policy_names: Vec<(Quota, HeaderValue)>,
+    global_cap: Option<SharedRateLimiter<(), StateInformationMiddleware>>,
+    uncacheable: Option<(PredicateCallback, Quota)>,
+    state_epoch: Arc<Mutex<Option<String>>>,
+    header_names: Arc<service::HeaderNames>,
+    resumed_session_quota: Option<Quota>,
+    probe_header: Option<(HeaderName, HeaderValue)>,
+    violations: KeyCounters<K::Key>,
+    #[cfg(feature = "metrics")]
+    metrics: Arc<metrics::Counters>,
+    #[cfg(feature = "tracing")]
+    key_span: bool,
+    consecutive_blocks: KeyCounters<K::Key>,
+    new_key_bonuses: KeyCounters<K::Key>,
+    draining: Arc<AtomicBool>,
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    block_log: Option<block_log::BlockLog<K::Key>>,
+    #[cfg(feature = "log")]
+    key_memory_warning: Option<KeyMemoryWarning>,
+    #[cfg(feature = "log")]
+    log_allowed: bool,
 }
 
 impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> Clone for GovernorConfig<K, M> {
@@ -550,8 +2202,535 @@ impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> Clone for Govern
         GovernorConfig {
             key_extractor: self.key_extractor.clone(),
             limiter: self.limiter.clone(),
+            quota: self.quota,
+            quota_limiters: self.quota_limiters.clone(),
             methods: self.methods.clone(),
             permissive: self.permissive,
+            trusted_forwarders: self.trusted_forwarders.clone(),
+            json_response: self.json_response,
+            penalty: self.penalty.clone(),
+            guard: self.guard.clone(),
+            exempt: self.exempt.clone(),
+            allowlist: self.allowlist.clone(),
+            rejection_mapper: self.rejection_mapper.clone(),
+            on_limited: self.on_limited.clone(),
+            unlimited_whitelist_headers: self.unlimited_whitelist_headers,
+            quota_whitelist_headers: self.quota_whitelist_headers,
+            rejection_headers: self.rejection_headers.clone(),
+            skip_header: self.skip_header.clone(),
+            retry_after_granularity: self.retry_after_granularity,
+            reset_alignment: self.reset_alignment,
+            refund_statuses: self.refund_statuses.clone(),
+            brute_force: self.brute_force.clone(),
+            charge_timing: self.charge_timing,
+            refund_window: self.refund_window,
+            new_key_burst_bonus: self.new_key_burst_bonus,
+            close_after_blocks: self.close_after_blocks,
+            adaptive: self.adaptive.clone(),
+            sampler: self.sampler.clone(),
+            audit_sink: self.audit_sink.clone(),
+            quota_extractor: self.quota_extractor.clone(),
+            quota_provider: self.quota_provider.clone(),
+            retainer: self.retainer.clone(),
+            content_length_threshold: self.content_length_threshold,
+            treat_head_as_get: self.treat_head_as_get,
+            new_key_limit: self.new_key_limit.clone(),
+            combined_header: self.combined_header,
+            ietf_headers: self.ietf_headers,
+            full_reset_header: self.full_reset_header,
+            info_header: self.info_header,
+            layer_headers: self.layer_headers,
+            violation_grace: self.violation_grace,
+            help_link: self.help_link.clone(),
+            rejection_status: self.rejection_status,
+            long_wait: self.long_wait,
+            rejection_delay: self.rejection_delay,
+            policy_names: self.policy_names.clone(),
+            global_cap: self.global_cap.clone(),
+            uncacheable: self.uncacheable.clone(),
+            state_epoch: self.state_epoch.clone(),
+            header_names: self.header_names.clone(),
+            resumed_session_quota: self.resumed_session_quota,
+            probe_header: self.probe_header.clone(),
+            violations: self.violations.clone(),
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics.clone(),
+            #[cfg(feature = "tracing")]
+            key_span: self.key_span,
+            consecutive_blocks: self.consecutive_blocks.clone(),
+            new_key_bonuses: self.new_key_bonuses.clone(),
+            draining: self.draining.clone(),
+            #[cfg(any(feature = "log", feature = "tracing"))]
+            block_log: self.block_log.clone(),
+            #[cfg(feature = "log")]
+            key_memory_warning: self.key_memory_warning.clone(),
+            #[cfg(feature = "log")]
+            log_allowed: self.log_allowed,
+        }
+    }
+}
+
+impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> GovernorConfig<K, M> {
+    /// Returns the HTTP methods this configuration applies to,
+    /// or `None` if it applies to all methods.
+    #[must_use]
+    pub fn methods(&self) -> Option<&[Method]> {
+        self.methods.as_deref()
+    }
+
+    /// Returns whether `key` is whitelisted by the key extractor, so its requests are never limited.
+    #[must_use]
+    pub fn is_whitelisted(&self, key: &K::Key) -> bool {
+        self.key_extractor.is_whitelisted(key)
+    }
+
+    /// Returns whether the requests of `key` are rate limited by the
+    /// [`sample_rate`](GovernorConfigBuilder::sample_rate).
+    fn is_sampled(&self, key: &K::Key) -> bool {
+        self.sampler
+            .as_ref()
+            .is_none_or(|sampler| sampler.sample(key))
+    }
+
+    /// Returns whether the peer of the request is in one of the
+    /// [`allowlist`](GovernorConfigBuilder::allowlist) ranges.
+    fn is_allowlisted(&self, req: &ServiceRequest) -> bool {
+        !self.allowlist.is_empty()
+            && req.peer_addr().is_some_and(|addr| {
+                self.allowlist
+                    .iter()
+                    .any(|(network, prefix)| key_extractor::in_range(addr.ip(), *network, *prefix))
+            })
+    }
+
+    /// Returns the number of keys that are currently tracked by all limiters of this configuration.
+    #[must_use]
+    pub fn tracked_keys(&self) -> usize {
+        tracked_keys(&self.limiter, &self.quota_limiters)
+    }
+
+    /// Shrinks the memory of all limiters of this configuration as much as possible,
+    /// for example after many keys were dropped with [`clear`](Self::clear).
+    /// The state of the tracked keys is kept.
+    pub fn shrink_to_fit(&self) {
+        self.limiter.shrink_to_fit();
+        for limiter in self
+            .quota_limiters
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+        {
+            limiter.shrink_to_fit();
+        }
+    }
+
+    /// Logs a warning if the number of tracked keys reached the
+    /// [`key_memory_warn_threshold`](GovernorConfigBuilder::key_memory_warn_threshold).
+    #[cfg(feature = "log")]
+    fn check_key_memory(&self) {
+        if let Some(warning) = &self.key_memory_warning {
+            let tracked = self.tracked_keys();
+            if tracked < warning.threshold {
+                warning.warned.store(false, Ordering::Relaxed);
+            } else if !warning.warned.swap(true, Ordering::Relaxed) {
+                log::warn!(
+                    "Rate limiter tracks {} keys, reaching the warning threshold of {}",
+                    tracked,
+                    warning.threshold
+                );
+            }
+        }
+    }
+
+    /// Stop limiting requests while the server is drained, for example during a graceful
+    /// shutdown, so in-flight clients can finish without spurious rejections.
+    /// All requests are passed through like whitelisted requests until [`end_drain`](Self::end_drain)
+    /// is called. With the `log` feature, the `reason` is logged.
+    ///
+    /// The drain mode is shared by all clones of this configuration.
+    pub fn begin_drain(&self, reason: &str) {
+        self.draining.store(true, Ordering::Relaxed);
+        #[cfg(feature = "log")]
+        log::warn!("Rate limiting is suspended while draining: {}", reason);
+        #[cfg(not(feature = "log"))]
+        let _ = reason;
+    }
+
+    /// Resume limiting requests after [`begin_drain`](Self::begin_drain).
+    pub fn end_drain(&self) {
+        if self.draining.swap(false, Ordering::Relaxed) {
+            #[cfg(feature = "log")]
+            log::info!("Rate limiting is resumed after draining");
+        }
+    }
+
+    /// Returns whether requests are passed through because of [`begin_drain`](Self::begin_drain).
+    #[must_use]
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Starts the task of the [`retain_interval`](GovernorConfigBuilder::retain_interval)
+    /// on the current actix runtime, unless it's already running.
+    fn start_retainer(&self)
+    where
+        K::Key: 'static,
+        M: 'static,
+    {
+        if let Some(retainer) = &self.retainer {
+            retainer.start(&self.limiter, &self.quota_limiters);
+        }
+    }
+
+    /// Drop the rate limiting state of all keys, so every client starts with a fresh quota.
+    ///
+    /// This is useful for "reset all limits" admin actions and for tests.
+    ///
+    /// Clearing is not atomic: requests that are checked concurrently may
+    /// be counted against the old or the fresh state.
+    #[doc(alias = "reset_all")]
+    pub fn clear(&self) {
+        self.limiter.clear();
+        for limiter in self
+            .quota_limiters
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+        {
+            limiter.clear();
+        }
+        self.new_key_bonuses
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+        self.consecutive_blocks
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+        self.violations
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+        if let Some(new_key_limit) = &self.new_key_limit {
+            new_key_limit.clear();
+        }
+        if let Some(global_cap) = &self.global_cap {
+            global_cap.clear();
+        }
+        if let Some(penalty) = &self.penalty {
+            penalty.limiter.clear();
+        }
+        if let Some(brute_force) = &self.brute_force {
+            brute_force.limiter.clear();
+        }
+    }
+
+    /// Returns the current [`state_epoch`](Self::set_state_epoch), if any.
+    #[must_use]
+    pub fn state_epoch(&self) -> Option<String> {
+        self.state_epoch
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Set the version string `epoch` and [`clear`](Self::clear) the rate limiting state
+    /// if it changed, so bumping the epoch, for example on deploy, starts every key with a
+    /// fresh quota, while setting the same epoch again preserves the state.
+    ///
+    /// The state isn't kept per epoch, the state of the previous epoch is dropped. A new
+    /// configuration starts without an epoch, so the first call clears the state as well.
+    /// Returns whether the epoch changed.
+    pub fn set_state_epoch(&self, epoch: &str) -> bool {
+        let mut current = self
+            .state_epoch
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if current.as_deref() == Some(epoch) {
+            return false;
+        }
+        *current = Some(epoch.to_owned());
+        self.clear();
+        true
+    }
+
+    /// Drop the rate limiting state of `key`, so the client starts with a fresh quota,
+    /// for example after an admin unbanned a user. Use [`clear`](Self::clear) to reset all keys.
+    ///
+    /// The state is removed from the concurrent state store of every quota, so
+    /// requests of the key that are checked concurrently may be counted against
+    /// the old or the fresh state.
+    ///
+    /// The keys that were introduced by a client for
+    /// [`max_new_keys_per_ip`](GovernorConfigBuilder::max_new_keys_per_ip) are forgotten as well.
+    /// The [`brute_force_protection`](GovernorConfigBuilder::brute_force_protection) and
+    /// [`penalize_extraction_errors`](GovernorConfigBuilder::penalize_extraction_errors) use
+    /// their own key extractors and track a hash of their keys, their state is dropped if
+    /// their key equals `key`, like when all of them use the [PeerIpKeyExtractor].
+    pub fn reset_key(&self, key: &K::Key) {
+        self.limiter.remove(key);
+        for limiter in self
+            .quota_limiters
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+        {
+            limiter.remove(key);
+        }
+        for counters in [
+            &self.new_key_bonuses,
+            &self.consecutive_blocks,
+            &self.violations,
+        ] {
+            counters
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove(key);
+        }
+        if let Some(new_key_limit) = &self.new_key_limit {
+            new_key_limit.forget(key);
+        }
+        let hash = hash_key(key);
+        if let Some(penalty) = &self.penalty {
+            penalty.limiter.remove(&hash);
+        }
+        if let Some(brute_force) = &self.brute_force {
+            brute_force.limiter.remove(&hash);
+        }
+    }
+
+    /// Drop the cached quota of `key`, so it's fetched from the
+    /// [`quota_provider`](GovernorConfigBuilder::quota_provider) again on its next request,
+    /// for example after the plan of a customer changed.
+    pub fn invalidate_quota(&self, key: &K::Key) {
+        if let Some(quota_provider) = &self.quota_provider {
+            quota_provider.invalidate(key);
+        }
+    }
+
+    /// Returns whether the request is covered by the content length threshold,
+    /// the methods filter and the guard and isn't skipped because of the skip header
+    /// or exempted.
+    fn applies_to(&self, req: &ServiceRequest) -> bool {
+        if self.exempt.as_ref().is_some_and(|exempt| (exempt.0)(req)) {
+            return false;
+        }
+        if let Some((name, value)) = &self.skip_header {
+            let skipped = req
+                .headers()
+                .get_all(name)
+                .any(|v| v.as_bytes().eq_ignore_ascii_case(value.as_bytes()));
+            if skipped {
+                return false;
+            }
+        }
+
+        if let Some((threshold, limit_unknown)) = self.content_length_threshold {
+            let content_length = req
+                .headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<u64>().ok());
+            let limited = match content_length {
+                Some(length) => length > threshold,
+                None => limit_unknown,
+            };
+            if !limited {
+                return false;
+            }
+        }
+
+        self.methods
+            .as_ref()
+            .is_none_or(|methods| methods.contains(req.method()))
+            && self
+                .guard
+                .as_ref()
+                .is_none_or(|guard| guard.0.check(&req.guard_ctx()))
+    }
+
+    /// Returns the reported wait time in seconds for a rejected request.
+    fn retry_after(&self, negative: &NotUntil<QuantaInstant>) -> u64 {
+        let wait = negative.wait_time_from(DefaultClock::default().now());
+        if let Some(interval) = self.reset_alignment.filter(|interval| !interval.is_zero()) {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos();
+            let reset = (now + wait.as_nanos()).div_ceil(interval.as_nanos()) * interval.as_nanos();
+            return u64::try_from((reset - now).div_ceil(1_000_000_000)).unwrap_or(u64::MAX);
+        }
+        match self.retry_after_granularity {
+            Some(granularity) if !granularity.is_zero() => {
+                let steps = wait.as_nanos().div_ceil(granularity.as_nanos());
+                let nanos = steps * granularity.as_nanos();
+                u64::try_from(nanos.div_ceil(1_000_000_000)).unwrap_or(u64::MAX)
+            }
+            _ => wait.as_secs(),
+        }
+    }
+
+    /// Returns the builder for rejection responses with the configured
+    /// [`rejection_status`](GovernorConfigBuilder::rejection_status), or the status of the
+    /// [`long_wait_threshold`](GovernorConfigBuilder::long_wait_threshold) if the wait of
+    /// `negative` exceeds it, [`help_link`](GovernorConfigBuilder::help_link)
+    /// and [`rejection_headers`](GovernorConfigBuilder::rejection_headers).
+    fn rejection_builder(&self, negative: &NotUntil<QuantaInstant>) -> HttpResponseBuilder {
+        let status = match self.long_wait {
+            Some((threshold, status))
+                if negative.wait_time_from(DefaultClock::default().now()) >= threshold =>
+            {
+                status
+            }
+            _ => self.rejection_status,
+        };
+        let mut builder = HttpResponse::build(status);
+        if let Some(link) = &self.help_link {
+            builder.insert_header((LINK, link.clone()));
+        }
+        for header in &self.rejection_headers {
+            builder.insert_header(header.clone());
+        }
+        builder
+    }
+
+    /// Applies the [`map_rejection_response`](GovernorConfigBuilder::map_rejection_response)
+    /// callback to a rejection response.
+    fn map_rejection(&self, response: HttpResponse) -> HttpResponse {
+        match &self.rejection_mapper {
+            Some(mapper) => (mapper.0)(response),
+            None => response,
+        }
+    }
+
+    /// Returns the name of the policy with `quota`, see [`GovernorConfigBuilder::policy_name`].
+    fn policy_name(&self, quota: Quota) -> Option<HeaderValue> {
+        self.policy_names
+            .iter()
+            .find(|(named, _)| *named == quota)
+            .map(|(_, name)| name.clone())
+    }
+
+    /// Refunds the `cost` in cells of an allowed request if `key` has a new key bonus left.
+    /// The bonus is granted if the key was `new` before the request was checked.
+    fn apply_new_key_bonus(
+        &self,
+        limiter: &state::Limiter<K::Key, M>,
+        key: &K::Key,
+        new: bool,
+        cost: u32,
+    ) {
+        let mut bonuses = self
+            .new_key_bonuses
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if new {
+            bonuses.insert(key.clone(), self.new_key_burst_bonus);
+        }
+        if let Some(remaining) = bonuses.get_mut(key) {
+            limiter.refund_cells(key, cost);
+            *remaining -= 1;
+            if *remaining == 0 {
+                bonuses.remove(key);
+            }
+        }
+    }
+
+    /// Tracks the consecutive blocks of `key` and returns whether the connection
+    /// should be closed, see [`GovernorConfigBuilder::close_connection_after_blocks`].
+    fn record_block(&self, key: &K::Key, blocked: bool) -> bool {
+        let Some(limit) = self.close_after_blocks else {
+            return false;
+        };
+        let mut blocks = self
+            .consecutive_blocks
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if !blocked {
+            blocks.remove(key);
+            return false;
+        }
+        let count = blocks.entry(key.clone()).or_default();
+        *count = count.saturating_add(1);
+        *count > limit
+    }
+
+    /// Counts the violations of `key` and returns whether the request is
+    /// let through anyway, see [`GovernorConfigBuilder::violation_grace`].
+    fn record_violation(&self, key: &K::Key, violated: bool) -> bool {
+        if self.violation_grace == 0 {
+            return false;
+        }
+        let mut violations = self
+            .violations
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if !violated {
+            violations.remove(key);
+            return false;
+        }
+        let count = violations.entry(key.clone()).or_default();
+        *count = count.saturating_add(1);
+        *count <= self.violation_grace
+    }
+
+    /// Charges the penalty for a failed key extraction.
+    /// Returns the negative outcome if the penalty quota is exceeded.
+    fn penalize(&self, req: &ServiceRequest) -> Option<NotUntil<QuantaInstant>> {
+        let penalty = self.penalty.as_ref()?;
+        let key = (penalty.key.0)(req)?;
+        penalty.limiter.check_key(&hash_key(&key)).err()
+    }
+
+    /// Charges the [`brute_force_protection`](GovernorConfigBuilder::brute_force_protection)
+    /// for an attempt and returns the hash of its key, if any.
+    /// Returns the negative outcome if the quota of the key is exceeded.
+    fn charge_attempt(&self, req: &ServiceRequest) -> Result<Option<u64>, NotUntil<QuantaInstant>> {
+        let Some(brute_force) = &self.brute_force else {
+            return Ok(None);
+        };
+        let Some(key) = (brute_force.key.0)(req) else {
+            return Ok(None);
+        };
+        brute_force.limiter.check_key(&key).map(|()| Some(key))
+    }
+
+    /// Gives back the cell of an attempt that was charged by [`charge_attempt`](Self::charge_attempt).
+    fn refund_attempt(&self, key: Option<u64>) {
+        if let (Some(brute_force), Some(key)) = (&self.brute_force, key) {
+            brute_force.limiter.refund(&key);
+        }
+    }
+
+    /// Returns the rate limiter responsible for the given request and key.
+    fn limiter(&self, req: &ServiceRequest, key: &K::Key) -> SharedRateLimiter<K::Key, M> {
+        let provided = self
+            .quota_provider
+            .as_ref()
+            .and_then(|quota_provider| quota_provider.cached(key));
+        let quota = match (&self.uncacheable, provided, &self.quota_extractor) {
+            (Some((predicate, quota)), _, _) if (predicate.0)(req) => *quota,
+            (_, Some(quota), _) => quota,
+            (_, _, Some(quota_extractor)) => (quota_extractor.0)(key),
+            _ => self.key_extractor.quota(key).unwrap_or_else(|| {
+                self.resumed_session_quota
+                    .filter(|_| {
+                        req.conn_data::<TlsSessionResumed>().is_some()
+                            || req.extensions().contains::<TlsSessionResumed>()
+                    })
+                    .unwrap_or(self.quota)
+            }),
+        };
+        let quota = scale_burst(quota, self.key_extractor.burst_multiplier(req));
+        if quota == self.quota {
+            self.limiter.clone()
+        } else {
+            self.quota_limiters
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .entry((quota.burst_size(), quota.replenish_interval()))
+                .or_insert_with(|| new_limiter(quota))
+                .clone()
         }
     }
 }
@@ -570,14 +2749,72 @@ impl<M: RateLimitingMiddleware<QuantaInstant>> GovernorConfig<PeerIpKeyExtractor
     ///
     /// This prevents brute-forcing passwords or security tokens
     /// yet allows to quickly retype a wrong password once before the quota is exceeded.
-    pub fn secure() -> Self {
+    pub fn secure() -> Self
+    where
+        M: 'static,
+    {
         GovernorConfigBuilder {
             period: Duration::from_secs(4),
             burst_size: 2,
+            quota: None,
             methods: None,
             key_extractor: PeerIpKeyExtractor,
             middleware: PhantomData,
             permissive: false,
+            trusted_forwarders: None,
+            json_response: false,
+            penalty: None,
+            guard: None,
+            exempt: None,
+            allowlist: Vec::new(),
+            rejection_mapper: None,
+            on_limited: None,
+            unlimited_whitelist_headers: false,
+            quota_whitelist_headers: false,
+            rejection_headers: Vec::new(),
+            skip_header: None,
+            retry_after_granularity: None,
+            reset_alignment: None,
+            refund_statuses: Vec::new(),
+            brute_force: None,
+            charge_timing: ChargeTiming::Before,
+            refund_window: None,
+            new_key_burst_bonus: 0,
+            close_after_blocks: None,
+            adaptive: None,
+            sample_rate: None,
+            audit_sink: None,
+            quota_extractor: None,
+            quota_provider: None,
+            key_options_dropped: false,
+            retain_interval: None,
+            content_length_threshold: None,
+            treat_head_as_get: false,
+            max_new_keys_per_ip: None,
+            combined_header: None,
+            ietf_headers: false,
+            full_reset_header: false,
+            info_header: false,
+            layer_headers: false,
+            violation_grace: 0,
+            help_link: None,
+            rejection_status: StatusCode::TOO_MANY_REQUESTS,
+            long_wait: None,
+            rejection_delay: None,
+            policy_names: Vec::new(),
+            global_cap: None,
+            uncacheable: None,
+            header_prefix: None,
+            resumed_session_quota: None,
+            probe_header: None,
+            #[cfg(any(feature = "log", feature = "tracing"))]
+            log_dedup_interval: None,
+            #[cfg(feature = "log")]
+            key_memory_warn_threshold: None,
+            #[cfg(feature = "log")]
+            log_allowed: false,
+            #[cfg(feature = "tracing")]
+            key_span: false,
         }
         .finish()
         .unwrap()
@@ -586,29 +2823,23 @@ impl<M: RateLimitingMiddleware<QuantaInstant>> GovernorConfig<PeerIpKeyExtractor
 
 /// Governor middleware factory.
 pub struct Governor<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> {
-    key_extractor: K,
-    limiter: SharedRateLimiter<K::Key, M>,
-    methods: Option<Vec<Method>>,
-    permissive: bool,
+    config: GovernorConfig<K, M>,
 }
 
 impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> Governor<K, M> {
     /// Create new governor middleware factory from configuration.
     pub fn new(config: &GovernorConfig<K, M>) -> Self {
         Governor {
-            key_extractor: config.key_extractor.clone(),
-            limiter: config.limiter.clone(),
-            methods: config.methods.clone(),
-            permissive: config.permissive,
+            config: config.clone(),
         }
     }
 }
 
 impl<S, B, K> Transform<S, ServiceRequest> for Governor<K, NoOpMiddleware>
 where
-    K: KeyExtractor,
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
-    B: MessageBody,
+    K: KeyExtractor + 'static,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
 {
     type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
@@ -617,21 +2848,19 @@ where
     type Future = future::Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
+        self.config.start_retainer();
         future::ok(GovernorMiddleware::<S, K, NoOpMiddleware> {
             service: Rc::new(RefCell::new(service)),
-            key_extractor: self.key_extractor.clone(),
-            limiter: self.limiter.clone(),
-            methods: self.methods.clone(),
-            permissive: self.permissive,
+            config: self.config.clone(),
         })
     }
 }
 
 impl<S, B, K> Transform<S, ServiceRequest> for Governor<K, StateInformationMiddleware>
 where
-    K: KeyExtractor,
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
-    B: MessageBody,
+    K: KeyExtractor + 'static,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
     <S as Service<ServiceRequest>>::Future: Unpin,
 {
     type Response = ServiceResponse<EitherBody<B>>;
@@ -641,12 +2870,10 @@ where
     type Future = future::Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
+        self.config.start_retainer();
         future::ok(GovernorMiddleware::<S, K, StateInformationMiddleware> {
             service: Rc::new(RefCell::new(service)),
-            key_extractor: self.key_extractor.clone(),
-            limiter: self.limiter.clone(),
-            methods: self.methods.clone(),
-            permissive: self.permissive,
+            config: self.config.clone(),
         })
     }
 }
@@ -669,6 +2896,9 @@ pub enum GovernorResult<E> {
         burst_size: Option<u32>,
         /// The time to wait for new requests in seconds.
         wait: u64,
+        /// The wall-clock time at which new requests are allowed, `wait` seconds after the
+        /// request was rate limited, for example to show clients when they can retry.
+        reset_at: SystemTime,
     },
     /// Internal error.
     Err(E),
@@ -693,17 +2923,19 @@ impl<E> GovernorResult<E> {
         }
     }
 
-    const fn wait(wait: u64) -> Self {
+    fn wait(wait: u64) -> Self {
         Self::Wait {
             wait,
             burst_size: None,
+            reset_at: reset_at(wait),
         }
     }
 
-    const fn wait_with_info(wait: u64, burst_size: u32) -> Self {
+    fn wait_with_info(wait: u64, burst_size: u32) -> Self {
         Self::Wait {
             wait,
             burst_size: Some(burst_size),
+            reset_at: reset_at(wait),
         }
     }
 
@@ -712,6 +2944,12 @@ impl<E> GovernorResult<E> {
     }
 }
 
+/// Returns the wall-clock time `wait` seconds from now.
+fn reset_at(wait: u64) -> SystemTime {
+    let now = SystemTime::now();
+    now.checked_add(Duration::from_secs(wait)).unwrap_or(now)
+}
+
 impl<E> GovernorResult<E> {
     /// Check if this request is rate limited.
     ///
@@ -733,8 +2971,16 @@ impl<E> GovernorResult<E> {
 /// A middleware that implements rate limiting based on the governor crate.
 pub struct GovernorMiddleware<S, K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> {
     service: std::rc::Rc<std::cell::RefCell<S>>,
-    key_extractor: K,
-    limiter: SharedRateLimiter<K::Key, M>,
-    methods: Option<Vec<Method>>,
-    permissive: bool,
+    config: GovernorConfig<K, M>,
+}
+
+impl<S, K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> Clone
+    for GovernorMiddleware<S, K, M>
+{
+    fn clone(&self) -> Self {
+        Self {
+            service: self.service.clone(),
+            config: self.config.clone(),
+        }
+    }
 }
@@ -0,0 +1,105 @@
+//! Counters of the middleware that can be rendered by a metrics exporter.
+
+use crate::{tracked_keys, Callback, GovernorConfig, KeyExtractor};
+
+use governor::{clock::QuantaInstant, middleware::RateLimitingMiddleware};
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+/// The counters that the middleware increments.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    allowed: AtomicU64,
+    blocked: AtomicU64,
+}
+
+impl Counters {
+    pub(crate) fn record_allowed(&self) {
+        self.allowed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_blocked(&self) {
+        self.blocked.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// A handle to the metrics of a [`GovernorConfig`], see [`GovernorConfig::metrics`].
+///
+/// All clones of the handle and of the configuration share the same counters,
+/// so the handle can be moved into a metrics exporter, for example one that renders
+/// the values in the Prometheus text format.
+#[derive(Debug, Clone)]
+pub struct GovernorMetrics {
+    counters: Arc<Counters>,
+    tracked_keys: Callback<dyn Fn() -> usize + Send + Sync>,
+}
+
+impl GovernorMetrics {
+    /// Returns the total number of requests that were within their quota.
+    #[must_use]
+    pub fn allowed(&self) -> u64 {
+        self.counters.allowed.load(Ordering::Relaxed)
+    }
+
+    /// Returns the total number of requests that were rejected because they exceeded their
+    /// quota, including rejections of the
+    /// [`penalize_extraction_errors`](crate::GovernorConfigBuilder::penalize_extraction_errors)
+    /// quota and of the [`max_new_keys_per_ip`](crate::GovernorConfigBuilder::max_new_keys_per_ip)
+    /// limit. Requests that are let through in permissive mode are not counted.
+    #[must_use]
+    pub fn blocked(&self) -> u64 {
+        self.counters.blocked.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of keys that are currently tracked, like
+    /// [`GovernorConfig::tracked_keys`], or `0` once the configuration was dropped.
+    #[must_use]
+    pub fn tracked_keys(&self) -> usize {
+        (self.tracked_keys.0)()
+    }
+}
+
+impl<K, M> GovernorConfig<K, M>
+where
+    K: KeyExtractor,
+    K::Key: Send + Sync + 'static,
+    M: RateLimitingMiddleware<QuantaInstant> + Send + Sync + 'static,
+{
+    /// Returns a handle to the counters of allowed and blocked requests and the number of
+    /// tracked keys of this configuration.
+    ///
+    /// ```rust
+    /// use actix_governor::GovernorConfigBuilder;
+    ///
+    /// let config = GovernorConfigBuilder::default().finish().unwrap();
+    /// let metrics = config.metrics();
+    ///
+    /// // Render the metrics with your own exporter
+    /// let rendered = format!(
+    ///     "governor_allowed_total {}\ngovernor_blocked_total {}\ngovernor_tracked_keys {}\n",
+    ///     metrics.allowed(),
+    ///     metrics.blocked(),
+    ///     metrics.tracked_keys(),
+    /// );
+    /// assert!(rendered.contains("governor_allowed_total 0"));
+    /// ```
+    pub fn metrics(&self) -> GovernorMetrics {
+        // Only keep weak references, so the handle doesn't keep the limiters alive.
+        let limiter = Arc::downgrade(&self.limiter);
+        let quota_limiters = Arc::downgrade(&self.quota_limiters);
+        GovernorMetrics {
+            counters: self.metrics.clone(),
+            tracked_keys: Callback(Arc::new(move || {
+                match (limiter.upgrade(), quota_limiters.upgrade()) {
+                    (Some(limiter), Some(quota_limiters)) => {
+                        tracked_keys(&limiter, &quota_limiters)
+                    }
+                    _ => 0,
+                }
+            })),
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+    net::IpAddr,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug)]
+struct Introductions<K> {
+    window_start: Instant,
+    keys: HashSet<K>,
+}
+
+#[derive(Debug)]
+struct Sources<K> {
+    sources: HashMap<IpAddr, Introductions<K>>,
+    last_cleanup: Instant,
+}
+
+/// Tracks the distinct keys each IP introduced in the current window
+/// to limit the enumeration of keys from a single source.
+#[derive(Debug)]
+pub(crate) struct NewKeyLimit<K> {
+    max_keys: usize,
+    window: Duration,
+    sources: Arc<Mutex<Sources<K>>>,
+}
+
+impl<K> Clone for NewKeyLimit<K> {
+    fn clone(&self) -> Self {
+        Self {
+            max_keys: self.max_keys,
+            window: self.window,
+            sources: self.sources.clone(),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone> NewKeyLimit<K> {
+    pub(crate) fn new(max_keys: usize, window: Duration) -> Self {
+        Self {
+            max_keys,
+            window,
+            sources: Arc::new(Mutex::new(Sources {
+                sources: HashMap::new(),
+                last_cleanup: Instant::now(),
+            })),
+        }
+    }
+
+    /// Records that `ip` used `key` and returns whether the key is admitted.
+    ///
+    /// Keys that `ip` already used in the current window are always admitted,
+    /// other keys only while `ip` introduced less than the maximum number of keys.
+    pub(crate) fn admit(&self, ip: IpAddr, key: &K) -> bool {
+        let now = Instant::now();
+        let window = self.window;
+        let mut sources = self.sources.lock().unwrap_or_else(PoisonError::into_inner);
+
+        // Forget sources of past windows so the map doesn't grow without bounds.
+        if now.duration_since(sources.last_cleanup) >= window {
+            sources
+                .sources
+                .retain(|_, source| now.duration_since(source.window_start) < window);
+            sources.last_cleanup = now;
+        }
+
+        let source = sources.sources.entry(ip).or_insert_with(|| Introductions {
+            window_start: now,
+            keys: HashSet::new(),
+        });
+        if now.duration_since(source.window_start) >= window {
+            source.window_start = now;
+            source.keys.clear();
+        }
+
+        if source.keys.contains(key) {
+            true
+        } else if source.keys.len() < self.max_keys {
+            source.keys.insert(key.clone());
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Forgets `key` for all sources, so it counts as new again.
+    pub(crate) fn forget(&self, key: &K) {
+        let mut sources = self.sources.lock().unwrap_or_else(PoisonError::into_inner);
+        for source in sources.sources.values_mut() {
+            source.keys.remove(key);
+        }
+    }
+
+    /// Forgets all introduced keys.
+    pub(crate) fn clear(&self) {
+        self.sources
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .sources
+            .clear();
+    }
+}
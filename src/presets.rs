@@ -0,0 +1,34 @@
+//! Ready-to-use configurations for common rate limiting policies.
+
+use governor::{middleware::NoOpMiddleware, Quota};
+
+use crate::{GovernorConfigBuilder, PeerIpKeyExtractor, ReadWriteKeyExtractor};
+
+/// Limit reads and writes of each peer IP independently.
+///
+/// `GET`, `HEAD` and `OPTIONS` requests are reads and use `read_quota`,
+/// all other methods are writes and use `write_quota`, see [`MethodClass`](crate::MethodClass).
+/// A burst of reads doesn't deplete the write budget and vice versa.
+///
+/// ```rust
+/// use actix_governor::presets;
+/// use governor::Quota;
+/// use std::num::NonZeroU32;
+///
+/// let config = presets::read_write(
+///     Quota::per_second(NonZeroU32::new(50).unwrap()),
+///     Quota::per_minute(NonZeroU32::new(10).unwrap()),
+/// )
+/// .finish()
+/// .unwrap();
+/// ```
+pub fn read_write(
+    read_quota: Quota,
+    write_quota: Quota,
+) -> GovernorConfigBuilder<ReadWriteKeyExtractor, NoOpMiddleware> {
+    GovernorConfigBuilder::default().key_extractor(ReadWriteKeyExtractor::new(
+        PeerIpKeyExtractor,
+        read_quota,
+        write_quota,
+    ))
+}
@@ -0,0 +1,104 @@
+use crate::QuotaProviderCallback;
+
+use futures::future::LocalBoxFuture;
+use governor::Quota;
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// The quotas fetched by the [`quota_provider`](crate::GovernorConfigBuilder::quota_provider)
+/// and the time they were fetched at.
+#[derive(Debug)]
+struct QuotaCache<K> {
+    quotas: HashMap<K, (Quota, Instant)>,
+    last_cleanup: Instant,
+}
+
+impl<K> QuotaCache<K> {
+    fn lock(cache: &Mutex<Self>) -> MutexGuard<'_, Self> {
+        cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Fetches the quota of each key asynchronously and caches it for `ttl`.
+#[derive(Debug)]
+pub(crate) struct QuotaProvider<K> {
+    provider: QuotaProviderCallback<K>,
+    ttl: Duration,
+    cache: Arc<Mutex<QuotaCache<K>>>,
+}
+
+impl<K> Clone for QuotaProvider<K> {
+    fn clone(&self) -> Self {
+        Self {
+            provider: self.provider.clone(),
+            ttl: self.ttl,
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+impl<K: Hash + Eq> QuotaProvider<K> {
+    pub(crate) fn new(provider: QuotaProviderCallback<K>, ttl: Duration) -> Self {
+        Self {
+            provider,
+            ttl,
+            cache: Arc::new(Mutex::new(QuotaCache {
+                quotas: HashMap::new(),
+                last_cleanup: Instant::now(),
+            })),
+        }
+    }
+
+    /// Returns the cached quota of `key`, even if it's stale.
+    pub(crate) fn cached(&self, key: &K) -> Option<Quota> {
+        QuotaCache::lock(&self.cache)
+            .quotas
+            .get(key)
+            .map(|(quota, _)| *quota)
+    }
+
+    /// Forgets the cached quota of `key`, so it's fetched again on its next request.
+    pub(crate) fn invalidate(&self, key: &K) {
+        QuotaCache::lock(&self.cache).quotas.remove(key);
+    }
+}
+
+impl<K: Hash + Eq + Clone + 'static> QuotaProvider<K> {
+    /// Returns a future that fetches the quota of `key` and caches it,
+    /// or `None` if the cached quota is still fresh.
+    pub(crate) fn fetch(&self, key: &K) -> Option<LocalBoxFuture<'static, ()>> {
+        let now = Instant::now();
+        let mut cache = QuotaCache::lock(&self.cache);
+        // Drop stale quotas so the cache doesn't grow without bounds.
+        if now.duration_since(cache.last_cleanup) >= self.ttl {
+            let ttl = self.ttl;
+            cache
+                .quotas
+                .retain(|_, (_, fetched)| now.duration_since(*fetched) < ttl);
+            cache.last_cleanup = now;
+        }
+        if cache
+            .quotas
+            .get(key)
+            .is_some_and(|(_, fetched)| now.duration_since(*fetched) < self.ttl)
+        {
+            return None;
+        }
+        drop(cache);
+
+        let quota = (self.provider.0)(key);
+        let cache = self.cache.clone();
+        let key = key.clone();
+        Some(Box::pin(async move {
+            let quota = quota.await;
+            QuotaCache::lock(&cache)
+                .quotas
+                .insert(key, (quota, Instant::now()));
+        }))
+    }
+}
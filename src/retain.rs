@@ -0,0 +1,81 @@
+use crate::{QuotaLimiters, SharedRateLimiter};
+
+use actix_rt::task::JoinHandle;
+use governor::clock::QuantaInstant;
+use governor::middleware::RateLimitingMiddleware;
+
+use std::{
+    hash::Hash,
+    sync::{Arc, OnceLock, PoisonError},
+    time::Duration,
+};
+
+/// The background task of the
+/// [`retain_interval`](crate::GovernorConfigBuilder::retain_interval),
+/// which is aborted once the last configuration that owns it is dropped.
+#[derive(Debug)]
+pub(crate) struct Retainer {
+    interval: Duration,
+    task: OnceLock<JoinHandle<()>>,
+}
+
+impl Retainer {
+    /// Creates a retainer that runs every `interval` once it was [started](Self::start).
+    pub(crate) fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            task: OnceLock::new(),
+        }
+    }
+
+    /// Spawns a task on the current actix runtime that drops the state of
+    /// fully replenished keys of `limiter` and `quota_limiters` every interval,
+    /// unless it was already started.
+    ///
+    /// The task only holds weak references, so it doesn't keep the limiters alive.
+    pub(crate) fn start<K, M>(
+        &self,
+        limiter: &SharedRateLimiter<K, M>,
+        quota_limiters: &QuotaLimiters<K, M>,
+    ) where
+        K: Hash + Eq + Clone + 'static,
+        M: RateLimitingMiddleware<QuantaInstant> + 'static,
+    {
+        self.task.get_or_init(|| {
+            let interval = self.interval;
+            let limiter = Arc::downgrade(limiter);
+            let quota_limiters = Arc::downgrade(quota_limiters);
+            actix_rt::spawn(async move {
+                let mut ticks = actix_rt::time::interval(interval);
+                // The first tick completes immediately.
+                ticks.tick().await;
+                loop {
+                    ticks.tick().await;
+                    let (Some(limiter), Some(quota_limiters)) =
+                        (limiter.upgrade(), quota_limiters.upgrade())
+                    else {
+                        return;
+                    };
+                    limiter.retain_recent();
+                    limiter.shrink_to_fit();
+                    for limiter in quota_limiters
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .values()
+                    {
+                        limiter.retain_recent();
+                        limiter.shrink_to_fit();
+                    }
+                }
+            })
+        });
+    }
+}
+
+impl Drop for Retainer {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.get() {
+            task.abort();
+        }
+    }
+}
@@ -1,135 +1,1007 @@
 use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse};
-use actix_web::http::header::{HeaderName, HeaderValue};
-use actix_web::{body::MessageBody, Error};
+use actix_web::error::ErrorPayloadTooLarge;
+use actix_web::http::header::{
+    ContentType, HeaderMap, HeaderName, HeaderValue, FORWARDED, X_FORWARDED_FOR,
+};
+use actix_web::http::{Method, StatusCode};
+use actix_web::web::BytesMut;
+use actix_web::{body::MessageBody, Error, HttpResponse, HttpResponseBuilder};
 use futures::{future, TryFutureExt};
-use governor::clock::{Clock, DefaultClock};
-use governor::middleware::{NoOpMiddleware, StateInformationMiddleware};
+use governor::clock::QuantaInstant;
+use governor::middleware::{
+    NoOpMiddleware, RateLimitingMiddleware, StateInformationMiddleware, StateSnapshot,
+};
+use governor::{Jitter, NotUntil, Quota};
 
 use actix_http::body::EitherBody;
 use actix_http::HttpMessage;
-use futures::future::{ok, Either, MapOk, Ready};
+use futures::future::{ok, Either, LocalBoxFuture, MapOk, Ready};
+use futures::StreamExt;
+use pin_project_lite::pin_project;
 use std::future::Future;
+use std::hash::Hash;
 use std::marker::Unpin;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
-use crate::{GovernorMiddleware, GovernorResult, KeyExtractor};
+#[cfg(any(feature = "log", feature = "tracing"))]
+use crate::block_log::BlockLogLevel;
+use crate::{
+    ChargeTiming, GovernorConfig, GovernorMiddleware, GovernorResult, KeyExtractor, RemainingQuota,
+    SharedRateLimiter, SimpleKeyExtractionError,
+};
 
 type ServiceFuture<S, B> = MapOk<
     <S as Service<ServiceRequest>>::Future,
     fn(ServiceResponse<B>) -> ServiceResponse<EitherBody<B>>,
 >;
 
-impl<S, B, K> Service<ServiceRequest> for GovernorMiddleware<S, K, NoOpMiddleware>
+type ForwardServiceFuture<S, B, K, M> =
+    ForwardFut<ServiceFuture<S, B>, <K as KeyExtractor>::Key, M>;
+
+/// The key of an allowed request, the rate limiter it was charged against and its cost in cells.
+type Charged<K, M> = (
+    <K as KeyExtractor>::Key,
+    SharedRateLimiter<<K as KeyExtractor>::Key, M>,
+    u32,
+);
+
+/// Builds a JSON body that mirrors the rate limit headers.
+fn json_response(mut response: HttpResponseBuilder, limit: u32, wait_time: u64) -> HttpResponse {
+    response.content_type(ContentType::json()).body(format!(
+        r#"{{"limit":{},"remaining":0,"retry_after":{}}}"#,
+        limit, wait_time
+    ))
+}
+
+/// Returns a random duration within the bounds of `jitter`.
+fn sample_jitter(jitter: Jitter) -> Duration {
+    // governor only samples a jitter when it's added to a duration.
+    jitter + Duration::ZERO
+}
+
+/// The outcome of [`GovernorConfig::decide`].
+pub enum Decision<K: KeyExtractor, P> {
+    /// The request is not covered by the configuration or its key is whitelisted.
+    Whitelisted,
+    /// The request is within the rate limit. The outcome is a
+    /// [`StateSnapshot`](governor::middleware::StateSnapshot) if
+    /// [`use_headers`](crate::GovernorConfigBuilder::use_headers) is enabled.
+    Allowed {
+        /// The extracted key.
+        key: K::Key,
+        /// The positive outcome of the rate limiting middleware.
+        outcome: P,
+    },
+    /// The request exceeds the rate limit.
+    RateLimited {
+        /// The extracted key.
+        key: K::Key,
+        /// Information about the rate limit that was exceeded.
+        negative: NotUntil<QuantaInstant>,
+        /// The reported wait time in seconds.
+        wait_time: u64,
+        /// Whether the connection should be closed, see
+        /// [`close_connection_after_blocks`](crate::GovernorConfigBuilder::close_connection_after_blocks).
+        close_connection: bool,
+        /// Whether the request should still be let through with a warning, see
+        /// [`violation_grace`](crate::GovernorConfigBuilder::violation_grace).
+        grace: bool,
+    },
+    /// The request is rejected before rate limiting, because it carries forwarded headers,
+    /// but doesn't come from a trusted proxy, see
+    /// [`reject_forwarded_from_untrusted`](crate::GovernorConfigBuilder::reject_forwarded_from_untrusted),
+    /// or because its peer IP introduced too many keys, see
+    /// [`max_new_keys_per_ip`](crate::GovernorConfigBuilder::max_new_keys_per_ip).
+    Forbidden(SimpleKeyExtractionError<&'static str>),
+    /// The key couldn't be extracted from the request.
+    ExtractionFailed {
+        /// The error of the key extractor.
+        error: K::KeyExtractionError,
+        /// Set if the quota of
+        /// [`penalize_extraction_errors`](crate::GovernorConfigBuilder::penalize_extraction_errors)
+        /// is exceeded.
+        penalty: Option<NotUntil<QuantaInstant>>,
+    },
+}
+
+/// The key of a request that was extracted ahead of rate limiting, so the quota of a
+/// [`quota_provider`](crate::GovernorConfigBuilder::quota_provider) could be fetched.
+struct PrefetchedKey<K: KeyExtractor>(Result<K::Key, K::KeyExtractionError>);
+
+/// The hash of the key of an attempt that was charged by the
+/// [`brute_force_protection`](crate::GovernorConfigBuilder::brute_force_protection),
+/// so its cell can be refunded once the service responded.
+struct Attempt(u64);
+
+/// How a request was charged, so the middleware can settle it once the service responded.
+struct Charge<Key: Hash + Eq + Clone, M: RateLimitingMiddleware<QuantaInstant>> {
+    /// The snapshot of the global quota if it allowed the request, see
+    /// [`global_cap`](crate::GovernorConfigBuilder::global_cap).
+    global: Option<StateSnapshot>,
+    /// The rate limiter of the quota of the key if it allowed the request.
+    limiter: Option<SharedRateLimiter<Key, M>>,
+    /// The number of cells the request consumed, see
+    /// [`adaptive`](crate::GovernorConfigBuilder::adaptive).
+    cost: u32,
+}
+
+impl<K, M> GovernorConfig<K, M>
+where
+    K: KeyExtractor + 'static,
+    M: RateLimitingMiddleware<QuantaInstant, NegativeOutcome = NotUntil<QuantaInstant>>,
+{
+    /// Decides how the middleware handles a request, without calling the service.
+    ///
+    /// This contains the whole rate limiting logic of the middleware, so it can be reused
+    /// outside of actix, for example in a tower layer. Each call charges the rate limiter
+    /// like a request that passes through the middleware. In permissive mode, forwarded
+    /// headers aren't checked and failed key extractions aren't penalized.
+    ///
+    /// Key extractors with a [`body_limit`](KeyExtractor::body_limit) expect the
+    /// buffered body in the request extensions. Quotas of a
+    /// [`quota_provider`](crate::GovernorConfigBuilder::quota_provider) are only
+    /// taken from its cache, they aren't fetched.
+    pub fn decide(&self, req: &ServiceRequest) -> Decision<K, M::PositiveOutcome> {
+        self.decide_layered(req).0
+    }
+
+    /// Like [`decide`](Self::decide), but also returns how the request was charged.
+    fn decide_layered(
+        &self,
+        req: &ServiceRequest,
+    ) -> (Decision<K, M::PositiveOutcome>, Charge<K::Key, M>) {
+        let (decision, charge) = self.evaluate(req);
+        self.audit(req, &decision);
+        (decision, charge)
+    }
+
+    /// Like [`decide`](Self::decide), but treats `HEAD` as `GET` if
+    /// [`treat_head_as_get`](crate::GovernorConfigBuilder::treat_head_as_get) is enabled.
+    fn decide_normalized(
+        &self,
+        req: &mut ServiceRequest,
+    ) -> (Decision<K, M::PositiveOutcome>, Charge<K::Key, M>) {
+        if !self.treat_head_as_get || req.method() != Method::HEAD {
+            return self.decide_layered(req);
+        }
+        req.head_mut().method = Method::GET;
+        let decision = self.decide_layered(req);
+        req.head_mut().method = Method::HEAD;
+        decision
+    }
+
+    fn evaluate(
+        &self,
+        req: &ServiceRequest,
+    ) -> (Decision<K, M::PositiveOutcome>, Charge<K::Key, M>) {
+        let mut charge = Charge {
+            global: None,
+            limiter: None,
+            cost: 1,
+        };
+        if self.is_draining() || !self.applies_to(req) || self.is_allowlisted(req) {
+            return (Decision::Whitelisted, charge);
+        }
+
+        if !self.permissive {
+            if let Err(e) = self.check_forwarded(req) {
+                return (Decision::Forbidden(e), charge);
+            }
+        }
+
+        let decision = self.evaluate_key(req, &mut charge);
+        (decision, charge)
+    }
+
+    /// Extracts the key of `req` ahead of rate limiting and returns a future that fetches
+    /// its quota from the [`quota_provider`](crate::GovernorConfigBuilder::quota_provider),
+    /// unless its cached quota is still fresh. The key is stored in the request extensions,
+    /// so it isn't extracted again.
+    fn prefetch_quota(&self, req: &mut ServiceRequest) -> Option<LocalBoxFuture<'static, ()>> {
+        let quota_provider = self.quota_provider.as_ref()?;
+        if self.is_draining() || !self.applies_to(req) || self.is_allowlisted(req) {
+            return None;
+        }
+        let head = self.treat_head_as_get && req.method() == Method::HEAD;
+        if head {
+            req.head_mut().method = Method::GET;
+        }
+        let key = self.key_extractor.extract(req);
+        if head {
+            req.head_mut().method = Method::HEAD;
+        }
+        // Whitelisted and unsampled keys are never limited, so their quota isn't needed.
+        let fetch = key
+            .as_ref()
+            .ok()
+            .filter(|key| !self.is_whitelisted(key) && self.is_sampled(key))
+            .and_then(|key| quota_provider.fetch(key));
+        req.extensions_mut().insert(PrefetchedKey::<K>(key));
+        fetch
+    }
+
+    /// Extracts the key of `req` and checks its quota, recording in `charge`
+    /// how the request was charged.
+    fn evaluate_key(
+        &self,
+        req: &ServiceRequest,
+        charge: &mut Charge<K::Key, M>,
+    ) -> Decision<K, M::PositiveOutcome> {
+        // Use the provided key extractor to extract the rate limiting key from the request.
+        let prefetched = req.extensions_mut().remove::<PrefetchedKey<K>>();
+        let key = match prefetched {
+            Some(PrefetchedKey(key)) => key,
+            None => self.key_extractor.extract(req),
+        };
+        match key {
+            Ok(key) => {
+                // Check if the rate limiting key is whitelisted or not sampled.
+                if self.is_whitelisted(&key) || !self.is_sampled(&key) {
+                    return Decision::Whitelisted;
+                }
+                if let (Some(new_key_limit), Some(addr)) = (&self.new_key_limit, req.peer_addr()) {
+                    if !new_key_limit.admit(addr.ip(), &key) {
+                        return Decision::Forbidden(
+                            SimpleKeyExtractionError::new("Too many distinct keys")
+                                .set_status_code(StatusCode::TOO_MANY_REQUESTS),
+                        );
+                    }
+                }
+                let limiter = self.limiter(req, &key);
+                #[cfg(feature = "log")]
+                let watch_keys = self.key_memory_warning.is_some();
+                #[cfg(not(feature = "log"))]
+                let watch_keys = false;
+                let new = (self.new_key_burst_bonus > 0 || watch_keys) && limiter.is_new(&key);
+                let attempt = match self.charge_attempt(req) {
+                    Ok(attempt) => attempt,
+                    Err(negative) => {
+                        return Decision::RateLimited {
+                            wait_time: self.retry_after(&negative),
+                            close_connection: false,
+                            grace: false,
+                            key,
+                            negative,
+                        }
+                    }
+                };
+                if let Some(global) = &self.global_cap {
+                    match global.check_key(&()) {
+                        Ok(snapshot) => charge.global = Some(snapshot),
+                        Err(negative) => {
+                            self.refund_attempt(attempt);
+                            return Decision::RateLimited {
+                                wait_time: self.retry_after(&negative),
+                                close_connection: false,
+                                grace: false,
+                                key,
+                                negative,
+                            };
+                        }
+                    }
+                }
+                let checked = match &self.adaptive {
+                    Some(adaptive) => {
+                        charge.cost = limiter.cost(adaptive.cost());
+                        limiter.check_key_cost(&key, charge.cost)
+                    }
+                    None => limiter.check_key(&key),
+                };
+                match checked {
+                    Ok(outcome) => {
+                        if self.new_key_burst_bonus > 0 {
+                            self.apply_new_key_bonus(&limiter, &key, new, charge.cost);
+                        }
+                        #[cfg(feature = "log")]
+                        if new {
+                            self.check_key_memory();
+                        }
+                        if self.charge_timing == ChargeTiming::After {
+                            limiter.refund_cells(&key, charge.cost);
+                        }
+                        self.record_block(&key, false);
+                        self.record_violation(&key, false);
+                        if let Some(attempt) = attempt {
+                            req.extensions_mut().insert(Attempt(attempt));
+                        }
+                        charge.limiter = Some(limiter);
+                        Decision::Allowed { key, outcome }
+                    }
+                    Err(negative) => {
+                        if let Some(global) = &self.global_cap {
+                            global.refund(&());
+                        }
+                        self.refund_attempt(attempt);
+                        Decision::RateLimited {
+                            wait_time: self.retry_after(&negative),
+                            close_connection: self.record_block(&key, true),
+                            grace: self.record_violation(&key, true),
+                            key,
+                            negative,
+                        }
+                    }
+                }
+            }
+            Err(error) => Decision::ExtractionFailed {
+                error,
+                penalty: if self.permissive {
+                    None
+                } else {
+                    self.penalize(req)
+                },
+            },
+        }
+    }
+}
+
+impl<K, M> GovernorConfig<K, M>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<QuantaInstant>,
+{
+    /// Builds the `429 Too Many Requests` response for a request that exceeded its rate limit,
+    /// like the middleware does without [`use_headers`](crate::GovernorConfigBuilder::use_headers).
+    pub fn rejection_response(&self, negative: &NotUntil<QuantaInstant>) -> HttpResponse {
+        self.rejection_response_with(negative, self.retry_after(negative), false, false)
+    }
+
+    fn rejection_response_with(
+        &self,
+        negative: &NotUntil<QuantaInstant>,
+        wait_time: u64,
+        limit_headers: bool,
+        close_connection: bool,
+    ) -> HttpResponse {
+        let burst_size = negative.quota().burst_size().get();
+        let mut response_builder = self.rejection_builder(negative);
+        response_builder
+            .insert_header(("retry-after", wait_time))
+            .insert_header((self.header_names.after.clone(), wait_time));
+        if close_connection {
+            response_builder.force_close();
+        }
+        if let Some(policy) = self.policy_name(negative.quota()) {
+            response_builder.insert_header((self.header_names.policy_name.clone(), policy));
+        }
+        if limit_headers {
+            let interval = negative.quota().replenish_interval();
+            let reset = wait_time.saturating_add(ceil_secs(interval * (burst_size - 1)));
+            let mut headers = HeaderMap::new();
+            insert_limit_headers(
+                &mut headers,
+                &self.header_names,
+                burst_size,
+                0,
+                reset,
+                self.combined_header,
+                self.ietf_window(negative.quota()),
+                self.full_reset_header,
+                self.info_header,
+            );
+            for header in headers {
+                response_builder.insert_header(header);
+            }
+        }
+        let response = if self.json_response {
+            json_response(response_builder, burst_size, wait_time)
+        } else {
+            self.key_extractor
+                .exceed_rate_limit_response(negative, response_builder)
+        };
+        self.map_rejection(response)
+    }
+
+    /// Returns the window of `quota` in seconds if the IETF headers are used,
+    /// see [`use_ietf_headers`](crate::GovernorConfigBuilder::use_ietf_headers).
+    fn ietf_window(&self, quota: Quota) -> Option<u64> {
+        self.ietf_headers
+            .then(|| ceil_secs(quota.replenish_interval() * quota.burst_size().get()))
+    }
+
+    /// Builds the response for a failed key extraction that exceeded the penalty quota.
+    fn penalty_response(&self, negative: &NotUntil<QuantaInstant>) -> HttpResponse {
+        let wait_time = self.retry_after(negative);
+
+        let mut response_builder = self.rejection_builder(negative);
+        response_builder
+            .insert_header(("retry-after", wait_time))
+            .insert_header((self.header_names.after.clone(), wait_time));
+        let response = self
+            .key_extractor
+            .exceed_rate_limit_response(negative, response_builder);
+        self.map_rejection(response)
+    }
+
+    /// Rejects requests with forwarded headers that don't come from a trusted proxy.
+    fn check_forwarded(
+        &self,
+        req: &ServiceRequest,
+    ) -> Result<(), SimpleKeyExtractionError<&'static str>> {
+        if let Some(trusted) = &self.trusted_forwarders {
+            let headers = req.headers();
+            if headers.contains_key(FORWARDED) || headers.contains_key(X_FORWARDED_FOR) {
+                let peer_ip = req.peer_addr().map(|socket| socket.ip());
+                if !peer_ip.is_some_and(|ip| trusted.contains(&ip)) {
+                    return Err(SimpleKeyExtractionError::new(
+                        "Forwarded headers are not accepted from untrusted peers",
+                    )
+                    .set_status_code(StatusCode::FORBIDDEN));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<K: KeyExtractor> GovernorConfig<K, StateInformationMiddleware> {
+    /// Builds the response to a probe request with the current quota of its key, without
+    /// consuming it, see [`probe_header`](crate::GovernorConfigBuilder::probe_header).
+    /// Returns `None` if `req` isn't a probe or its key can't be extracted.
+    fn probe_response(&self, req: &ServiceRequest) -> Option<HttpResponse> {
+        let (name, value) = self.probe_header.as_ref()?;
+        let probe = req
+            .headers()
+            .get_all(name)
+            .any(|v| v.as_bytes().eq_ignore_ascii_case(value.as_bytes()));
+        if !probe {
+            return None;
+        }
+
+        let mut response = HttpResponse::Ok();
+        let whitelisted = self.is_draining() || !self.applies_to(req) || self.is_allowlisted(req);
+        let key = match whitelisted {
+            true => None,
+            false => Some(self.key_extractor.extract(req).ok()?),
+        };
+        let Some(key) = key.filter(|key| !self.is_whitelisted(key) && self.is_sampled(key)) else {
+            response.insert_header((
+                self.header_names.whitelisted.clone(),
+                HeaderValue::from_static("true"),
+            ));
+            return Some(response.finish());
+        };
+
+        let limiter = self.limiter(req, &key);
+        let quota = limiter.quota();
+        let burst_size = quota.burst_size().get();
+        let interval = quota.replenish_interval();
+        // A key without state has the full quota, so its state isn't created by the probe.
+        let checked = (!limiter.is_new(&key)).then(|| limiter.check_key(&key));
+        let (remaining, reset) = match checked {
+            None => (burst_size, 0),
+            Some(Ok(snapshot)) => {
+                // Give the cell back, so the probe doesn't consume the quota.
+                limiter.refund(&key);
+                let remaining = snapshot.remaining_burst_capacity() + 1;
+                (remaining, ceil_secs(interval * (burst_size - remaining)))
+            }
+            Some(Err(negative)) => {
+                let wait_time = self.retry_after(&negative);
+                response
+                    .insert_header(("retry-after", wait_time))
+                    .insert_header((self.header_names.after.clone(), wait_time));
+                let reset = wait_time.saturating_add(ceil_secs(interval * (burst_size - 1)));
+                (0, reset)
+            }
+        };
+        let mut headers = HeaderMap::new();
+        insert_limit_headers(
+            &mut headers,
+            &self.header_names,
+            burst_size,
+            remaining,
+            reset,
+            self.combined_header,
+            self.ietf_window(quota),
+            self.full_reset_header,
+            self.info_header,
+        );
+        for header in headers {
+            response.insert_header(header);
+        }
+        Some(response.finish())
+    }
+}
+
+impl<S, B, K, M> GovernorMiddleware<S, K, M>
 where
     K: KeyExtractor,
+    M: RateLimitingMiddleware<QuantaInstant>,
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
     B: MessageBody,
 {
-    type Response = ServiceResponse<EitherBody<B>>;
-    type Error = S::Error;
-    type Future =
-        Either<ServiceFuture<S, B>, Ready<Result<ServiceResponse<EitherBody<B>>, Self::Error>>>;
+    /// Calls the service. If `key` is set with the rate limiter it was charged against and
+    /// its cost in cells, the cells are refunded if the service responds with one of the
+    /// refund status codes. With [`ChargeTiming::After`], the cells are consumed after the
+    /// service responded unless they would be refunded. The cell of a brute force attempt
+    /// is refunded unless the service responds with one of its status codes.
+    fn forward(
+        &self,
+        req: ServiceRequest,
+        key: Option<Charged<K, M>>,
+    ) -> ForwardServiceFuture<S, B, K, M> {
+        let policy = match &key {
+            Some((_, limiter, _)) if !self.config.policy_names.is_empty() => self
+                .config
+                .policy_name(limiter.quota())
+                .map(|policy| (self.config.header_names.policy_name.clone(), policy)),
+            _ => None,
+        };
+        let span = KeySpan {
+            #[cfg(feature = "tracing")]
+            span: match &key {
+                Some((key, _, _)) if self.config.key_span => Some(tracing::info_span!(
+                    "rate_limit",
+                    extractor = self.config.key_extractor.name(),
+                    key = self.config.key_extractor.key_name(key).as_deref(),
+                )),
+                _ => None,
+            },
+        };
+        #[cfg(feature = "tracing")]
+        let _entered = span.span.clone().map(tracing::Span::entered);
+        let deadline = self
+            .config
+            .refund_window
+            .and_then(|window| Instant::now().checked_add(window));
+        let refund = match key {
+            Some((key, limiter, cells))
+                if !self.config.refund_statuses.is_empty()
+                    || self.config.charge_timing == ChargeTiming::After =>
+            {
+                Some(Refund {
+                    limiter,
+                    key,
+                    cells,
+                    statuses: self.config.refund_statuses.clone(),
+                    charged: Vec::new(),
+                    after: self.config.charge_timing == ChargeTiming::After,
+                    deadline,
+                })
+            }
+            _ => None,
+        };
+        let attempt = req
+            .extensions_mut()
+            .remove::<Attempt>()
+            .zip(self.config.brute_force.as_ref())
+            .map(|(Attempt(key), brute_force)| Refund {
+                limiter: brute_force.limiter.clone(),
+                key,
+                cells: 1,
+                statuses: Vec::new(),
+                charged: brute_force.statuses.clone(),
+                after: false,
+                deadline,
+            });
+        ForwardFut {
+            future: self
+                .service
+                .call(req)
+                .map_ok(ServiceResponse::map_into_left_body as fn(_) -> _),
+            refund,
+            attempt,
+            warning: None,
+            policy,
+            span,
+        }
+    }
 
-    forward_ready!(service);
+    /// Returns the rejection `response`, delayed by the
+    /// [`rejection_delay`](crate::GovernorConfigBuilder::rejection_delay), if any.
+    fn delay_rejection(&self, response: ServiceResponse<EitherBody<B>>) -> RejectionFut<B> {
+        RejectionFut {
+            response: ok(response),
+            delay: self
+                .config
+                .rejection_delay
+                .map(|delay| Box::pin(actix_rt::time::sleep(sample_jitter(delay)))),
+        }
+    }
 
-    fn call(&self, req: ServiceRequest) -> Self::Future {
-        if let Some(configured_methods) = &self.methods {
-            if !configured_methods.contains(req.method()) {
+    /// Counts a forbidden request as blocked if it was rejected with
+    /// `429 Too Many Requests`, for example by the
+    /// [`max_new_keys_per_ip`](crate::GovernorConfigBuilder::max_new_keys_per_ip) limit.
+    #[cfg(feature = "metrics")]
+    fn record_forbidden(&self, error: &SimpleKeyExtractionError<&'static str>) {
+        if actix_web::ResponseError::status_code(error) == StatusCode::TOO_MANY_REQUESTS {
+            self.config.metrics.record_blocked();
+        }
+    }
+
+    /// Forwards a request that exceeded the quota, but is let through because of the
+    /// [`violation_grace`](crate::GovernorConfigBuilder::violation_grace), with a warning header.
+    fn forward_with_warning(&self, req: ServiceRequest) -> ForwardServiceFuture<S, B, K, M> {
+        let mut fut = self.forward(req, None);
+        fut.warning = Some(self.config.header_names.warning.clone());
+        fut
+    }
+}
+
+#[cfg(feature = "log")]
+impl<S, K, M> GovernorMiddleware<S, K, M>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<QuantaInstant>,
+{
+    /// Logs that a request of `key` was allowed, if
+    /// [`log_allowed`](crate::GovernorConfigBuilder::log_allowed) is enabled.
+    fn log_allow(&self, key: &K::Key, remaining: Option<u32>) {
+        if !self.config.log_allowed {
+            return;
+        }
+        let key_name = match self.config.key_extractor.key_name(key) {
+            Some(n) => format!(" [{}]", &n),
+            None => "".to_owned(),
+        };
+        match remaining {
+            Some(remaining) => log::debug!(
+                "Request allowed for {}{}, {} remaining",
+                self.config.key_extractor.name(),
+                key_name,
+                remaining
+            ),
+            None => log::debug!(
+                "Request allowed for {}{}",
+                self.config.key_extractor.name(),
+                key_name
+            ),
+        }
+    }
+}
+
+#[cfg(any(feature = "log", feature = "tracing"))]
+impl<S, K, M> GovernorMiddleware<S, K, M>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<QuantaInstant>,
+{
+    /// Logs that `key` exceeded its rate limit. With the `tracing` feature, this emits
+    /// an event with the extractor, key name and wait time as structured fields instead.
+    fn log_block(&self, key: &K::Key, wait_time: u64) {
+        let level = match &self.config.block_log {
+            Some(block_log) => block_log.record(key),
+            None => BlockLogLevel::Repeated { suppressed: 0 },
+        };
+        let (first, suppressed) = match level {
+            BlockLogLevel::First => (true, 0),
+            BlockLogLevel::Repeated { suppressed } => (false, suppressed),
+            BlockLogLevel::Suppressed => return,
+        };
+        let extractor = self.config.key_extractor.name();
+        let key_name = self.config.key_extractor.key_name(key);
+
+        #[cfg(not(feature = "tracing"))]
+        log::log!(
+            if first {
+                log::Level::Warn
+            } else {
+                log::Level::Info
+            },
+            "Rate limit exceeded for {}{}, quota reset in {}s{}",
+            extractor,
+            key_name
+                .as_ref()
+                .map_or_else(String::new, |n| format!(" [{}]", n)),
+            wait_time,
+            match suppressed {
+                0 => String::new(),
+                suppressed => format!(" ({} similar messages suppressed)", suppressed),
+            }
+        );
+
+        #[cfg(feature = "tracing")]
+        {
+            let key_name = key_name.as_deref();
+            match first {
+                true => tracing::warn!(
+                    extractor,
+                    key_name,
+                    wait_seconds = wait_time,
+                    suppressed,
+                    "Rate limit exceeded"
+                ),
+                false => tracing::info!(
+                    extractor,
+                    key_name,
+                    wait_seconds = wait_time,
+                    suppressed,
+                    "Rate limit exceeded"
+                ),
+            }
+        }
+    }
+}
+
+/// Buffers the body, resolves the key or fetches its quota before the request is rate limited.
+type PrepareFuture<B> = LocalBoxFuture<'static, Result<ServiceResponse<EitherBody<B>>, Error>>;
+
+/// Buffers the request body for [`KeyExtractor::body_limit`] and stores it
+/// as [`Bytes`](actix_web::web::Bytes) in the request extensions.
+async fn buffer_body(req: &mut ServiceRequest, limit: usize) -> Result<(), Error> {
+    let mut payload = req.take_payload();
+    let mut body = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        if body.len() + chunk.len() > limit {
+            return Err(ErrorPayloadTooLarge("Request body is too large"));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let body = body.freeze();
+    req.extensions_mut().insert(body.clone());
+
+    // Put the body back, so the service can read it.
+    let (_, mut buffered) = actix_http::h1::Payload::create(true);
+    buffered.unread_data(body);
+    req.set_payload(buffered.into());
+    Ok(())
+}
+
+type NoOpFuture<S, B, K> = Either<ForwardServiceFuture<S, B, K, NoOpMiddleware>, RejectionFut<B>>;
+
+impl<S, B, K> GovernorMiddleware<S, K, NoOpMiddleware>
+where
+    K: KeyExtractor + 'static,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    fn limit(&self, mut req: ServiceRequest) -> NoOpFuture<S, B, K> {
+        let (decision, charge) = self.config.decide_normalized(&mut req);
+        match decision {
+            // The request is not covered or whitelisted, we're ignoring this one.
+            Decision::Whitelisted => {
                 req.extensions_mut()
                     .insert(GovernorResult::<K::KeyExtractionError>::whitelist());
 
-                // The request method is not configured, we're ignoring this one.
-                let fut = self.service.call(req);
-                return Either::Left(fut.map_ok(|resp| resp.map_into_left_body()));
+                let fut = self.forward(req, None);
+                Either::Left(fut)
             }
-        }
 
-        // Use the provided key extractor to extract the rate limiting key from the request.
-        match self.key_extractor.extract(&req) {
-            // Extraction worked, let's check if rate limiting is needed.
-            Ok(key) => {
-                // Check if the rate limiting key is whitelisted.
-                if self.key_extractor.whitelisted_keys().contains(&key) {
-                    req.extensions_mut()
-                        .insert(GovernorResult::<K::KeyExtractionError>::whitelist());
+            Decision::Allowed { key, outcome: () } => {
+                #[cfg(feature = "log")]
+                self.log_allow(&key, None);
+                #[cfg(feature = "metrics")]
+                self.config.metrics.record_allowed();
 
-                    let fut = self.service.call(req);
-                    Either::Left(fut.map_ok(|resp| resp.map_into_left_body()))
-                } else {
-                    match self.limiter.check_key(&key) {
-                        Ok(_) => {
-                            req.extensions_mut()
-                                .insert(GovernorResult::<K::KeyExtractionError>::ok());
+                req.extensions_mut()
+                    .insert(GovernorResult::<K::KeyExtractionError>::ok());
 
-                            let fut = self.service.call(req);
-                            Either::Left(fut.map_ok(|resp| resp.map_into_left_body()))
-                        }
+                let charged = charge.limiter.map(|limiter| (key, limiter, charge.cost));
+                Either::Left(self.forward(req, charged))
+            }
 
-                        Err(negative) => {
-                            let wait_time = negative
-                                .wait_time_from(DefaultClock::default().now())
-                                .as_secs();
-
-                            #[cfg(feature = "log")]
-                            {
-                                let key_name = match self.key_extractor.key_name(&key) {
-                                    Some(n) => format!(" [{}]", &n),
-                                    None => "".to_owned(),
-                                };
-                                log::info!(
-                                    "Rate limit exceeded for {}{}, quota reset in {}s",
-                                    self.key_extractor.name(),
-                                    key_name,
-                                    &wait_time
-                                );
-                            }
-
-                            req.extensions_mut()
-                                .insert(GovernorResult::<K::KeyExtractionError>::wait(wait_time));
-
-                            if self.permissive {
-                                let fut = self.service.call(req);
-                                return Either::Left(fut.map_ok(|resp| resp.map_into_left_body()));
-                            }
-
-                            let mut response_builder = actix_web::HttpResponse::TooManyRequests();
-                            response_builder.insert_header(("retry-after", wait_time));
-                            response_builder.insert_header(("x-ratelimit-after", wait_time));
-                            let response = self
-                                .key_extractor
-                                .exceed_rate_limit_response(&negative, response_builder);
-
-                            let response = req.into_response(response);
-                            Either::Right(ok(response.map_into_right_body()))
-                        }
-                    }
+            Decision::RateLimited {
+                key,
+                negative,
+                wait_time,
+                close_connection,
+                grace,
+            } => {
+                #[cfg(any(feature = "log", feature = "tracing"))]
+                self.log_block(&key, wait_time);
+
+                req.extensions_mut()
+                    .insert(GovernorResult::<K::KeyExtractionError>::wait(wait_time));
+
+                if self.config.permissive || self.config.key_extractor.is_soft_limited(&key) {
+                    let fut = self.forward(req, None);
+                    return Either::Left(fut);
+                }
+                if grace {
+                    return Either::Left(self.forward_with_warning(req));
+                }
+                if let Some(on_limited) = &self.config.on_limited {
+                    (on_limited.0)(&req, &negative);
                 }
+                #[cfg(feature = "metrics")]
+                self.config.metrics.record_blocked();
+
+                let response = self.config.rejection_response_with(
+                    &negative,
+                    wait_time,
+                    false,
+                    close_connection,
+                );
+                let response = req.into_response(response);
+                Either::Right(self.delay_rejection(response.map_into_right_body()))
+            }
+
+            Decision::Forbidden(e) => {
+                #[cfg(feature = "metrics")]
+                self.record_forbidden(&e);
+                Either::Right(future::err(e.into()).into())
             }
 
             // Extraction failed, stop right now.
-            Err(e) => {
-                if self.permissive {
+            Decision::ExtractionFailed { error, penalty } => {
+                if self.config.permissive {
                     req.extensions_mut()
-                        .insert(GovernorResult::<K::KeyExtractionError>::err(e));
+                        .insert(GovernorResult::<K::KeyExtractionError>::err(error));
 
-                    let fut = self.service.call(req);
-                    Either::Left(fut.map_ok(|resp| resp.map_into_left_body()))
+                    let fut = self.forward(req, None);
+                    Either::Left(fut)
+                } else if let Some(negative) = penalty {
+                    #[cfg(feature = "metrics")]
+                    self.config.metrics.record_blocked();
+                    let response = req.into_response(self.config.penalty_response(&negative));
+                    Either::Right(ok(response.map_into_right_body()).into())
                 } else {
-                    Either::Right(future::err(e.into()))
+                    Either::Right(future::err(error.into()).into())
                 }
             }
         }
     }
 }
 
+impl<S, B, K> Service<ServiceRequest> for GovernorMiddleware<S, K, NoOpMiddleware>
+where
+    K: KeyExtractor + 'static,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = S::Error;
+    type Future = Either<NoOpFuture<S, B, K>, PrepareFuture<B>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let body_limit = self.config.key_extractor.body_limit();
+        let resolve = self.config.key_extractor.resolve(req.request());
+        if body_limit.is_none() && resolve.is_none() && self.config.quota_provider.is_none() {
+            return Either::Left(self.limit(req));
+        }
+        let middleware = self.clone();
+        Either::Right(Box::pin(async move {
+            let mut req = req;
+            if let Some(limit) = body_limit {
+                buffer_body(&mut req, limit).await?;
+            }
+            if let Some(resolve) = resolve {
+                resolve.await;
+            }
+            if let Some(fetch) = middleware.config.prefetch_quota(&mut req) {
+                fetch.await;
+            }
+            middleware.limit(req).await
+        }))
+    }
+}
+
+/// Rounds `duration` up to whole seconds.
+fn ceil_secs(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos().div_ceil(1_000_000_000)).unwrap_or(u64::MAX)
+}
+
+/// The names of the `x-ratelimit-*` headers with the
+/// [`header_prefix`](crate::GovernorConfigBuilder::header_prefix) applied.
+#[derive(Debug, Clone)]
+pub(crate) struct HeaderNames {
+    after: HeaderName,
+    limit: HeaderName,
+    remaining: HeaderName,
+    reset: HeaderName,
+    full_reset: HeaderName,
+    info: HeaderName,
+    /// The combined header, named after the prefix without its trailing `-`.
+    combined: HeaderName,
+    whitelisted: HeaderName,
+    warning: HeaderName,
+    /// The header with the name of the policy that applied to a request,
+    /// see [`policy_name`](crate::GovernorConfigBuilder::policy_name).
+    policy_name: HeaderName,
+    limit_key: HeaderName,
+    remaining_key: HeaderName,
+    limit_global: HeaderName,
+    remaining_global: HeaderName,
+}
+
+impl HeaderNames {
+    /// Returns the header names with `prefix`, or `None` if one of them isn't a legal header name.
+    pub(crate) fn new(prefix: &str) -> Option<Self> {
+        let name = |suffix: &str| HeaderName::try_from(format!("{}{}", prefix, suffix)).ok();
+        Some(Self {
+            after: name("after")?,
+            limit: name("limit")?,
+            remaining: name("remaining")?,
+            reset: name("reset")?,
+            full_reset: name("full-reset")?,
+            info: name("info")?,
+            combined: HeaderName::try_from(prefix.strip_suffix('-').unwrap_or(prefix)).ok()?,
+            whitelisted: name("whitelisted")?,
+            warning: name("warning")?,
+            policy_name: name("policy-name")?,
+            limit_key: name("limit-key")?,
+            remaining_key: name("remaining-key")?,
+            limit_global: name("limit-global")?,
+            remaining_global: name("remaining-global")?,
+        })
+    }
+}
+
+/// Inserts the `x-ratelimit-limit`, `x-ratelimit-remaining` and `x-ratelimit-reset` headers
+/// and, if `combined` is set, the combined `x-ratelimit` header, replacing the others if
+/// `combined` is `true`.
+///
+/// If `ietf_window` is set, only the `RateLimit` and `RateLimit-Policy` headers
+/// are inserted instead, with the window in seconds. Otherwise, if `full_reset`
+/// is set, the `x-ratelimit-full-reset` header is inserted as well, and if `info`
+/// is set, the `x-ratelimit-info` header with the state as a JSON object.
+#[allow(clippy::too_many_arguments)]
+fn insert_limit_headers(
+    headers: &mut HeaderMap,
+    names: &HeaderNames,
+    burst_size: u32,
+    remaining: u32,
+    reset: u64,
+    combined: Option<bool>,
+    ietf_window: Option<u64>,
+    full_reset: bool,
+    info: bool,
+) {
+    if let Some(window) = ietf_window {
+        let value = format!(
+            "limit={}, remaining={}, reset={}",
+            burst_size, remaining, reset
+        );
+        headers.insert(
+            HeaderName::from_static("ratelimit"),
+            HeaderValue::try_from(value).unwrap(),
+        );
+        let value = format!("{};w={}", burst_size, window);
+        headers.insert(
+            HeaderName::from_static("ratelimit-policy"),
+            HeaderValue::try_from(value).unwrap(),
+        );
+        return;
+    }
+    if full_reset {
+        headers.insert(names.full_reset.clone(), reset.into());
+    }
+    if info {
+        let value = format!(
+            r#"{{"limit":{},"remaining":{},"reset":{}}}"#,
+            burst_size, remaining, reset
+        );
+        headers.insert(names.info.clone(), HeaderValue::try_from(value).unwrap());
+    }
+    if combined != Some(true) {
+        headers.insert(names.limit.clone(), burst_size.into());
+        headers.insert(names.remaining.clone(), remaining.into());
+        headers.insert(names.reset.clone(), reset.into());
+    }
+    if combined.is_some() {
+        let value = format!(
+            "limit={}, remaining={}, reset={}",
+            burst_size, remaining, reset
+        );
+        headers.insert(
+            names.combined.clone(),
+            HeaderValue::try_from(value).unwrap(),
+        );
+    }
+}
+
 pub struct RateLimitHeaderFut<F>
 where
     F: Future,
 {
     future: F,
+    names: Arc<HeaderNames>,
     burst_size: u32,
     remaining_burst_capacity: u32,
+    reset: u64,
+    combined: Option<bool>,
+    ietf_window: Option<u64>,
+    full_reset: bool,
+    info: bool,
+    /// The limit and remaining quota of the key and the global quota,
+    /// see [`layer_headers`](crate::GovernorConfigBuilder::layer_headers).
+    layers: Option<((u32, u32), (u32, u32))>,
 }
 
 impl<F, B> Future for RateLimitHeaderFut<F>
@@ -144,15 +1016,28 @@ where
             Poll::Pending => Poll::Pending,
             Poll::Ready(response) => Poll::Ready(match response {
                 Ok(mut response) => {
-                    let headers = response.headers_mut();
-                    headers.insert(
-                        HeaderName::from_static("x-ratelimit-limit"),
-                        self.burst_size.into(),
-                    );
-                    headers.insert(
-                        HeaderName::from_static("x-ratelimit-remaining"),
-                        self.remaining_burst_capacity.into(),
+                    insert_limit_headers(
+                        response.headers_mut(),
+                        &self.names,
+                        self.burst_size,
+                        self.remaining_burst_capacity,
+                        self.reset,
+                        self.combined,
+                        self.ietf_window,
+                        self.full_reset,
+                        self.info,
                     );
+                    if let (Some((key, global)), None) = (self.layers, self.ietf_window) {
+                        let names = &self.names;
+                        let headers = response.headers_mut();
+                        insert_layer_headers(headers, &names.limit_key, &names.remaining_key, key);
+                        insert_layer_headers(
+                            headers,
+                            &names.limit_global,
+                            &names.remaining_global,
+                            global,
+                        );
+                    }
                     Ok(response)
                 }
                 Err(err) => Err(err),
@@ -161,11 +1046,163 @@ where
     }
 }
 
+/// Inserts the `x-ratelimit-limit-{layer}` and `x-ratelimit-remaining-{layer}` headers
+/// with the `(limit, remaining)` of a layer.
+fn insert_layer_headers(
+    headers: &mut HeaderMap,
+    limit_name: &HeaderName,
+    remaining_name: &HeaderName,
+    (limit, remaining): (u32, u32),
+) {
+    headers.insert(limit_name.clone(), limit.into());
+    headers.insert(remaining_name.clone(), remaining.into());
+}
+
+struct Refund<Key: Hash + Eq + Clone, M: RateLimitingMiddleware<QuantaInstant>> {
+    limiter: SharedRateLimiter<Key, M>,
+    key: Key,
+    /// The number of cells the request costs.
+    cells: u32,
+    statuses: Vec<StatusCode>,
+    /// If not empty, all other statuses are refunded.
+    charged: Vec<StatusCode>,
+    /// Whether the cell wasn't consumed yet, see [`ChargeTiming::After`].
+    after: bool,
+    /// After this instant, the cell is no longer refunded.
+    deadline: Option<Instant>,
+}
+
+impl<Key: Hash + Eq + Clone, M: RateLimitingMiddleware<QuantaInstant>> Refund<Key, M> {
+    fn applies(&self, status: StatusCode) -> bool {
+        self.statuses.contains(&status)
+            || !(self.charged.is_empty() || self.charged.contains(&status))
+    }
+
+    /// Refunds the cells of the key or, with [`ChargeTiming::After`], consumes them,
+    /// depending on the `status` of the response.
+    fn settle(self, status: StatusCode) {
+        match (self.applies(status), self.after) {
+            (true, false)
+                if self
+                    .deadline
+                    .is_none_or(|deadline| Instant::now() <= deadline) =>
+            {
+                self.limiter.refund_cells(&self.key, self.cells)
+            }
+            (false, true) => {
+                let _ = self.limiter.check_key_cost(&self.key, self.cells);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The span of [`key_span`](crate::GovernorConfigBuilder::key_span),
+/// entered while the service handles the request.
+struct KeySpan {
+    #[cfg(feature = "tracing")]
+    span: Option<tracing::Span>,
+}
+
+pin_project! {
+    pub struct ForwardFut<F, Key, M>
+    where
+        F: Future,
+        Key: Hash,
+        Key: Eq,
+        Key: Clone,
+        M: RateLimitingMiddleware<QuantaInstant>,
+    {
+        #[pin]
+        future: F,
+        refund: Option<Refund<Key, M>>,
+        attempt: Option<Refund<u64, NoOpMiddleware>>,
+        warning: Option<HeaderName>,
+        policy: Option<(HeaderName, HeaderValue)>,
+        span: KeySpan,
+    }
+}
+
+impl<F, B, Key, M> Future for ForwardFut<F, Key, M>
+where
+    F: Future<Output = Result<ServiceResponse<B>, Error>>,
+    Key: Hash + Eq + Clone,
+    M: RateLimitingMiddleware<QuantaInstant>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        #[cfg(feature = "tracing")]
+        let _entered = this.span.span.as_ref().map(tracing::Span::enter);
+        match this.future.poll(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(mut response) => {
+                if let Ok(response) = &response {
+                    if let Some(refund) = this.refund.take() {
+                        refund.settle(response.status());
+                    }
+                    if let Some(attempt) = this.attempt.take() {
+                        attempt.settle(response.status());
+                    }
+                }
+                if let Ok(response) = &mut response {
+                    if let Some(warning) = this.warning.take() {
+                        response
+                            .headers_mut()
+                            .insert(warning, HeaderValue::from_static("quota exceeded"));
+                    }
+                    if let Some((name, policy)) = this.policy.take() {
+                        response.headers_mut().insert(name, policy);
+                    }
+                }
+                Poll::Ready(response)
+            }
+        }
+    }
+}
+
+/// A response of the middleware itself, which is sent after the `delay`, if any.
+pub struct RejectionFut<B> {
+    response: Ready<Result<ServiceResponse<EitherBody<B>>, Error>>,
+    delay: Option<Pin<Box<actix_rt::time::Sleep>>>,
+}
+
+impl<B> From<Ready<Result<ServiceResponse<EitherBody<B>>, Error>>> for RejectionFut<B> {
+    fn from(response: Ready<Result<ServiceResponse<EitherBody<B>>, Error>>) -> Self {
+        Self {
+            response,
+            delay: None,
+        }
+    }
+}
+
+impl<B> Future for RejectionFut<B> {
+    type Output = Result<ServiceResponse<EitherBody<B>>, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(delay) = &mut self.delay {
+            if delay.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.delay = None;
+        }
+        Pin::new(&mut self.response).poll(cx)
+    }
+}
+
 pub struct WhitelistedHeaderFut<F>
 where
     F: Future,
 {
     future: F,
+    names: Arc<HeaderNames>,
+    /// The limit and remaining quota to report, if any.
+    limit: Option<u32>,
+    combined: Option<bool>,
+    ietf_window: Option<u64>,
+    full_reset: bool,
+    info: bool,
 }
 
 impl<F, B> Future for WhitelistedHeaderFut<F>
@@ -182,9 +1219,22 @@ where
                 Ok(mut response) => {
                     let headers = response.headers_mut();
                     headers.insert(
-                        HeaderName::from_static("x-ratelimit-whitelisted"),
+                        self.names.whitelisted.clone(),
                         HeaderValue::from_static("true"),
                     );
+                    if let Some(limit) = self.limit {
+                        insert_limit_headers(
+                            headers,
+                            &self.names,
+                            limit,
+                            limit,
+                            0,
+                            self.combined,
+                            self.ietf_window,
+                            self.full_reset,
+                            self.info,
+                        );
+                    }
                     Ok(response)
                 }
                 Err(err) => Err(err),
@@ -194,138 +1244,207 @@ where
 }
 
 /// Implementation using rate limit headers
-impl<S, B, K> Service<ServiceRequest> for GovernorMiddleware<S, K, StateInformationMiddleware>
+type StateInformationFuture<S, B, K> = Either<
+    Either<
+        Either<
+            RateLimitHeaderFut<ForwardServiceFuture<S, B, K, StateInformationMiddleware>>,
+            WhitelistedHeaderFut<ForwardServiceFuture<S, B, K, StateInformationMiddleware>>,
+        >,
+        RejectionFut<B>,
+    >,
+    ForwardServiceFuture<S, B, K, StateInformationMiddleware>,
+>;
+
+impl<S, B, K> GovernorMiddleware<S, K, StateInformationMiddleware>
 where
-    K: KeyExtractor,
+    K: KeyExtractor + 'static,
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
     B: MessageBody,
     S::Future: Unpin,
 {
-    type Response = ServiceResponse<EitherBody<B>>;
-    type Error = S::Error;
-    type Future = Either<
-        Either<
-            Either<
-                RateLimitHeaderFut<ServiceFuture<S, B>>,
-                WhitelistedHeaderFut<ServiceFuture<S, B>>,
-            >,
-            Ready<Result<ServiceResponse<EitherBody<B>>, Error>>,
-        >,
-        ServiceFuture<S, B>,
-    >;
-
-    forward_ready!(service);
-
-    fn call(&self, req: ServiceRequest) -> Self::Future {
-        if let Some(configured_methods) = &self.methods {
-            if !configured_methods.contains(req.method()) {
-                // The request method is not configured, we're ignoring this one.
+    fn limit(&self, mut req: ServiceRequest) -> StateInformationFuture<S, B, K> {
+        if let Some(response) = self.config.probe_response(&req) {
+            let response = req.into_response(response).map_into_right_body();
+            return Either::Left(Either::Right(RejectionFut::from(ok(response))));
+        }
+        let (decision, charge) = self.config.decide_normalized(&mut req);
+        match decision {
+            // The request is not covered or whitelisted, we're ignoring this one.
+            Decision::Whitelisted => {
                 req.extensions_mut()
                     .insert(GovernorResult::<K::KeyExtractionError>::whitelist());
 
-                let fut = self.service.call(req);
-                return Either::Left(Either::Left(Either::Right(WhitelistedHeaderFut {
-                    future: fut.map_ok(|resp| resp.map_into_left_body()),
-                })));
+                let limit = if self.config.quota_whitelist_headers {
+                    Some(self.config.quota.burst_size().get())
+                } else if self.config.unlimited_whitelist_headers {
+                    Some(u32::MAX)
+                } else {
+                    None
+                };
+                let fut = self.forward(req, None);
+                Either::Left(Either::Left(Either::Right(WhitelistedHeaderFut {
+                    future: fut,
+                    names: self.config.header_names.clone(),
+                    limit,
+                    combined: self.config.combined_header,
+                    ietf_window: self.config.ietf_window(self.config.quota),
+                    full_reset: self.config.full_reset_header,
+                    info: self.config.info_header,
+                })))
             }
-        }
 
-        // Use the provided key extractor to extract the rate limiting key from the request.
-        match self.key_extractor.extract(&req) {
-            // Extraction worked, let's check if rate limiting is needed.
-            Ok(key) => {
-                // Check if the key is whitelisted.
-                if self.key_extractor.whitelisted_keys().contains(&key) {
-                    req.extensions_mut()
-                        .insert(GovernorResult::<K::KeyExtractionError>::whitelist());
+            Decision::Allowed {
+                key,
+                outcome: snapshot,
+            } => {
+                let burst_size = snapshot.quota().burst_size().get();
+                let remaining = snapshot.remaining_burst_capacity();
+                let reset =
+                    ceil_secs(snapshot.quota().replenish_interval() * (burst_size - remaining));
 
-                    let fut = self.service.call(req);
-                    Either::Left(Either::Left(Either::Right(WhitelistedHeaderFut {
-                        future: fut.map_ok(|resp| resp.map_into_left_body()),
-                    })))
+                #[cfg(feature = "log")]
+                self.log_allow(&key, Some(remaining));
+                #[cfg(feature = "metrics")]
+                self.config.metrics.record_allowed();
+
+                req.extensions_mut()
+                    .insert(GovernorResult::<K::KeyExtractionError>::ok_with_info(
+                        burst_size, remaining,
+                    ));
+                req.extensions_mut().insert(RemainingQuota(Some(remaining)));
+
+                let charged = charge.limiter.map(|limiter| (key, limiter, charge.cost));
+                let fut = self.forward(req, charged);
+                if self.config.permissive {
+                    Either::Right(fut)
                 } else {
-                    match self.limiter.check_key(&key) {
-                        Ok(snapshot) => {
-                            let burst_size = snapshot.quota().burst_size().get();
-                            let remaining = snapshot.remaining_burst_capacity();
-                            req.extensions_mut().insert(
-                                GovernorResult::<K::KeyExtractionError>::ok_with_info(
-                                    burst_size, remaining,
-                                ),
-                            );
-
-                            let fut = self.service.call(req);
-                            if self.permissive {
-                                Either::Right(fut.map_ok(|resp| resp.map_into_left_body()))
-                            } else {
-                                Either::Left(Either::Left(Either::Left(RateLimitHeaderFut {
-                                    future: fut.map_ok(|resp| resp.map_into_left_body()),
-                                    burst_size,
-                                    remaining_burst_capacity: remaining,
-                                })))
-                            }
-                        }
+                    Either::Left(Either::Left(Either::Left(RateLimitHeaderFut {
+                        future: fut,
+                        names: self.config.header_names.clone(),
+                        burst_size,
+                        remaining_burst_capacity: remaining,
+                        reset,
+                        combined: self.config.combined_header,
+                        ietf_window: self.config.ietf_window(snapshot.quota()),
+                        full_reset: self.config.full_reset_header,
+                        info: self.config.info_header,
+                        layers: charge
+                            .global
+                            .filter(|_| self.config.layer_headers)
+                            .map(|global| {
+                                (
+                                    (burst_size, remaining),
+                                    (
+                                        global.quota().burst_size().get(),
+                                        global.remaining_burst_capacity(),
+                                    ),
+                                )
+                            }),
+                    })))
+                }
+            }
 
-                        Err(negative) => {
-                            let wait_time = negative
-                                .wait_time_from(DefaultClock::default().now())
-                                .as_secs();
-                            let burst_size = negative.quota().burst_size().get();
-
-                            #[cfg(feature = "log")]
-                            {
-                                let key_name = match self.key_extractor.key_name(&key) {
-                                    Some(n) => format!(" [{}]", &n),
-                                    None => "".to_owned(),
-                                };
-                                log::info!(
-                                    "Rate limit exceeded for {}{}, quota reset in {}s",
-                                    self.key_extractor.name(),
-                                    key_name,
-                                    &wait_time
-                                );
-                            }
-
-                            req.extensions_mut().insert(
-                                GovernorResult::<K::KeyExtractionError>::wait_with_info(
-                                    wait_time, burst_size,
-                                ),
-                            );
-
-                            if self.permissive {
-                                let fut = self.service.call(req);
-                                return Either::Right(fut.map_ok(|resp| resp.map_into_left_body()));
-                            }
-
-                            let mut response_builder = actix_web::HttpResponse::TooManyRequests();
-                            response_builder
-                                .insert_header(("retry-after", wait_time))
-                                .insert_header(("x-ratelimit-after", wait_time))
-                                .insert_header(("x-ratelimit-limit", burst_size))
-                                .insert_header(("x-ratelimit-remaining", 0));
-                            let response = self
-                                .key_extractor
-                                .exceed_rate_limit_response(&negative, response_builder);
-
-                            let response = req.into_response(response);
-                            Either::Left(Either::Right(ok(response.map_into_right_body())))
-                        }
-                    }
+            Decision::RateLimited {
+                key,
+                negative,
+                wait_time,
+                close_connection,
+                grace,
+            } => {
+                let burst_size = negative.quota().burst_size().get();
+
+                #[cfg(any(feature = "log", feature = "tracing"))]
+                self.log_block(&key, wait_time);
+
+                req.extensions_mut().insert(
+                    GovernorResult::<K::KeyExtractionError>::wait_with_info(wait_time, burst_size),
+                );
+                req.extensions_mut().insert(RemainingQuota(Some(0)));
+
+                if self.config.permissive || self.config.key_extractor.is_soft_limited(&key) {
+                    let fut = self.forward(req, None);
+                    return Either::Right(fut);
                 }
+                if grace {
+                    return Either::Right(self.forward_with_warning(req));
+                }
+                if let Some(on_limited) = &self.config.on_limited {
+                    (on_limited.0)(&req, &negative);
+                }
+                #[cfg(feature = "metrics")]
+                self.config.metrics.record_blocked();
+
+                let response = self.config.rejection_response_with(
+                    &negative,
+                    wait_time,
+                    true,
+                    close_connection,
+                );
+                let response = req.into_response(response);
+                Either::Left(Either::Right(
+                    self.delay_rejection(response.map_into_right_body()),
+                ))
+            }
+
+            Decision::Forbidden(e) => {
+                #[cfg(feature = "metrics")]
+                self.record_forbidden(&e);
+                Either::Left(Either::Right(future::err(e.into()).into()))
             }
 
             // Extraction failed, stop right now.
-            Err(e) => {
-                if self.permissive {
+            Decision::ExtractionFailed { error, penalty } => {
+                if self.config.permissive {
                     req.extensions_mut()
-                        .insert(GovernorResult::<K::KeyExtractionError>::err(e));
+                        .insert(GovernorResult::<K::KeyExtractionError>::err(error));
 
-                    let fut = self.service.call(req);
-                    Either::Right(fut.map_ok(|resp| resp.map_into_left_body()))
+                    let fut = self.forward(req, None);
+                    Either::Right(fut)
+                } else if let Some(negative) = penalty {
+                    #[cfg(feature = "metrics")]
+                    self.config.metrics.record_blocked();
+                    let response = req.into_response(self.config.penalty_response(&negative));
+                    Either::Left(Either::Right(ok(response.map_into_right_body()).into()))
                 } else {
-                    Either::Left(Either::Right(future::err(e.into())))
+                    Either::Left(Either::Right(future::err(error.into()).into()))
                 }
             }
         }
     }
 }
+
+impl<S, B, K> Service<ServiceRequest> for GovernorMiddleware<S, K, StateInformationMiddleware>
+where
+    K: KeyExtractor + 'static,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+    S::Future: Unpin,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = S::Error;
+    type Future = Either<StateInformationFuture<S, B, K>, PrepareFuture<B>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let body_limit = self.config.key_extractor.body_limit();
+        let resolve = self.config.key_extractor.resolve(req.request());
+        if body_limit.is_none() && resolve.is_none() && self.config.quota_provider.is_none() {
+            return Either::Left(self.limit(req));
+        }
+        let middleware = self.clone();
+        Either::Right(Box::pin(async move {
+            let mut req = req;
+            if let Some(limit) = body_limit {
+                buffer_body(&mut req, limit).await?;
+            }
+            if let Some(resolve) = resolve {
+                resolve.await;
+            }
+            if let Some(fetch) = middleware.config.prefetch_quota(&mut req) {
+                fetch.await;
+            }
+            middleware.limit(req).await
+        }))
+    }
+}
@@ -0,0 +1,213 @@
+//! Dry runs of a [`GovernorConfig`] against recorded traffic or hand-picked instants.
+
+use crate::{GovernorConfig, KeyExtractor};
+
+use governor::clock::{Clock, FakeRelativeClock, QuantaInstant};
+use governor::middleware::{RateLimitingMiddleware, StateInformationMiddleware};
+use governor::state::keyed::HashMapStateStore;
+use governor::RateLimiter;
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::num::NonZeroU32;
+use std::time::{Duration, Instant};
+
+type SimulatedLimiter<Key> =
+    RateLimiter<Key, HashMapStateStore<Key>, FakeRelativeClock, StateInformationMiddleware>;
+
+/// The number of allowed and blocked requests of a key in a [`SimulationReport`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SimulationTally {
+    /// The number of requests that would have been allowed, including whitelisted ones.
+    pub allowed: usize,
+    /// The number of requests that would have been rejected with `429 Too Many Requests`.
+    pub blocked: usize,
+}
+
+/// The result of [`GovernorConfig::simulate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulationReport<Key: Hash + Eq> {
+    /// The tally of each key in the trace.
+    pub keys: HashMap<Key, SimulationTally>,
+}
+
+impl<Key: Hash + Eq> SimulationReport<Key> {
+    /// Returns the tally of all keys.
+    #[must_use]
+    pub fn total(&self) -> SimulationTally {
+        self.keys
+            .values()
+            .fold(SimulationTally::default(), |total, tally| SimulationTally {
+                allowed: total.allowed + tally.allowed,
+                blocked: total.blocked + tally.blocked,
+            })
+    }
+}
+
+impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> GovernorConfig<K, M> {
+    /// Replays a `trace` of requests against fresh rate limiters with a simulated clock and
+    /// reports how many requests of each key would have been allowed or blocked.
+    ///
+    /// This is an aid for sizing quotas with recorded traffic, the state of the middleware
+    /// is not affected. The events are expected in chronological order, events that are
+    /// older than their predecessor are replayed at the time of the predecessor.
+    /// Per-key quotas of the key extractor and the
+    /// [`quota_extractor`](crate::GovernorConfigBuilder::quota_extractor), whitelisted keys
+    /// and the [`sample_rate`](crate::GovernorConfigBuilder::sample_rate) are taken into
+    /// account. The [`global_cap`](crate::GovernorConfigBuilder::global_cap), the
+    /// [`adaptive`](crate::GovernorConfigBuilder::adaptive) mode and request-dependent options
+    /// like burst multipliers are not.
+    ///
+    /// ```rust
+    /// use actix_governor::GovernorConfigBuilder;
+    /// use std::net::IpAddr;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .seconds_per_request(1)
+    ///     .burst_size(2)
+    ///     .finish()
+    ///     .unwrap();
+    ///
+    /// let client: IpAddr = "10.0.0.1".parse().unwrap();
+    /// let start = Instant::now();
+    /// let trace: Vec<_> = (0..4).map(|i| (client, start + Duration::from_millis(i * 100))).collect();
+    ///
+    /// let report = config.simulate(&trace);
+    /// assert_eq!(report.keys[&client].allowed, 2);
+    /// assert_eq!(report.keys[&client].blocked, 2);
+    /// ```
+    #[must_use]
+    pub fn simulate(&self, trace: &[(K::Key, Instant)]) -> SimulationReport<K::Key> {
+        let mut tester = self.tester();
+        let mut keys: HashMap<K::Key, SimulationTally> = HashMap::new();
+
+        for (key, time) in trace {
+            let tally = keys.entry(key.clone()).or_default();
+            match tester.test_decide(key, *time) {
+                TestDecision::Allowed { .. } | TestDecision::Whitelisted => tally.allowed += 1,
+                TestDecision::Blocked { .. } => tally.blocked += 1,
+            }
+        }
+
+        SimulationReport { keys }
+    }
+
+    /// Returns a [`DecisionTester`] for unit-testing quotas and key logic
+    /// without an actix app.
+    #[must_use]
+    pub fn tester(&self) -> DecisionTester<'_, K, M> {
+        DecisionTester {
+            config: self,
+            clock: FakeRelativeClock::default(),
+            previous: None,
+            limiters: HashMap::new(),
+        }
+    }
+}
+
+/// The result of [`DecisionTester::test_decide`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestDecision {
+    /// The request would have been allowed.
+    Allowed {
+        /// The number of requests that would still be allowed right after this one.
+        remaining: u32,
+    },
+    /// The request would have been rejected with `429 Too Many Requests`.
+    Blocked {
+        /// The time until the next request of the key would be allowed.
+        wait_time: Duration,
+    },
+    /// The key is whitelisted.
+    Whitelisted,
+}
+
+/// Decides requests of a [`GovernorConfig`] at controlled instants,
+/// created with [`GovernorConfig::tester`].
+///
+/// The tester keeps its own rate limiters on a simulated clock, so the state of the
+/// middleware is not affected and no time has to pass. It takes the same options into
+/// account as [`GovernorConfig::simulate`], so the
+/// [`global_cap`](crate::GovernorConfigBuilder::global_cap), the
+/// [`adaptive`](crate::GovernorConfigBuilder::adaptive) mode and request-dependent options
+/// like burst multipliers are ignored.
+///
+/// ```rust
+/// use actix_governor::{GovernorConfigBuilder, TestDecision};
+/// use std::net::IpAddr;
+/// use std::time::{Duration, Instant};
+///
+/// let config = GovernorConfigBuilder::default()
+///     .seconds_per_request(1)
+///     .burst_size(2)
+///     .finish()
+///     .unwrap();
+///
+/// let client: IpAddr = "10.0.0.1".parse().unwrap();
+/// let start = Instant::now();
+/// let mut tester = config.tester();
+///
+/// assert_eq!(tester.test_decide(&client, start), TestDecision::Allowed { remaining: 1 });
+/// assert_eq!(tester.test_decide(&client, start), TestDecision::Allowed { remaining: 0 });
+/// assert_eq!(
+///     tester.test_decide(&client, start + Duration::from_millis(400)),
+///     TestDecision::Blocked { wait_time: Duration::from_millis(600) }
+/// );
+/// // One request has been replenished after a second
+/// assert_eq!(
+///     tester.test_decide(&client, start + Duration::from_secs(1)),
+///     TestDecision::Allowed { remaining: 0 }
+/// );
+/// ```
+pub struct DecisionTester<'a, K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> {
+    config: &'a GovernorConfig<K, M>,
+    clock: FakeRelativeClock,
+    previous: Option<Instant>,
+    limiters: HashMap<(NonZeroU32, Duration), SimulatedLimiter<K::Key>>,
+}
+
+impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> DecisionTester<'_, K, M> {
+    /// Decides a request of `key` at the instant `at`.
+    ///
+    /// The instants are expected in chronological order, a request that is older than its
+    /// predecessor is decided at the time of the predecessor.
+    pub fn test_decide(&mut self, key: &K::Key, at: Instant) -> TestDecision {
+        if let Some(previous) = self.previous.as_mut() {
+            self.clock.advance(at.saturating_duration_since(*previous));
+            *previous = (*previous).max(at);
+        } else {
+            self.previous = Some(at);
+        }
+
+        // Unsampled keys are passed through like whitelisted ones.
+        if self.config.is_whitelisted(key) || !self.config.is_sampled(key) {
+            return TestDecision::Whitelisted;
+        }
+
+        let quota = match &self.config.quota_extractor {
+            Some(quota_extractor) => (quota_extractor.0)(key),
+            None => self
+                .config
+                .key_extractor
+                .quota(key)
+                .unwrap_or(self.config.quota),
+        };
+        let clock = &self.clock;
+        let limiter = self
+            .limiters
+            .entry((quota.burst_size(), quota.replenish_interval()))
+            .or_insert_with(|| {
+                RateLimiter::hashmap_with_clock(quota, clock.clone())
+                    .with_middleware::<StateInformationMiddleware>()
+            });
+        match limiter.check_key(key) {
+            Ok(snapshot) => TestDecision::Allowed {
+                remaining: snapshot.remaining_burst_capacity(),
+            },
+            Err(negative) => TestDecision::Blocked {
+                wait_time: negative.wait_time_from(self.clock.now()),
+            },
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use governor::{
+    clock::{DefaultClock, QuantaInstant},
+    middleware::{NoOpMiddleware, RateLimitingMiddleware},
+    nanos::Nanos,
+    state::keyed::{DefaultKeyedStateStore, ShrinkableKeyedStateStore},
+    state::StateStore,
+    Quota, RateLimiter,
+};
+
+use std::{hash::Hash, num::NonZeroU32, ops::Deref, sync::Arc};
+
+/// A [`DefaultKeyedStateStore`] that is shared between a rate limiter and its [`Limiter`],
+/// so the state can be modified while the rate limiter is in use.
+#[derive(Debug)]
+pub(crate) struct SharedStateStore<K: Hash + Eq + Clone>(Arc<DefaultKeyedStateStore<K>>);
+
+impl<K: Hash + Eq + Clone> Clone for SharedStateStore<K> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<K: Hash + Eq + Clone> StateStore for SharedStateStore<K> {
+    type Key = K;
+
+    fn measure_and_replace<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+    {
+        self.0.measure_and_replace(key, f)
+    }
+}
+
+impl<K: Hash + Eq + Clone> ShrinkableKeyedStateStore<K> for SharedStateStore<K> {
+    fn retain_recent(&self, drop_below: Nanos) {
+        self.0.retain_recent(drop_below)
+    }
+
+    fn shrink_to_fit(&self) {
+        ShrinkableKeyedStateStore::shrink_to_fit(&*self.0)
+    }
+
+    fn len(&self) -> usize {
+        ShrinkableKeyedStateStore::len(&*self.0)
+    }
+
+    fn is_empty(&self) -> bool {
+        ShrinkableKeyedStateStore::is_empty(&*self.0)
+    }
+}
+
+/// A keyed rate limiter that keeps a handle to its state.
+#[derive(Debug)]
+pub(crate) struct Limiter<K: Hash + Eq + Clone, M: RateLimitingMiddleware<QuantaInstant>> {
+    limiter: RateLimiter<K, SharedStateStore<K>, DefaultClock, M>,
+    state: SharedStateStore<K>,
+    quota: Quota,
+}
+
+impl<K: Hash + Eq + Clone, M: RateLimitingMiddleware<QuantaInstant>> Limiter<K, M> {
+    pub(crate) fn new(quota: Quota) -> Self {
+        let state = SharedStateStore(Arc::default());
+        Self {
+            limiter: RateLimiter::<_, _, _, NoOpMiddleware>::new(
+                quota,
+                state.clone(),
+                DefaultClock::default(),
+            )
+            .with_middleware::<M>(),
+            state,
+            quota,
+        }
+    }
+
+    /// Returns the quota of this rate limiter.
+    pub(crate) fn quota(&self) -> Quota {
+        self.quota
+    }
+
+    /// Gives back one cell of `key` that was consumed by a previous check.
+    ///
+    /// The cell is returned by moving the theoretical arrival time of the key back by one
+    /// replenish interval, so the quota can't exceed the burst size.
+    pub(crate) fn refund(&self, key: &K) {
+        self.refund_cells(key, 1);
+    }
+
+    /// Gives back the `cells` of `key` that were consumed by a previous check,
+    /// like [`refund`](Self::refund).
+    pub(crate) fn refund_cells(&self, key: &K, cells: u32) {
+        // A key without state has nothing to refund, so don't create its state.
+        if self.is_new(key) {
+            return;
+        }
+        let interval = Nanos::from(self.quota.replenish_interval()) * u64::from(cells);
+        let _ = self.state.measure_and_replace(key, |tat| match tat {
+            Some(tat) => Ok(((), tat.saturating_sub(interval))),
+            None => Err(()),
+        });
+    }
+
+    /// Returns the number of cells a check with `cost` consumes, at least one
+    /// and at most the burst size.
+    pub(crate) fn cost(&self, cost: u32) -> u32 {
+        cost.clamp(1, self.quota.burst_size().get())
+    }
+
+    /// Checks `key` like [`check_key`](RateLimiter::check_key), but consumes
+    /// [`cost`](Self::cost) cells.
+    pub(crate) fn check_key_cost(
+        &self,
+        key: &K,
+        cost: u32,
+    ) -> Result<M::PositiveOutcome, M::NegativeOutcome> {
+        match self
+            .limiter
+            .check_key_n(key, NonZeroU32::new(self.cost(cost)).unwrap())
+        {
+            Ok(result) => result,
+            Err(_) => self.limiter.check_key(key),
+        }
+    }
+
+    /// Returns whether `key` has no rate limiting state yet.
+    pub(crate) fn is_new(&self, key: &K) -> bool {
+        !self.state.0.contains_key(key)
+    }
+
+    /// Drops the state of `key`.
+    pub(crate) fn remove(&self, key: &K) {
+        self.state.0.remove(key);
+    }
+
+    /// Drops the state of all keys.
+    pub(crate) fn clear(&self) {
+        self.state.0.clear();
+    }
+}
+
+impl<K: Hash + Eq + Clone, M: RateLimitingMiddleware<QuantaInstant>> Deref for Limiter<K, M> {
+    type Target = RateLimiter<K, SharedStateStore<K>, DefaultClock, M>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.limiter
+    }
+}
@@ -17,6 +17,11 @@ impl KeyExtractor for WhitelistedKeyExtractor {
     type Key = String;
     type KeyExtractionError = SimpleKeyExtractionError<String>;
 
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        "test"
+    }
+
     fn extract(
         &self,
         req: &actix_web::dev::ServiceRequest,
@@ -352,6 +357,12 @@ async fn test_server_use_headers() {
             .unwrap(),
         "1"
     );
+    assert_eq!(
+        test.headers()
+            .get(HeaderName::from_static("x-ratelimit-reset"))
+            .unwrap(),
+        "1"
+    );
     assert!(test
         .headers()
         .get(HeaderName::from_static("retry-after"))
@@ -384,6 +395,12 @@ async fn test_server_use_headers() {
             .unwrap(),
         "0"
     );
+    assert_eq!(
+        test.headers()
+            .get(HeaderName::from_static("x-ratelimit-reset"))
+            .unwrap(),
+        "1"
+    );
     assert!(test
         .headers()
         .get(HeaderName::from_static("retry-after"))
@@ -428,6 +445,12 @@ async fn test_server_use_headers() {
             .unwrap(),
         "0"
     );
+    assert_eq!(
+        test.headers()
+            .get(HeaderName::from_static("x-ratelimit-reset"))
+            .unwrap(),
+        "1"
+    );
     assert!(test
         .headers()
         .get(HeaderName::from_static("x-ratelimit-whitelisted"))
@@ -456,6 +479,12 @@ async fn test_server_use_headers() {
             .unwrap(),
         "0"
     );
+    assert_eq!(
+        test.headers()
+            .get(HeaderName::from_static("x-ratelimit-reset"))
+            .unwrap(),
+        "1"
+    );
     assert!(test
         .headers()
         .get(HeaderName::from_static("retry-after"))
@@ -500,6 +529,12 @@ async fn test_server_use_headers() {
             .unwrap(),
         "0"
     );
+    assert_eq!(
+        test.headers()
+            .get(HeaderName::from_static("x-ratelimit-reset"))
+            .unwrap(),
+        "1"
+    );
     assert!(test
         .headers()
         .get(HeaderName::from_static("x-ratelimit-whitelisted"))
@@ -677,6 +712,11 @@ async fn test_json_error_response() {
         type Key = String;
         type KeyExtractionError = SimpleKeyExtractionError<String>;
 
+        #[cfg(any(feature = "log", feature = "tracing"))]
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
         fn extract(
             &self,
             _req: &actix_web::dev::ServiceRequest,
@@ -905,6 +945,11 @@ async fn test_forbidden_response_error() {
         type Key = String;
         type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
 
+        #[cfg(any(feature = "log", feature = "tracing"))]
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
         fn extract(
             &self,
             _req: &actix_web::dev::ServiceRequest,
@@ -947,6 +992,11 @@ async fn test_html_error_response() {
         type Key = String;
         type KeyExtractionError = SimpleKeyExtractionError<String>;
 
+        #[cfg(any(feature = "log", feature = "tracing"))]
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
         fn extract(
             &self,
             _req: &actix_web::dev::ServiceRequest,
@@ -1009,6 +1059,11 @@ async fn test_network_authentication_required_response_error() {
         type Key = String;
         type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
 
+        #[cfg(any(feature = "log", feature = "tracing"))]
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
         fn extract(
             &self,
             _req: &actix_web::dev::ServiceRequest,
@@ -1046,7 +1101,18 @@ async fn permissive_route(GovernorExtractor(result): GovernorExtractor) -> impl
             burst_size,
             remaining,
         } => format!("Ok: {:?} {:?}", burst_size, remaining),
-        GovernorResult::Wait { wait, burst_size } => format!("Wait: {} {:?}", wait, burst_size),
+        GovernorResult::Wait {
+            wait,
+            burst_size,
+            reset_at,
+        } => {
+            let reset_in = reset_at
+                .duration_since(std::time::SystemTime::now())
+                .unwrap_or_default()
+                .as_secs_f64()
+                .round();
+            format!("Wait: {} {:?} reset in {}s", wait, burst_size, reset_in)
+        }
         GovernorResult::Whitelisted => "Whitelisted".into(),
         GovernorResult::Err(e) => format!("Err: {}", e),
     }
@@ -1100,7 +1166,7 @@ async fn test_server_permissive() {
         .to_request();
     let test = app.call(req).await.unwrap();
     let body = actix_web::body::to_bytes(test.into_body()).await.unwrap();
-    assert_eq!(body, Bytes::from_static(b"Wait: 0 None"));
+    assert_eq!(body, Bytes::from_static(b"Wait: 0 None reset in 0s"));
 
     // Replenish one element by waiting for >90ms
     let sleep_time = std::time::Duration::from_millis(100);
@@ -1122,5 +1188,5509 @@ async fn test_server_permissive() {
         .to_request();
     let test = app.call(req).await.unwrap();
     let body = actix_web::body::to_bytes(test.into_body()).await.unwrap();
-    assert_eq!(body, Bytes::from_static(b"Wait: 0 None"));
+    assert_eq!(body, Bytes::from_static(b"Wait: 0 None reset in 0s"));
+}
+
+#[actix_rt::test]
+async fn test_server_permissive_reset_at() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use actix_web::web::Bytes;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .permissive(true)
+        .use_headers()
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(permissive_route)),
+    )
+    .await;
+
+    let addr = "127.0.0.1:80".parse().unwrap();
+    let req = test::TestRequest::get()
+        .peer_addr(addr)
+        .uri("/")
+        .to_request();
+    let body = test::read_body(test::call_service(&app, req).await).await;
+    assert_eq!(body, Bytes::from_static(b"Ok: Some(1) Some(0)"));
+
+    let req = test::TestRequest::get()
+        .peer_addr(addr)
+        .uri("/")
+        .to_request();
+    let body = test::read_body(test::call_service(&app, req).await).await;
+    assert_eq!(body, Bytes::from_static(b"Wait: 59 Some(1) reset in 59s"));
+}
+
+#[actix_rt::test]
+async fn test_reject_forwarded_from_untrusted() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    let proxy = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+    let config = GovernorConfigBuilder::default()
+        .milliseconds_per_request(90)
+        .burst_size(2)
+        .reject_forwarded_from_untrusted(vec![proxy])
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let untrusted = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 80u16);
+    let trusted = SocketAddr::new(proxy, 80u16);
+
+    // Untrusted peer without forwarded headers
+    let req = test::TestRequest::get()
+        .peer_addr(untrusted)
+        .uri("/")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::OK);
+
+    // Untrusted peer with X-Forwarded-For header
+    let req = test::TestRequest::get()
+        .peer_addr(untrusted)
+        .insert_header((header::X_FORWARDED_FOR, "1.2.3.4"))
+        .uri("/")
+        .to_request();
+    let err_res = app.call(req).await.unwrap_err();
+    assert_eq!(
+        err_res.as_response_error().status_code(),
+        StatusCode::FORBIDDEN
+    );
+
+    // Untrusted peer with Forwarded header
+    let req = test::TestRequest::get()
+        .peer_addr(untrusted)
+        .insert_header((header::FORWARDED, "for=1.2.3.4"))
+        .uri("/")
+        .to_request();
+    let err_res = app.call(req).await.unwrap_err();
+    assert_eq!(
+        err_res.as_response_error().status_code(),
+        StatusCode::FORBIDDEN
+    );
+
+    // Trusted proxy with X-Forwarded-For header
+    let req = test::TestRequest::get()
+        .peer_addr(trusted)
+        .insert_header((header::X_FORWARDED_FOR, "1.2.3.4"))
+        .uri("/")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn test_json_response() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(3)
+        .burst_size(2)
+        .use_json_response()
+        .use_headers()
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 80u16);
+
+    for _ in 0..2 {
+        let req = test::TestRequest::get()
+            .peer_addr(addr)
+            .uri("/")
+            .to_request();
+        let test = test::call_service(&app, req).await;
+        assert_eq!(test.status(), StatusCode::OK);
+    }
+
+    // Third request -> Over limit, returns JSON
+    let req = test::TestRequest::get()
+        .peer_addr(addr)
+        .uri("/")
+        .to_request();
+    let test = app.call(req).await.unwrap();
+    assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(
+        test.headers().get(header::CONTENT_TYPE).unwrap(),
+        HeaderValue::from_static("application/json")
+    );
+    let limit = test.headers().get("x-ratelimit-limit").unwrap().clone();
+    let remaining = test.headers().get("x-ratelimit-remaining").unwrap().clone();
+    let after = test.headers().get("x-ratelimit-after").unwrap().clone();
+    let body = actix_web::body::to_bytes(test.into_body()).await.unwrap();
+    assert_eq!(
+        body,
+        format!(
+            r#"{{"limit":{},"remaining":{},"retry_after":{}}}"#,
+            limit.to_str().unwrap(),
+            remaining.to_str().unwrap(),
+            after.to_str().unwrap()
+        )
+    );
+    assert_eq!(body, r#"{"limit":2,"remaining":0,"retry_after":2}"#);
+}
+
+#[actix_rt::test]
+async fn test_limit_anonymous_by_ip() {
+    use crate::{AuthIndicator, Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(3)
+        .burst_size(2)
+        .limit_anonymous_by_ip(AuthIndicator::Header(header::AUTHORIZATION))
+        .use_headers()
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 80u16);
+
+    // Authenticated requests are never limited
+    for _ in 0..4 {
+        let req = test::TestRequest::get()
+            .peer_addr(addr)
+            .insert_header((header::AUTHORIZATION, "Bearer token"))
+            .uri("/")
+            .to_request();
+        let test = test::call_service(&app, req).await;
+        assert_eq!(test.status(), StatusCode::OK);
+        assert_eq!(
+            test.headers().get("x-ratelimit-whitelisted"),
+            Some(&"true".parse().unwrap())
+        );
+    }
+
+    // Anonymous requests are limited by IP
+    for _ in 0..2 {
+        let req = test::TestRequest::get()
+            .peer_addr(addr)
+            .uri("/")
+            .to_request();
+        let test = test::call_service(&app, req).await;
+        assert_eq!(test.status(), StatusCode::OK);
+        assert_eq!(test.headers().get("x-ratelimit-whitelisted"), None);
+    }
+    let req = test::TestRequest::get()
+        .peer_addr(addr)
+        .uri("/")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // Another anonymous client has its own quota
+    let req = test::TestRequest::get()
+        .peer_addr(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+            80u16,
+        ))
+        .uri("/")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::OK);
+}
+
+#[test]
+fn test_x_forwarded_for_trusted_hops() {
+    use crate::XForwardedForKeyExtractor;
+    use actix_web::test;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    let peer = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 3)), 80u16);
+    let client = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7));
+
+    // Two proxy layers that append their own address
+    let extractor = XForwardedForKeyExtractor::new(Vec::new()).trusted_hops(2);
+    let req = test::TestRequest::get()
+        .peer_addr(peer)
+        .insert_header((
+            header::X_FORWARDED_FOR,
+            "1.1.1.1, 203.0.113.7, 10.0.0.1, 10.0.0.2",
+        ))
+        .to_srv_request();
+    assert_eq!(extractor.extract(&req).unwrap(), client);
+
+    // The chain is too short, fall back to the peer IP
+    let req = test::TestRequest::get()
+        .peer_addr(peer)
+        .insert_header((header::X_FORWARDED_FOR, "10.0.0.1, 10.0.0.2"))
+        .to_srv_request();
+    assert_eq!(extractor.extract(&req).unwrap(), peer.ip());
+
+    // Trusted proxy set
+    let extractor = XForwardedForKeyExtractor::new(vec![
+        peer.ip(),
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+    ]);
+    let req = test::TestRequest::get()
+        .peer_addr(peer)
+        .insert_header((
+            header::X_FORWARDED_FOR,
+            "1.1.1.1, 203.0.113.7, 10.0.0.1, 10.0.0.2",
+        ))
+        .to_srv_request();
+    assert_eq!(extractor.extract(&req).unwrap(), client);
+
+    // Untrusted peer, the header is ignored
+    let untrusted = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 80u16);
+    let req = test::TestRequest::get()
+        .peer_addr(untrusted)
+        .insert_header((header::X_FORWARDED_FOR, "203.0.113.7"))
+        .to_srv_request();
+    assert_eq!(extractor.extract(&req).unwrap(), untrusted.ip());
+}
+
+#[actix_rt::test]
+async fn test_server_x_forwarded_for() {
+    use crate::{Governor, GovernorConfigBuilder, XForwardedForKeyExtractor};
+    use actix_web::test;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(3)
+        .burst_size(2)
+        .key_extractor(XForwardedForKeyExtractor::new(Vec::new()).trusted_hops(1))
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let proxy = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 80u16);
+    let request = |forwarded_for: &'static str| {
+        test::TestRequest::get()
+            .peer_addr(proxy)
+            .insert_header((header::X_FORWARDED_FOR, forwarded_for))
+            .uri("/")
+            .to_request()
+    };
+
+    for _ in 0..2 {
+        let test = test::call_service(&app, request("203.0.113.7, 10.0.0.1")).await;
+        assert_eq!(test.status(), StatusCode::OK);
+    }
+    let test = test::call_service(&app, request("203.0.113.7, 10.0.0.1")).await;
+    assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // A different client behind the same proxies has its own quota
+    let test = test::call_service(&app, request("203.0.113.8, 10.0.0.1")).await;
+    assert_eq!(test.status(), StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn test_scheme_key_extractor() {
+    use crate::{Governor, GovernorConfigBuilder, PeerIpKeyExtractor, SchemeKeyExtractor};
+    use actix_web::test;
+    use governor::Quota;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::num::NonZeroU32;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(3)
+        .burst_size(3)
+        .key_extractor(
+            SchemeKeyExtractor::new(PeerIpKeyExtractor).scheme_quota(
+                "http",
+                Quota::with_period(std::time::Duration::from_secs(3))
+                    .unwrap()
+                    .allow_burst(NonZeroU32::new(1).unwrap()),
+            ),
+        )
+        .use_headers()
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 80u16);
+
+    // Plain HTTP requests get the strict quota
+    let req = test::TestRequest::get()
+        .peer_addr(addr)
+        .uri("http://localhost/")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::OK);
+    assert_eq!(test.headers().get("x-ratelimit-limit").unwrap(), "1");
+    let req = test::TestRequest::get()
+        .peer_addr(addr)
+        .uri("http://localhost/")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // HTTPS requests use a separate bucket with the configured quota
+    for remaining in ["2", "1", "0"] {
+        let req = test::TestRequest::get()
+            .peer_addr(addr)
+            .uri("https://localhost/")
+            .to_request();
+        let test = test::call_service(&app, req).await;
+        assert_eq!(test.status(), StatusCode::OK);
+        assert_eq!(test.headers().get("x-ratelimit-limit").unwrap(), "3");
+        assert_eq!(
+            test.headers().get("x-ratelimit-remaining").unwrap(),
+            remaining
+        );
+    }
+    let req = test::TestRequest::get()
+        .peer_addr(addr)
+        .uri("https://localhost/")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_rt::test]
+async fn test_clear() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(3)
+        .burst_size(2)
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let clients = [
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 80u16),
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)), 80u16),
+    ];
+
+    for addr in clients {
+        for _ in 0..2 {
+            let req = test::TestRequest::get()
+                .peer_addr(addr)
+                .uri("/")
+                .to_request();
+            let test = test::call_service(&app, req).await;
+            assert_eq!(test.status(), StatusCode::OK);
+        }
+        let req = test::TestRequest::get()
+            .peer_addr(addr)
+            .uri("/")
+            .to_request();
+        let test = test::call_service(&app, req).await;
+        assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    config.clear();
+
+    // All clients get a fresh quota
+    for addr in clients {
+        for _ in 0..2 {
+            let req = test::TestRequest::get()
+                .peer_addr(addr)
+                .uri("/")
+                .to_request();
+            let test = test::call_service(&app, req).await;
+            assert_eq!(test.status(), StatusCode::OK);
+        }
+    }
+}
+
+#[actix_rt::test]
+async fn test_reset_key() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(3)
+        .burst_size(1)
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let clients = [
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 80u16),
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)), 80u16),
+    ];
+
+    let call = |addr: SocketAddr| {
+        let req = test::TestRequest::get()
+            .peer_addr(addr)
+            .uri("/")
+            .to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await.status() }
+    };
+
+    for addr in clients {
+        assert_eq!(call(addr).await, StatusCode::OK);
+        assert_eq!(call(addr).await, StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    config.reset_key(&clients[0].ip());
+
+    // Only the reset client gets a fresh quota
+    assert_eq!(call(clients[0]).await, StatusCode::OK);
+    assert_eq!(call(clients[0]).await, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(call(clients[1]).await, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_rt::test]
+async fn test_retain_interval() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use std::time::Duration;
+
+    let config = GovernorConfigBuilder::default()
+        .milliseconds_per_request(10)
+        .burst_size(1)
+        .retain_interval(Duration::from_millis(20))
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .peer_addr("127.0.0.1:80".parse().unwrap())
+        .uri("/")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    assert_eq!(config.tracked_keys(), 1);
+
+    // The key is dropped once it replenished
+    actix_rt::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(config.tracked_keys(), 0);
+}
+
+#[test]
+fn test_retain_interval_outside_runtime() {
+    use crate::GovernorConfigBuilder;
+    use std::time::Duration;
+
+    // The task is only spawned once the middleware is constructed
+    let config = GovernorConfigBuilder::default()
+        .retain_interval(Duration::from_secs(1))
+        .finish();
+    assert!(config.is_some());
+}
+
+#[actix_rt::test]
+async fn test_retain_interval_before_use_headers() {
+    use crate::{Governor, GovernorConfigBuilder, PeerIpKeyExtractor};
+    use actix_web::test;
+    use std::time::Duration;
+
+    // The interval is kept when the key extractor and the middleware change
+    let config = GovernorConfigBuilder::default()
+        .milliseconds_per_request(10)
+        .burst_size(1)
+        .retain_interval(Duration::from_millis(20))
+        .key_extractor(PeerIpKeyExtractor)
+        .use_headers()
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    for ip in 1..=3 {
+        let req = test::TestRequest::get()
+            .peer_addr(format!("127.0.0.{}:80", ip).parse().unwrap())
+            .uri("/")
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+    assert_eq!(config.tracked_keys(), 3);
+
+    // The stale keys are dropped once they replenished
+    actix_rt::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(config.tracked_keys(), 0);
+}
+
+#[actix_rt::test]
+async fn test_global_cap() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use governor::Quota;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::num::NonZeroU32;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(2)
+        .global_cap(
+            Quota::with_period(std::time::Duration::from_secs(60))
+                .unwrap()
+                .allow_burst(NonZeroU32::new(3).unwrap()),
+        )
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = |ip: u8| {
+        let req = test::TestRequest::get()
+            .peer_addr(SocketAddr::new(
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, ip)),
+                80,
+            ))
+            .uri("/")
+            .to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await.status() }
+    };
+
+    // The quota of the key applies
+    assert_eq!(call(1).await, StatusCode::OK);
+    assert_eq!(call(1).await, StatusCode::OK);
+    assert_eq!(call(1).await, StatusCode::TOO_MANY_REQUESTS);
+
+    // The blocked request didn't deplete the global quota
+    assert_eq!(call(2).await, StatusCode::OK);
+
+    // The global quota applies to fresh keys as well
+    assert_eq!(call(2).await, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(call(3).await, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_rt::test]
+async fn test_layer_headers() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use governor::Quota;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::num::NonZeroU32;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(2)
+        .global_cap(
+            Quota::with_period(std::time::Duration::from_secs(60))
+                .unwrap()
+                .allow_burst(NonZeroU32::new(5).unwrap()),
+        )
+        .use_headers()
+        .layer_headers()
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = |ip: u8| {
+        let req = test::TestRequest::get()
+            .peer_addr(SocketAddr::new(
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, ip)),
+                80,
+            ))
+            .uri("/")
+            .to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await.headers().clone() }
+    };
+    let header = |headers: &header::HeaderMap, name: &str| {
+        headers.get(name).unwrap().to_str().unwrap().to_owned()
+    };
+
+    let res = call(1).await;
+    assert_eq!(header(&res, "x-ratelimit-limit-key"), "2");
+    assert_eq!(header(&res, "x-ratelimit-remaining-key"), "1");
+    assert_eq!(header(&res, "x-ratelimit-limit-global"), "5");
+    assert_eq!(header(&res, "x-ratelimit-remaining-global"), "4");
+    // The unprefixed headers report the quota of the key
+    assert_eq!(header(&res, "x-ratelimit-remaining"), "1");
+
+    let res = call(2).await;
+    assert_eq!(header(&res, "x-ratelimit-remaining-key"), "1");
+    assert_eq!(header(&res, "x-ratelimit-remaining-global"), "3");
+
+    let res = call(1).await;
+    assert_eq!(header(&res, "x-ratelimit-remaining-key"), "0");
+    assert_eq!(header(&res, "x-ratelimit-remaining-global"), "2");
+}
+
+#[actix_rt::test]
+async fn test_tracked_keys() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    let config = GovernorConfigBuilder::default().finish().unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    assert_eq!(config.tracked_keys(), 0);
+    for ip in 1..=3 {
+        let req = test::TestRequest::get()
+            .peer_addr(format!("127.0.2.{}:80", ip).parse().unwrap())
+            .uri("/")
+            .to_request();
+        let test = test::call_service(&app, req).await;
+        assert_eq!(test.status(), StatusCode::OK);
+        assert_eq!(config.tracked_keys(), ip);
+    }
+
+    // Shrinking keeps the state
+    config.shrink_to_fit();
+    assert_eq!(config.tracked_keys(), 3);
+}
+
+#[actix_rt::test]
+async fn test_version_key_extractor() {
+    use crate::{Governor, GovernorConfigBuilder, PeerIpKeyExtractor, VersionKeyExtractor};
+    use actix_web::{http::Version, test};
+    use governor::Quota;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::num::NonZeroU32;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(3)
+        .burst_size(1)
+        .key_extractor(
+            VersionKeyExtractor::new(PeerIpKeyExtractor).version_quota(
+                Version::HTTP_2,
+                Quota::with_period(std::time::Duration::from_secs(3))
+                    .unwrap()
+                    .allow_burst(NonZeroU32::new(2).unwrap()),
+            ),
+        )
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 80u16);
+    let request = |version| {
+        test::TestRequest::get()
+            .peer_addr(addr)
+            .version(version)
+            .uri("/")
+            .to_request()
+    };
+
+    // HTTP/1.1 uses the configured quota
+    let test = test::call_service(&app, request(Version::HTTP_11)).await;
+    assert_eq!(test.status(), StatusCode::OK);
+    let test = test::call_service(&app, request(Version::HTTP_11)).await;
+    assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // HTTP/2 is limited independently with its own quota
+    for _ in 0..2 {
+        let test = test::call_service(&app, request(Version::HTTP_2)).await;
+        assert_eq!(test.status(), StatusCode::OK);
+    }
+    let test = test::call_service(&app, request(Version::HTTP_2)).await;
+    assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_rt::test]
+async fn test_penalize_extraction_errors() {
+    use crate::{Governor, GovernorConfigBuilder, PeerIpKeyExtractor};
+    use actix_web::test;
+    use governor::Quota;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::num::NonZeroU32;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(3)
+        .burst_size(2)
+        .key_extractor(WhitelistedKeyExtractor)
+        .penalize_extraction_errors(
+            PeerIpKeyExtractor,
+            Quota::with_period(std::time::Duration::from_secs(3))
+                .unwrap()
+                .allow_burst(NonZeroU32::new(2).unwrap()),
+        )
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 80u16);
+
+    // Extraction errors are returned until the penalty quota is exhausted
+    for _ in 0..2 {
+        let req = test::TestRequest::get()
+            .peer_addr(addr)
+            .uri("/")
+            .to_request();
+        let err_res = app.call(req).await.unwrap_err();
+        assert_eq!(
+            err_res.as_response_error().status_code(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+    let req = test::TestRequest::get()
+        .peer_addr(addr)
+        .uri("/")
+        .to_request();
+    let test = app.call(req).await.unwrap();
+    assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(test.headers().get("retry-after").unwrap(), "2");
+
+    // Another client is not affected
+    let req = test::TestRequest::get()
+        .peer_addr(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+            80u16,
+        ))
+        .uri("/")
+        .to_request();
+    assert!(app.call(req).await.is_err());
+
+    // Valid requests are still limited by their own key
+    let req = test::TestRequest::get()
+        .peer_addr(addr)
+        .insert_header(("super-token", "token"))
+        .uri("/")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::OK);
+
+    // Clearing the state drops the penalties as well
+    config.clear();
+    let req = test::TestRequest::get()
+        .peer_addr(addr)
+        .uri("/")
+        .to_request();
+    let err_res = app.call(req).await.unwrap_err();
+    assert_eq!(
+        err_res.as_response_error().status_code(),
+        StatusCode::INTERNAL_SERVER_ERROR
+    );
+}
+
+#[actix_rt::test]
+async fn test_burst_multiplier() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct PlanKeyExtractor;
+
+    impl KeyExtractor for PlanKeyExtractor {
+        type Key = String;
+        type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
+
+        #[cfg(any(feature = "log", feature = "tracing"))]
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
+        fn extract(
+            &self,
+            _req: &actix_web::dev::ServiceRequest,
+        ) -> Result<Self::Key, Self::KeyExtractionError> {
+            Ok("test".to_owned())
+        }
+
+        fn burst_multiplier(&self, req: &actix_web::dev::ServiceRequest) -> f64 {
+            match req.headers().get("x-plan") {
+                Some(plan) if plan == "premium" => 2.1,
+                _ => 1.0,
+            }
+        }
+    }
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(3)
+        .burst_size(2)
+        .key_extractor(PlanKeyExtractor)
+        .use_headers()
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    // Free plan gets the configured burst
+    for _ in 0..2 {
+        let req = test::TestRequest::get().uri("/").to_request();
+        let test = test::call_service(&app, req).await;
+        assert_eq!(test.status(), StatusCode::OK);
+        assert_eq!(test.headers().get("x-ratelimit-limit").unwrap(), "2");
+    }
+    let req = test::TestRequest::get().uri("/").to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // Premium plan gets twice the burst
+    for _ in 0..4 {
+        let req = test::TestRequest::get()
+            .insert_header(("x-plan", "premium"))
+            .uri("/")
+            .to_request();
+        let test = test::call_service(&app, req).await;
+        assert_eq!(test.status(), StatusCode::OK);
+        assert_eq!(test.headers().get("x-ratelimit-limit").unwrap(), "4");
+    }
+    let req = test::TestRequest::get()
+        .insert_header(("x-plan", "premium"))
+        .uri("/")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+/// The messages logged by all tests, see [`capture_logs`].
+#[cfg(feature = "log")]
+static LOGS: std::sync::Mutex<Vec<(log::Level, String)>> = std::sync::Mutex::new(Vec::new());
+
+#[cfg(feature = "log")]
+struct CaptureLogger;
+
+#[cfg(feature = "log")]
+impl log::Log for CaptureLogger {
+    fn enabled(&self, _metadata: &log::Metadata<'_>) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        LOGS.lock()
+            .unwrap()
+            .push((record.level(), record.args().to_string()));
+    }
+
+    fn flush(&self) {}
+}
+
+/// Collects the log messages of all tests in [`LOGS`]. Tests run concurrently,
+/// so they have to filter the messages they are interested in.
+#[cfg(feature = "log")]
+fn capture_logs() {
+    let _ = log::set_logger(&CaptureLogger);
+    log::set_max_level(log::LevelFilter::Debug);
+}
+
+/// A `tracing` subscriber that records the fields of the entered spans for each event.
+#[cfg(feature = "tracing")]
+#[derive(Default, Clone)]
+struct SpanRecorder {
+    spans: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+    entered: std::sync::Arc<std::sync::Mutex<Vec<u64>>>,
+    events: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+}
+
+#[cfg(feature = "tracing")]
+struct FieldRecorder<'a>(&'a mut String);
+
+#[cfg(feature = "tracing")]
+impl tracing::field::Visit for FieldRecorder<'_> {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        self.0.push_str(&format!("{}={:?} ", field.name(), value));
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl tracing::Subscriber for SpanRecorder {
+    fn enabled(&self, _metadata: &tracing::Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+        let mut fields = String::new();
+        span.record(&mut FieldRecorder(&mut fields));
+        let mut spans = self.spans.lock().unwrap();
+        spans.push(fields);
+        tracing::span::Id::from_u64(spans.len() as u64)
+    }
+
+    fn record(&self, _span: &tracing::span::Id, _values: &tracing::span::Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &tracing::span::Id, _follows: &tracing::span::Id) {}
+
+    fn event(&self, event: &tracing::Event<'_>) {
+        let mut fields = String::new();
+        for id in self.entered.lock().unwrap().iter() {
+            fields.push_str(&self.spans.lock().unwrap()[*id as usize - 1]);
+        }
+        event.record(&mut FieldRecorder(&mut fields));
+        self.events.lock().unwrap().push(fields);
+    }
+
+    fn enter(&self, span: &tracing::span::Id) {
+        self.entered.lock().unwrap().push(span.into_u64());
+    }
+
+    fn exit(&self, _span: &tracing::span::Id) {
+        self.entered.lock().unwrap().pop();
+    }
+}
+
+#[cfg(feature = "tracing")]
+#[actix_rt::test]
+async fn test_key_span() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    async fn traced() -> impl Responder {
+        tracing::info!("handled");
+        HttpResponse::Ok().finish()
+    }
+
+    let recorder = SpanRecorder::default();
+    let _guard = tracing::subscriber::set_default(recorder.clone());
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .key_span()
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(traced)),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .peer_addr("127.0.0.1:80".parse().unwrap())
+        .uri("/")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    let events = recorder.events.lock().unwrap();
+    assert_eq!(
+        events.as_slice(),
+        ["extractor=\"peer IP\" key=\"127.0.0.1\" message=handled "]
+    );
+}
+
+#[cfg(all(feature = "log", not(feature = "tracing")))]
+#[actix_rt::test]
+async fn test_log_dedup_interval() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    capture_logs();
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .log_dedup_interval(std::time::Duration::from_secs(60))
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let addr = "127.0.0.42:80".parse().unwrap();
+    for _ in 0..5 {
+        let req = test::TestRequest::get()
+            .peer_addr(addr)
+            .uri("/")
+            .to_request();
+        test::call_service(&app, req).await;
+    }
+
+    let logs: Vec<_> = LOGS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, message)| message.contains("[127.0.0.42]"))
+        .cloned()
+        .collect();
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0].0, log::Level::Warn);
+}
+
+#[cfg(feature = "log")]
+#[actix_rt::test]
+async fn test_log_allowed() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    capture_logs();
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(2)
+        .log_allowed()
+        .use_headers()
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let addr = "127.0.0.43:80".parse().unwrap();
+    for _ in 0..3 {
+        let req = test::TestRequest::get()
+            .peer_addr(addr)
+            .uri("/")
+            .to_request();
+        test::call_service(&app, req).await;
+    }
+
+    let logs: Vec<_> = LOGS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, message)| message.contains("[127.0.0.43]"))
+        .cloned()
+        .collect();
+    // With the `tracing` feature, the block is an event instead of a log message
+    assert_eq!(logs.len(), if cfg!(feature = "tracing") { 2 } else { 3 });
+    assert_eq!(
+        logs[0],
+        (
+            log::Level::Debug,
+            "Request allowed for peer IP [127.0.0.43], 1 remaining".to_owned()
+        )
+    );
+    assert_eq!(logs[1].0, log::Level::Debug);
+    assert!(logs[1].1.ends_with("0 remaining"));
+    // Blocks are still logged
+    #[cfg(not(feature = "tracing"))]
+    assert_eq!(logs[2].0, log::Level::Info);
+}
+
+#[actix_rt::test]
+async fn test_peer_ip_with_unknown_key() {
+    use crate::{Governor, GovernorConfigBuilder, PeerIpKeyExtractor};
+    use actix_web::test;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(2)
+        .key_extractor(PeerIpKeyExtractor::with_unknown_key(IpAddr::V4(
+            Ipv4Addr::UNSPECIFIED,
+        )))
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    // Requests without peer IP share the unknown key
+    for _ in 0..2 {
+        let req = test::TestRequest::get().uri("/").to_request();
+        let test = test::call_service(&app, req).await;
+        assert_eq!(test.status(), StatusCode::OK);
+    }
+    let req = test::TestRequest::get().uri("/").to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // Requests with peer IP are not affected
+    let req = test::TestRequest::get()
+        .peer_addr("127.0.0.1:80".parse().unwrap())
+        .uri("/")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::OK);
+}
+
+#[test]
+fn test_methods_accessor() {
+    use crate::{GovernorConfigBuilder, Method};
+
+    let config = GovernorConfigBuilder::default()
+        .methods(vec![Method::GET, Method::POST])
+        .finish()
+        .unwrap();
+    assert_eq!(config.methods(), Some(&[Method::GET, Method::POST][..]));
+
+    let config = GovernorConfigBuilder::default().finish().unwrap();
+    assert_eq!(config.methods(), None);
+}
+
+#[actix_rt::test]
+async fn test_body_hash_key_extractor() {
+    use crate::{BodyHashKeyExtractor, Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    async fn echo(body: String) -> impl Responder {
+        body
+    }
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .key_extractor(BodyHashKeyExtractor::new(16))
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::post().to(echo)),
+    )
+    .await;
+
+    // The body is still passed to the service
+    let req = test::TestRequest::post()
+        .uri("/")
+        .set_payload("payload")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::OK);
+    assert_eq!(test::read_body(test).await, "payload");
+
+    // Identical payloads share a bucket
+    let req = test::TestRequest::post()
+        .uri("/")
+        .set_payload("payload")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // A different payload doesn't
+    let req = test::TestRequest::post()
+        .uri("/")
+        .set_payload("other payload")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::OK);
+
+    // Bodies above the limit are rejected
+    let req = test::TestRequest::post()
+        .uri("/")
+        .set_payload("a payload that is too large")
+        .to_request();
+    let err = app.call(req).await.unwrap_err();
+    assert_eq!(
+        err.as_response_error().status_code(),
+        StatusCode::PAYLOAD_TOO_LARGE
+    );
+}
+
+#[test]
+fn test_body_limit_forwarded() {
+    use crate::{
+        BodyHashKeyExtractor, EitherKeyExtractor, EndpointKeyExtractor, FixedWindowKeyExtractor,
+        PeerIpKeyExtractor,
+    };
+
+    let body_hash = BodyHashKeyExtractor::new(16);
+    assert_eq!(EndpointKeyExtractor::new(body_hash).body_limit(), Some(16));
+    assert_eq!(
+        FixedWindowKeyExtractor::hourly(body_hash).body_limit(),
+        Some(16)
+    );
+    assert_eq!(
+        EitherKeyExtractor::new(PeerIpKeyExtractor, body_hash).body_limit(),
+        Some(16)
+    );
+    assert_eq!(
+        EndpointKeyExtractor::new(PeerIpKeyExtractor).body_limit(),
+        None
+    );
+}
+
+#[actix_rt::test]
+async fn test_guard() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::{guard, test};
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .guard(guard::fn_guard(|ctx| {
+            ctx.head().uri.path().starts_with("/api")
+        }))
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello))
+            .route("/api", web::get().to(hello)),
+    )
+    .await;
+
+    // Requests that match the guard are limited
+    let req = test::TestRequest::get()
+        .peer_addr("127.0.0.1:80".parse().unwrap())
+        .uri("/api")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::OK);
+
+    let req = test::TestRequest::get()
+        .peer_addr("127.0.0.1:80".parse().unwrap())
+        .uri("/api")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // Other requests pass through
+    for _ in 0..3 {
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri("/")
+            .to_request();
+        let test = test::call_service(&app, req).await;
+        assert_eq!(test.status(), StatusCode::OK);
+    }
+}
+
+#[actix_rt::test]
+async fn test_exempt() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .use_headers()
+        .exempt(|req| req.path() == "/health")
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello))
+            .route("/health", web::get().to(hello)),
+    )
+    .await;
+
+    let call = |uri: &'static str| async {
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri(uri)
+            .to_request();
+        test::call_service(&app, req).await
+    };
+
+    // Exempted requests pass through
+    for _ in 0..5 {
+        let test = call("/health").await;
+        assert_eq!(test.status(), StatusCode::OK);
+        assert_eq!(
+            test.headers().get("x-ratelimit-whitelisted").unwrap(),
+            "true"
+        );
+    }
+
+    // Other requests are limited
+    assert_eq!(call("/").await.status(), StatusCode::OK);
+    assert_eq!(call("/").await.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(call("/health").await.status(), StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn test_idempotency_key_extractor() {
+    use crate::{Governor, GovernorConfigBuilder, IdempotencyKeyExtractor, PeerIpKeyExtractor};
+    use actix_web::test;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(2)
+        .key_extractor(IdempotencyKeyExtractor::new(PeerIpKeyExtractor))
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/payments", web::post().to(hello))
+            .route("/refunds", web::post().to(hello)),
+    )
+    .await;
+
+    let call = |uri: &'static str, idempotency_key: Option<&'static str>| {
+        let mut req = test::TestRequest::post()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri(uri);
+        if let Some(idempotency_key) = idempotency_key {
+            req = req.insert_header(("idempotency-key", idempotency_key));
+        }
+        let req = req.to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await.status() }
+    };
+
+    // Retries of one operation share a budget
+    assert_eq!(call("/payments", Some("a")).await, StatusCode::OK);
+    assert_eq!(call("/payments", Some("a")).await, StatusCode::OK);
+    assert_eq!(
+        call("/payments", Some("a")).await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // Other operations and endpoints are independent
+    assert_eq!(call("/payments", Some("b")).await, StatusCode::OK);
+    assert_eq!(call("/refunds", Some("a")).await, StatusCode::OK);
+
+    // Requests without an idempotency key share the budget of the client on the endpoint
+    assert_eq!(call("/payments", None).await, StatusCode::OK);
+    assert_eq!(call("/payments", None).await, StatusCode::OK);
+    assert_eq!(call("/payments", None).await, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_rt::test]
+async fn test_rejection_delay() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use std::time::{Duration, Instant};
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .rejection_delay(Duration::from_millis(50), Duration::from_millis(100))
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = || async {
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri("/")
+            .to_request();
+        let start = Instant::now();
+        let status = test::call_service(&app, req).await.status();
+        (status, start.elapsed())
+    };
+
+    // Allowed requests aren't delayed
+    let (status, elapsed) = call().await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(elapsed < Duration::from_millis(50));
+
+    for _ in 0..3 {
+        let (status, elapsed) = call().await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert!(elapsed >= Duration::from_millis(50));
+        // Leave some room for the timer resolution
+        assert!(elapsed < Duration::from_millis(150));
+    }
+}
+
+#[actix_rt::test]
+async fn test_allowlist() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .use_headers()
+        .allowlist(vec![
+            (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 8),
+            (
+                IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0)),
+                32,
+            ),
+        ])
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = |addr: &'static str| {
+        let req = test::TestRequest::get()
+            .peer_addr(addr.parse().unwrap())
+            .uri("/")
+            .to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await }
+    };
+
+    // Peers in the ranges are never limited
+    for addr in ["10.1.2.3:80", "[2001:db8::1]:80"] {
+        for _ in 0..3 {
+            let test = call(addr).await;
+            assert_eq!(test.status(), StatusCode::OK);
+            assert_eq!(
+                test.headers().get("x-ratelimit-whitelisted").unwrap(),
+                "true"
+            );
+        }
+    }
+
+    // Other peers are limited
+    for addr in ["11.1.2.3:80", "[2001:db9::1]:80"] {
+        let test = call(addr).await;
+        assert_eq!(test.status(), StatusCode::OK);
+        assert!(!test.headers().contains_key("x-ratelimit-whitelisted"));
+        assert_eq!(call(addr).await.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+}
+
+#[actix_rt::test]
+async fn test_fingerprint_key_extractor() {
+    use crate::{FingerprintKeyExtractor, Governor, GovernorConfigBuilder};
+    use actix_web::http::header::{ACCEPT_LANGUAGE, USER_AGENT};
+    use actix_web::test;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(2)
+        .key_extractor(FingerprintKeyExtractor::new())
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = |addr: &'static str, user_agent: &'static str| {
+        let req = test::TestRequest::get()
+            .peer_addr(addr.parse().unwrap())
+            .insert_header((USER_AGENT, user_agent))
+            .insert_header((ACCEPT_LANGUAGE, "en-US"))
+            .uri("/")
+            .to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await.status() }
+    };
+
+    // Identical fingerprints share a bucket
+    assert_eq!(call("127.0.0.1:80", "curl").await, StatusCode::OK);
+    assert_eq!(call("127.0.0.1:1234", "curl").await, StatusCode::OK);
+    assert_eq!(
+        call("127.0.0.1:80", "curl").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // Other headers or IPs result in other fingerprints
+    assert_eq!(call("127.0.0.1:80", "firefox").await, StatusCode::OK);
+    assert_eq!(call("127.0.0.2:80", "curl").await, StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn test_remaining_quota() {
+    use crate::{Governor, GovernorConfigBuilder, RemainingQuota};
+    use actix_web::test;
+
+    async fn remaining(RemainingQuota(remaining): RemainingQuota) -> impl Responder {
+        format!("{:?}", remaining)
+    }
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(2)
+        .permissive(true)
+        .use_headers()
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(remaining)),
+    )
+    .await;
+
+    for expected in ["Some(1)", "Some(0)", "Some(0)"] {
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri("/")
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(body, expected);
+    }
+
+    // Without state information, the remaining quota is unknown
+    let config = GovernorConfigBuilder::default().finish().unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(remaining)),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .peer_addr("127.0.0.1:80".parse().unwrap())
+        .uri("/")
+        .to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    assert_eq!(body, "None");
+}
+
+#[test]
+fn test_x_real_ip_trust_boundaries() {
+    use crate::XForwardedForKeyExtractor;
+    use actix_web::test;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    let forwarding_proxy = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 80u16);
+    let real_ip_proxy = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 1, 1)), 80u16);
+    let client = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7));
+    let spoofed = "198.51.100.1";
+
+    let extractor = XForwardedForKeyExtractor::new(vec![forwarding_proxy.ip()])
+        .real_ip_from(vec![real_ip_proxy.ip()]);
+
+    // Each proxy tier is trusted for its own header
+    let req = test::TestRequest::get()
+        .peer_addr(real_ip_proxy)
+        .insert_header(("x-real-ip", "203.0.113.7"))
+        .to_srv_request();
+    assert_eq!(extractor.extract(&req).unwrap(), client);
+
+    let req = test::TestRequest::get()
+        .peer_addr(forwarding_proxy)
+        .insert_header((header::X_FORWARDED_FOR, "203.0.113.7"))
+        .to_srv_request();
+    assert_eq!(extractor.extract(&req).unwrap(), client);
+
+    // But not for the header of the other tier
+    let req = test::TestRequest::get()
+        .peer_addr(real_ip_proxy)
+        .insert_header((header::X_FORWARDED_FOR, spoofed))
+        .to_srv_request();
+    assert_eq!(extractor.extract(&req).unwrap(), real_ip_proxy.ip());
+
+    let req = test::TestRequest::get()
+        .peer_addr(forwarding_proxy)
+        .insert_header(("x-real-ip", spoofed))
+        .insert_header((header::X_FORWARDED_FOR, "203.0.113.7"))
+        .to_srv_request();
+    assert_eq!(extractor.extract(&req).unwrap(), client);
+
+    // Clients can't set either header
+    let untrusted = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 80u16);
+    let req = test::TestRequest::get()
+        .peer_addr(untrusted)
+        .insert_header(("x-real-ip", spoofed))
+        .insert_header((header::X_FORWARDED_FOR, spoofed))
+        .to_srv_request();
+    assert_eq!(extractor.extract(&req).unwrap(), untrusted.ip());
+}
+
+#[actix_rt::test]
+async fn test_map_rejection_response() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .map_rejection_response(|mut response| {
+            response.headers_mut().insert(
+                HeaderName::from_static("x-custom"),
+                HeaderValue::from_static("rejected"),
+            );
+            response
+        })
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .peer_addr("127.0.0.1:80".parse().unwrap())
+        .uri("/")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::OK);
+    assert!(test.headers().get("x-custom").is_none());
+
+    let req = test::TestRequest::get()
+        .peer_addr("127.0.0.1:80".parse().unwrap())
+        .uri("/")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(test.headers().get("x-custom").unwrap(), "rejected");
+    assert!(test.headers().get("retry-after").is_some());
+}
+
+#[actix_rt::test]
+async fn test_unlimited_whitelist_headers() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    let config = GovernorConfigBuilder::default()
+        .key_extractor(WhitelistedKeyExtractor)
+        .unlimited_whitelist_headers()
+        .use_headers()
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .insert_header(("super-token", "AdminSecretToken"))
+        .uri("/")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::OK);
+    assert_eq!(
+        test.headers().get("x-ratelimit-whitelisted").unwrap(),
+        "true"
+    );
+    assert_eq!(
+        test.headers().get("x-ratelimit-limit").unwrap(),
+        "4294967295"
+    );
+    assert_eq!(
+        test.headers().get("x-ratelimit-remaining").unwrap(),
+        "4294967295"
+    );
+
+    // Limited keys still get their real values
+    let req = test::TestRequest::get()
+        .insert_header(("super-token", "UserToken"))
+        .uri("/")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::OK);
+    assert!(test.headers().get("x-ratelimit-whitelisted").is_none());
+    assert_eq!(test.headers().get("x-ratelimit-limit").unwrap(), "8");
+}
+
+#[test]
+fn test_user_key_extractor() {
+    use crate::{UserKeyExtractor, UserSource};
+    use actix_http::HttpMessage;
+    use actix_web::test;
+
+    let extractor =
+        UserKeyExtractor::new(UserSource::Header(HeaderName::from_static("x-user-email")));
+
+    // Different casing and whitespace result in the same key
+    let req = test::TestRequest::get()
+        .insert_header(("x-user-email", "Alice@Example.com"))
+        .to_srv_request();
+    let key = extractor.extract(&req).unwrap();
+    assert_eq!(key, "alice@example.com");
+
+    let req = test::TestRequest::get()
+        .insert_header(("x-user-email", " ALICE@example.COM "))
+        .to_srv_request();
+    assert_eq!(extractor.extract(&req).unwrap(), key);
+
+    // Missing identity
+    let req = test::TestRequest::get().to_srv_request();
+    let err = extractor.extract(&req).unwrap_err();
+    assert_eq!(err.status_code, StatusCode::UNAUTHORIZED);
+
+    // Claim provided by an authentication middleware
+    struct Claims {
+        preferred_username: String,
+    }
+    let extractor = UserKeyExtractor::new(UserSource::Custom(|req| {
+        req.extensions()
+            .get::<Claims>()
+            .map(|claims| claims.preferred_username.clone())
+    }));
+    let req = test::TestRequest::get().to_srv_request();
+    req.extensions_mut().insert(Claims {
+        preferred_username: "Bob".to_owned(),
+    });
+    assert_eq!(extractor.extract(&req).unwrap(), "bob");
+
+    let req = test::TestRequest::get().to_srv_request();
+    assert!(extractor.extract(&req).is_err());
+}
+
+#[actix_rt::test]
+async fn test_fixed_window_key_extractor() {
+    use crate::{FixedWindowKeyExtractor, Governor, GovernorConfigBuilder, PeerIpKeyExtractor};
+    use actix_web::test;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    static NOW: AtomicU64 = AtomicU64::new(10 * 3600 + 3599);
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(3600)
+        .burst_size(2)
+        .key_extractor(
+            FixedWindowKeyExtractor::hourly(PeerIpKeyExtractor)
+                .with_time_source(|| UNIX_EPOCH + Duration::from_secs(NOW.load(Ordering::SeqCst))),
+        )
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = || async {
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri("/")
+            .to_request();
+        test::call_service(&app, req).await.status()
+    };
+
+    assert_eq!(call().await, StatusCode::OK);
+    assert_eq!(call().await, StatusCode::OK);
+    assert_eq!(call().await, StatusCode::TOO_MANY_REQUESTS);
+
+    // The next hour starts with a fresh quota
+    NOW.fetch_add(1, Ordering::SeqCst);
+    assert_eq!(call().await, StatusCode::OK);
+    assert_eq!(call().await, StatusCode::OK);
+    assert_eq!(call().await, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_rt::test]
+async fn test_rejection_headers() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .rejection_headers(vec![(
+            header::LINK,
+            HeaderValue::from_static("<https://example.com/docs/rate-limits>; rel=\"help\""),
+        )])
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .peer_addr("127.0.0.1:80".parse().unwrap())
+        .uri("/")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::OK);
+    assert!(test.headers().get(header::LINK).is_none());
+
+    let req = test::TestRequest::get()
+        .peer_addr("127.0.0.1:80".parse().unwrap())
+        .uri("/")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(
+        test.headers().get(header::LINK).unwrap(),
+        "<https://example.com/docs/rate-limits>; rel=\"help\""
+    );
+}
+
+#[actix_rt::test]
+async fn test_read_write_preset() {
+    use crate::{presets, Governor, Method};
+    use actix_web::test;
+    use governor::Quota;
+    use std::num::NonZeroU32;
+
+    let config = presets::read_write(
+        Quota::per_minute(NonZeroU32::new(3).unwrap()),
+        Quota::per_minute(NonZeroU32::new(1).unwrap()),
+    )
+    .finish()
+    .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello))
+            .route("/", web::head().to(hello))
+            .route("/", web::post().to(hello)),
+    )
+    .await;
+
+    let call = |method: Method| async {
+        let req = test::TestRequest::default()
+            .method(method)
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri("/")
+            .to_request();
+        test::call_service(&app, req).await.status()
+    };
+
+    // A burst of reads doesn't deplete the write budget
+    assert_eq!(call(Method::GET).await, StatusCode::OK);
+    assert_eq!(call(Method::HEAD).await, StatusCode::OK);
+    assert_eq!(call(Method::GET).await, StatusCode::OK);
+    assert_eq!(call(Method::GET).await, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(call(Method::POST).await, StatusCode::OK);
+
+    // And writes don't deplete the read budget
+    let config = presets::read_write(
+        Quota::per_minute(NonZeroU32::new(1).unwrap()),
+        Quota::per_minute(NonZeroU32::new(1).unwrap()),
+    )
+    .finish()
+    .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello))
+            .route("/", web::post().to(hello)),
+    )
+    .await;
+
+    let call = |method: Method| async {
+        let req = test::TestRequest::default()
+            .method(method)
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri("/")
+            .to_request();
+        test::call_service(&app, req).await.status()
+    };
+
+    assert_eq!(call(Method::POST).await, StatusCode::OK);
+    assert_eq!(call(Method::POST).await, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(call(Method::GET).await, StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn test_skip_if_header() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .skip_if_header(
+            HeaderName::from_static("cdn-cache-status"),
+            HeaderValue::from_static("HIT"),
+        )
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .peer_addr("127.0.0.1:80".parse().unwrap())
+        .uri("/")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::OK);
+
+    // Cache hits bypass the limit
+    for value in ["HIT", "hit"] {
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .insert_header(("cdn-cache-status", value))
+            .uri("/")
+            .to_request();
+        let test = test::call_service(&app, req).await;
+        assert_eq!(test.status(), StatusCode::OK);
+    }
+
+    // Other requests are still limited
+    let req = test::TestRequest::get()
+        .peer_addr("127.0.0.1:80".parse().unwrap())
+        .insert_header(("cdn-cache-status", "MISS"))
+        .uri("/")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_rt::test]
+async fn test_retry_after_granularity() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use std::time::Duration;
+
+    for (seconds_per_request, expected) in [(7, 10), (25, 30), (30, 30)] {
+        let config = GovernorConfigBuilder::default()
+            .seconds_per_request(seconds_per_request)
+            .burst_size(1)
+            .retry_after_granularity(Duration::from_secs(10))
+            .finish()
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .wrap(Governor::new(&config))
+                .route("/", web::get().to(hello)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri("/")
+            .to_request();
+        let test = test::call_service(&app, req).await;
+        assert_eq!(test.status(), StatusCode::OK);
+
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri("/")
+            .to_request();
+        let test = test::call_service(&app, req).await;
+        assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let retry_after: u64 = test
+            .headers()
+            .get("retry-after")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(retry_after % 10, 0);
+        assert!(retry_after >= seconds_per_request - 1);
+        assert_eq!(retry_after, expected);
+        assert_eq!(
+            test.headers().get("x-ratelimit-after").unwrap(),
+            &retry_after.to_string()
+        );
+    }
+}
+
+#[test]
+fn test_decide() {
+    use crate::{Decision, GovernorConfigBuilder};
+    use actix_web::test;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .finish()
+        .unwrap();
+
+    let req = test::TestRequest::get()
+        .peer_addr("127.0.0.1:80".parse().unwrap())
+        .to_srv_request();
+    assert!(matches!(
+        config.decide(&req),
+        Decision::Allowed { outcome: (), .. }
+    ));
+
+    match config.decide(&req) {
+        Decision::RateLimited {
+            key,
+            negative,
+            wait_time,
+            ..
+        } => {
+            assert_eq!(key, "127.0.0.1".parse::<std::net::IpAddr>().unwrap());
+            assert!(wait_time <= 60);
+            let response = config.rejection_response(&negative);
+            assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+            assert!(response.headers().get("retry-after").is_some());
+        }
+        _ => panic!("expected the request to be rate limited"),
+    }
+
+    // Without peer address the key can't be extracted
+    let req = test::TestRequest::get().to_srv_request();
+    assert!(matches!(
+        config.decide(&req),
+        Decision::ExtractionFailed { penalty: None, .. }
+    ));
+
+    // Whitelisted keys
+    let config = GovernorConfigBuilder::default()
+        .key_extractor(WhitelistedKeyExtractor)
+        .use_headers()
+        .finish()
+        .unwrap();
+    let req = test::TestRequest::get()
+        .insert_header(("super-token", "AdminSecretToken"))
+        .to_srv_request();
+    assert!(matches!(config.decide(&req), Decision::Whitelisted));
+
+    let req = test::TestRequest::get()
+        .insert_header(("super-token", "UserToken"))
+        .to_srv_request();
+    match config.decide(&req) {
+        Decision::Allowed {
+            outcome: snapshot, ..
+        } => assert_eq!(snapshot.remaining_burst_capacity(), 7),
+        _ => panic!("expected the request to be allowed"),
+    }
+}
+
+#[actix_rt::test]
+async fn test_refund_on_status() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    async fn not_modified() -> impl Responder {
+        HttpResponse::NotModified().finish()
+    }
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(2)
+        .refund_on_status(vec![StatusCode::NOT_MODIFIED])
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello))
+            .route("/cached", web::get().to(not_modified)),
+    )
+    .await;
+
+    let call = |uri: &'static str| async {
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri(uri)
+            .to_request();
+        test::call_service(&app, req).await.status()
+    };
+
+    // 304 responses don't deplete the bucket
+    for _ in 0..5 {
+        assert_eq!(call("/cached").await, StatusCode::NOT_MODIFIED);
+    }
+
+    // 200 responses do
+    assert_eq!(call("/").await, StatusCode::OK);
+    assert_eq!(call("/").await, StatusCode::OK);
+    assert_eq!(call("/").await, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_rt::test]
+async fn test_refund_window() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use std::time::Duration;
+
+    async fn not_modified() -> impl Responder {
+        HttpResponse::NotModified().finish()
+    }
+
+    async fn slow_not_modified() -> impl Responder {
+        actix_rt::time::sleep(Duration::from_millis(100)).await;
+        HttpResponse::NotModified().finish()
+    }
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(2)
+        .refund_on_status(vec![StatusCode::NOT_MODIFIED])
+        .refund_window(Duration::from_millis(50))
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/cached", web::get().to(not_modified))
+            .route("/slow", web::get().to(slow_not_modified)),
+    )
+    .await;
+
+    let call = |uri: &'static str| async {
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri(uri)
+            .to_request();
+        test::call_service(&app, req).await.status()
+    };
+
+    // Fast responses are refunded
+    for _ in 0..5 {
+        assert_eq!(call("/cached").await, StatusCode::NOT_MODIFIED);
+    }
+
+    // Responses past the window are charged
+    assert_eq!(call("/slow").await, StatusCode::NOT_MODIFIED);
+    assert_eq!(call("/slow").await, StatusCode::NOT_MODIFIED);
+    assert_eq!(call("/cached").await, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_rt::test]
+async fn test_user_agent_class_key_extractor() {
+    use crate::{
+        Governor, GovernorConfigBuilder, PeerIpKeyExtractor, UserAgentClass,
+        UserAgentClassKeyExtractor,
+    };
+    use actix_web::http::header::USER_AGENT;
+    use actix_web::test;
+
+    fn classify(user_agent: &str) -> UserAgentClass {
+        if user_agent.contains("bot") {
+            UserAgentClass::Bot
+        } else if user_agent.starts_with("Mozilla/") {
+            UserAgentClass::Browser
+        } else {
+            UserAgentClass::Unknown
+        }
+    }
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .key_extractor(UserAgentClassKeyExtractor::new(
+            PeerIpKeyExtractor,
+            classify,
+        ))
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = |user_agent: Option<&'static str>| {
+        let mut req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri("/");
+        if let Some(user_agent) = user_agent {
+            req = req.insert_header((USER_AGENT, user_agent));
+        }
+        let req = req.to_request();
+        async { test::call_service(&app, req).await.status() }
+    };
+
+    // Bots and browsers from the same IP don't share a bucket
+    assert_eq!(call(Some("googlebot/2.1")).await, StatusCode::OK);
+    assert_eq!(
+        call(Some("googlebot/2.1")).await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(call(Some("Mozilla/5.0")).await, StatusCode::OK);
+    assert_eq!(
+        call(Some("Mozilla/5.0")).await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // Requests without a user agent are unknown, like unclassified ones
+    assert_eq!(call(None).await, StatusCode::OK);
+    assert_eq!(call(Some("curl/8.0")).await, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[cfg(feature = "debug-endpoint")]
+#[actix_rt::test]
+async fn test_debug_endpoint() {
+    use crate::{Governor, GovernorConfig, GovernorConfigBuilder, Method, PeerIpKeyExtractor};
+    use actix_web::test;
+    use governor::middleware::NoOpMiddleware;
+
+    type Config = GovernorConfig<PeerIpKeyExtractor, NoOpMiddleware>;
+
+    async fn ratelimit_config(config: web::Data<Config>) -> HttpResponse {
+        config.debug_response()
+    }
+
+    let config = GovernorConfigBuilder::default()
+        .milliseconds_per_request(500)
+        .burst_size(3)
+        .methods(vec![Method::POST])
+        .refund_on_status(vec![StatusCode::NOT_MODIFIED])
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(config.clone()))
+            .route("/admin/ratelimit/config", web::get().to(ratelimit_config))
+            .service(
+                web::scope("/")
+                    .wrap(Governor::new(&config))
+                    .route("", web::post().to(hello)),
+            ),
+    )
+    .await;
+
+    // Two clients are tracked
+    for peer in ["127.0.0.1:80", "127.0.0.2:80"] {
+        let req = test::TestRequest::post()
+            .peer_addr(peer.parse().unwrap())
+            .uri("/")
+            .to_request();
+        let test = test::call_service(&app, req).await;
+        assert_eq!(test.status(), StatusCode::OK);
+    }
+
+    let req = test::TestRequest::get()
+        .uri("/admin/ratelimit/config")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::OK);
+    assert_eq!(
+        test.headers().get(header::CONTENT_TYPE).unwrap(),
+        "application/json"
+    );
+    let body = actix_web::body::to_bytes(test.into_body()).await.unwrap();
+    assert_eq!(
+        body,
+        concat!(
+            r#"{"quota":{"burst_size":3,"period_ms":500},"tracked_keys":2,"#,
+            r#""methods":["POST"],"permissive":false,"json_response":false,"#,
+            r#""trusted_forwarders":null,"penalize_extraction_errors":false,"guard":false,"#,
+            r#""unlimited_whitelist_headers":false,"skip_header":null,"#,
+            r#""retry_after_granularity_ms":null,"refund_statuses":[304],"#,
+            r#""adaptive":false,"global_cap":false,"sample_rate":null,"draining":false,"#,
+            r#""brute_force_protection":false,"charge_timing":"before"}"#
+        )
+    );
+}
+
+#[actix_rt::test]
+async fn test_new_key_burst_bonus() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    let config = GovernorConfigBuilder::default()
+        .milliseconds_per_request(200)
+        .burst_size(2)
+        .new_key_burst_bonus(3)
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = || async {
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri("/")
+            .to_request();
+        test::call_service(&app, req).await.status()
+    };
+
+    // A brand-new key gets the bonus on top of the burst size
+    for _ in 0..5 {
+        assert_eq!(call().await, StatusCode::OK);
+    }
+    assert_eq!(call().await, StatusCode::TOO_MANY_REQUESTS);
+
+    // A returning key only gets the replenished burst
+    let sleep_time = std::time::Duration::from_millis(450);
+    std::thread::sleep(sleep_time);
+    assert_eq!(call().await, StatusCode::OK);
+    assert_eq!(call().await, StatusCode::OK);
+    assert_eq!(call().await, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_rt::test]
+async fn test_new_key_burst_bonus_rejected_key() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use governor::Quota;
+    use std::num::NonZeroU32;
+
+    let config = GovernorConfigBuilder::default()
+        .new_key_burst_bonus(3)
+        .global_cap(Quota::per_minute(NonZeroU32::MIN))
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    for (peer, status) in [
+        ("127.0.0.1:80", StatusCode::OK),
+        ("127.0.0.2:80", StatusCode::TOO_MANY_REQUESTS),
+    ] {
+        let req = test::TestRequest::get()
+            .peer_addr(peer.parse().unwrap())
+            .uri("/")
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), status);
+    }
+
+    // Checking whether a key is new doesn't create its state
+    assert_eq!(config.tracked_keys(), 1);
+}
+
+#[actix_rt::test]
+async fn test_close_connection_after_blocks() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_http::ConnectionType;
+    use actix_web::test;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .close_connection_after_blocks(2)
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = || async {
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri("/")
+            .to_request();
+        let test = test::call_service(&app, req).await;
+        (test.status(), test.response().head().connection_type())
+    };
+
+    let (status, connection) = call().await;
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(connection, ConnectionType::Close);
+
+    // The first blocks keep the connection open
+    for _ in 0..2 {
+        let (status, connection) = call().await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_ne!(connection, ConnectionType::Close);
+    }
+
+    // Further blocks close it
+    for _ in 0..2 {
+        let (status, connection) = call().await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(connection, ConnectionType::Close);
+    }
+}
+
+#[actix_rt::test]
+async fn test_adaptive() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let overloaded = Arc::new(AtomicBool::new(false));
+    let load = overloaded.clone();
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(4)
+        .adaptive(
+            move || {
+                if load.load(Ordering::Relaxed) {
+                    2.0
+                } else {
+                    0.5
+                }
+            },
+            0.25,
+        )
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = |peer: &'static str| async {
+        let req = test::TestRequest::get()
+            .peer_addr(peer.parse().unwrap())
+            .uri("/")
+            .to_request();
+        test::call_service(&app, req).await.status()
+    };
+
+    // Without load, the full quota is available
+    for _ in 0..4 {
+        assert_eq!(call("127.0.0.1:80").await, StatusCode::OK);
+    }
+    assert_eq!(call("127.0.0.1:80").await, StatusCode::TOO_MANY_REQUESTS);
+
+    // The load is sampled at most once per interval
+    overloaded.store(true, Ordering::Relaxed);
+    for _ in 0..4 {
+        assert_eq!(call("127.0.0.2:80").await, StatusCode::OK);
+    }
+    actix_rt::time::sleep(crate::ADAPTIVE_INTERVAL).await;
+
+    // Under load, requests cost more cells, but repeated requests don't tighten it further
+    for _ in 0..2 {
+        assert_eq!(call("127.0.0.3:80").await, StatusCode::OK);
+    }
+    assert_eq!(call("127.0.0.3:80").await, StatusCode::TOO_MANY_REQUESTS);
+
+    // The minimum fraction must be in `(0, 1]`
+    for min_fraction in [0.0, 1.5, f64::NAN] {
+        assert!(GovernorConfigBuilder::default()
+            .adaptive(|| 0.0, min_fraction)
+            .finish()
+            .is_none());
+    }
+}
+
+#[actix_rt::test]
+async fn test_adaptive_refund_on_status() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(4)
+        .refund_on_status(vec![StatusCode::INTERNAL_SERVER_ERROR])
+        // Always overloaded, so every request costs two cells
+        .adaptive(|| 2.0, 0.5)
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello))
+            .route("/fail", web::get().to(HttpResponse::InternalServerError)),
+    )
+    .await;
+
+    let call = |uri: &'static str| {
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri(uri)
+            .to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await.status() }
+    };
+
+    // Refunded requests give back all of their cells
+    for _ in 0..4 {
+        assert_eq!(call("/fail").await, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    assert_eq!(call("/").await, StatusCode::OK);
+    assert_eq!(call("/").await, StatusCode::OK);
+    assert_eq!(call("/").await, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[test]
+fn test_simulate() {
+    use crate::{GovernorConfigBuilder, SimulationTally};
+    use std::net::IpAddr;
+    use std::time::{Duration, Instant};
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(1)
+        .burst_size(3)
+        .finish()
+        .unwrap();
+
+    let burst: IpAddr = "10.0.0.1".parse().unwrap();
+    let steady: IpAddr = "10.0.0.2".parse().unwrap();
+    let start = Instant::now();
+
+    // A burst of ten requests within 100ms, then another one after the quota replenished
+    let mut trace: Vec<_> = (0..10)
+        .map(|i| (burst, start + Duration::from_millis(i * 10)))
+        .collect();
+    trace.push((burst, start + Duration::from_secs(5)));
+    // One request per second never exceeds the quota
+    trace.extend((0..6).map(|i| (steady, start + Duration::from_secs(i))));
+    trace.sort_by_key(|(_, time)| *time);
+
+    let report = config.simulate(&trace);
+    assert_eq!(
+        report.keys[&burst],
+        SimulationTally {
+            allowed: 4,
+            blocked: 7
+        }
+    );
+    assert_eq!(
+        report.keys[&steady],
+        SimulationTally {
+            allowed: 6,
+            blocked: 0
+        }
+    );
+    assert_eq!(report.total().allowed, 10);
+
+    // The state of the middleware is not affected
+    let req = actix_web::test::TestRequest::get()
+        .peer_addr("10.0.0.1:80".parse().unwrap())
+        .to_srv_request();
+    assert!(matches!(
+        config.decide(&req),
+        crate::Decision::Allowed { .. }
+    ));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SoftLimitKeyExtractor;
+
+impl KeyExtractor for SoftLimitKeyExtractor {
+    type Key = String;
+    type KeyExtractionError = SimpleKeyExtractionError<String>;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        "test"
+    }
+
+    fn extract(
+        &self,
+        req: &actix_web::dev::ServiceRequest,
+    ) -> Result<Self::Key, Self::KeyExtractionError> {
+        req.headers()
+            .get("x-service")
+            .map(|v| v.to_str().unwrap().to_owned())
+            .ok_or_else(|| SimpleKeyExtractionError::new("Missing x-service header".to_owned()))
+    }
+
+    fn is_soft_limited(&self, key: &Self::Key) -> bool {
+        key.starts_with("internal-")
+    }
+}
+
+#[actix_rt::test]
+async fn test_soft_limited_keys() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    async fn result(req: actix_web::HttpRequest) -> impl Responder {
+        use actix_web::HttpMessage;
+        match req
+            .extensions()
+            .get::<GovernorResult<SimpleKeyExtractionError<String>>>()
+        {
+            Some(GovernorResult::Wait { .. }) => "exceeded",
+            _ => "ok",
+        }
+    }
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .key_extractor(SoftLimitKeyExtractor)
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(result)),
+    )
+    .await;
+
+    let call = |service: &'static str| {
+        let req = test::TestRequest::get()
+            .insert_header(("x-service", service))
+            .uri("/")
+            .to_request();
+        async {
+            let test = test::call_service(&app, req).await;
+            let status = test.status();
+            let body = actix_web::body::to_bytes(test.into_body()).await.unwrap();
+            (status, body)
+        }
+    };
+
+    // A soft-limited key passes through on overflow, but the service can see it
+    assert_eq!(
+        call("internal-billing").await,
+        (StatusCode::OK, "ok".into())
+    );
+    assert_eq!(
+        call("internal-billing").await,
+        (StatusCode::OK, "exceeded".into())
+    );
+
+    // A normal key is rejected
+    assert_eq!(call("partner").await, (StatusCode::OK, "ok".into()));
+    assert_eq!(call("partner").await.0, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_rt::test]
+async fn test_audit_sink() {
+    use crate::{Governor, GovernorConfigBuilder, RateLimitOutcome};
+    use actix_web::test;
+    use std::net::IpAddr;
+
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(16);
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .audit_sink(sender)
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello))
+            .route("/other", web::get().to(hello)),
+    )
+    .await;
+
+    for uri in ["/", "/other", "/"] {
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri(uri)
+            .to_request();
+        test::call_service(&app, req).await;
+    }
+    let req = test::TestRequest::get().uri("/").to_request();
+    let _ = app.call(req).await;
+
+    let ip: IpAddr = "127.0.0.1".parse().unwrap();
+    let expected = [
+        (Some(ip), RateLimitOutcome::Allowed, "/"),
+        (Some(ip), RateLimitOutcome::RateLimited, "/other"),
+        (Some(ip), RateLimitOutcome::RateLimited, "/"),
+        (None, RateLimitOutcome::ExtractionFailed, "/"),
+    ];
+    for (key, outcome, path) in expected {
+        let event = receiver.try_recv().unwrap();
+        assert_eq!(event.key, key);
+        assert_eq!(event.outcome, outcome);
+        assert_eq!(event.path, path);
+    }
+    assert!(receiver.try_recv().is_err());
+}
+
+#[test]
+fn test_audit_sink_before_key_extractor() {
+    use crate::{GlobalKeyExtractor, GovernorConfigBuilder};
+
+    let (sender, _receiver) = tokio::sync::mpsc::channel(16);
+
+    assert!(GovernorConfigBuilder::default()
+        .audit_sink(sender)
+        .key_extractor(GlobalKeyExtractor)
+        .finish()
+        .is_none());
+}
+
+#[test]
+fn test_quota_extractor_before_key_extractor() {
+    use crate::{GovernorConfigBuilder, PathKeyExtractor};
+    use governor::Quota;
+    use std::net::IpAddr;
+    use std::num::NonZeroU32;
+
+    assert!(GovernorConfigBuilder::default()
+        .quota_extractor(|_: &IpAddr| Quota::per_second(NonZeroU32::MIN))
+        .key_extractor(PathKeyExtractor)
+        .finish()
+        .is_none());
+}
+
+#[test]
+fn test_quota_provider_before_key_extractor() {
+    use crate::{GovernorConfigBuilder, PeerIpKeyExtractor};
+    use governor::Quota;
+    use std::net::IpAddr;
+    use std::num::NonZeroU32;
+    use std::time::Duration;
+
+    // The flag is kept when the key extractor is changed again
+    assert!(GovernorConfigBuilder::default()
+        .quota_provider(
+            |_: &IpAddr| async { Quota::per_second(NonZeroU32::MIN) },
+            Duration::from_secs(60),
+        )
+        .per_endpoint()
+        .key_extractor(PeerIpKeyExtractor)
+        .finish()
+        .is_none());
+}
+
+#[actix_rt::test]
+async fn test_limit_only_above_content_length() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    for limit_unknown in [false, true] {
+        let config = GovernorConfigBuilder::default()
+            .seconds_per_request(60)
+            .burst_size(1)
+            .limit_only_above_content_length(1024, limit_unknown)
+            .finish()
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .wrap(Governor::new(&config))
+                .route("/", web::post().to(hello)),
+        )
+        .await;
+
+        let call = |length: Option<usize>| {
+            let mut req = test::TestRequest::post()
+                .peer_addr("127.0.0.1:80".parse().unwrap())
+                .uri("/");
+            if let Some(length) = length {
+                req = req.set_payload(vec![0; length]);
+            }
+            let req = req.to_request();
+            async { test::call_service(&app, req).await.status() }
+        };
+
+        // Small requests are never blocked
+        for _ in 0..5 {
+            assert_eq!(call(Some(1024)).await, StatusCode::OK);
+        }
+
+        // Large ones are
+        assert_eq!(call(Some(1025)).await, StatusCode::OK);
+        assert_eq!(call(Some(4096)).await, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(call(Some(16)).await, StatusCode::OK);
+
+        // Requests without a content length depend on the configuration
+        let status = call(None).await;
+        if limit_unknown {
+            assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        } else {
+            assert_eq!(status, StatusCode::OK);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct MethodKeyExtractor;
+
+impl KeyExtractor for MethodKeyExtractor {
+    type Key = String;
+    type KeyExtractionError = SimpleKeyExtractionError<String>;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        "test"
+    }
+
+    fn extract(
+        &self,
+        req: &actix_web::dev::ServiceRequest,
+    ) -> Result<Self::Key, Self::KeyExtractionError> {
+        Ok(req.method().to_string())
+    }
+}
+
+#[actix_rt::test]
+async fn test_treat_head_as_get() {
+    use crate::{Governor, GovernorConfigBuilder, Method};
+    use actix_web::test;
+
+    async fn method(req: actix_web::HttpRequest) -> impl Responder {
+        req.method().to_string()
+    }
+
+    for treat_head_as_get in [false, true] {
+        let mut builder = GovernorConfigBuilder::default();
+        builder.seconds_per_request(60).burst_size(1);
+        if treat_head_as_get {
+            builder.treat_head_as_get();
+        }
+        let config = builder.key_extractor(MethodKeyExtractor).finish().unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .wrap(Governor::new(&config))
+                .route("/", web::head().to(method))
+                .route("/", web::get().to(method)),
+        )
+        .await;
+
+        let call = |method: Method| async {
+            let req = test::TestRequest::default()
+                .method(method)
+                .uri("/")
+                .to_request();
+            test::call_service(&app, req).await
+        };
+
+        // The service still sees the original method
+        let test = call(Method::HEAD).await;
+        assert_eq!(test.status(), StatusCode::OK);
+        assert_eq!(test.request().method(), Method::HEAD);
+
+        let status = call(Method::GET).await.status();
+        if treat_head_as_get {
+            assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        } else {
+            assert_eq!(status, StatusCode::OK);
+        }
+    }
+}
+
+#[test]
+fn test_is_whitelisted() {
+    use crate::GovernorConfigBuilder;
+
+    let config = GovernorConfigBuilder::default()
+        .key_extractor(WhitelistedKeyExtractor)
+        .finish()
+        .unwrap();
+
+    assert!(config.is_whitelisted(&"AdminSecretToken".to_owned()));
+    assert!(config.is_whitelisted(&"AdminSecretToken2".to_owned()));
+    assert!(!config.is_whitelisted(&"UserToken".to_owned()));
+
+    let config = GovernorConfigBuilder::default().finish().unwrap();
+    assert!(!config.is_whitelisted(&"127.0.0.1".parse().unwrap()));
+}
+
+#[actix_rt::test]
+async fn test_fixed_window_whitelisted_key() {
+    use crate::{FixedWindowKeyExtractor, Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    static NOW: AtomicU64 = AtomicU64::new(10 * 3600 + 3599);
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(3600)
+        .burst_size(1)
+        .key_extractor(
+            FixedWindowKeyExtractor::hourly(WhitelistedKeyExtractor)
+                .with_time_source(|| UNIX_EPOCH + Duration::from_secs(NOW.load(Ordering::SeqCst))),
+        )
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = |token| {
+        let app = &app;
+        async move {
+            let req = test::TestRequest::get()
+                .insert_header(("super-token", token))
+                .uri("/")
+                .to_request();
+            test::call_service(app, req).await.status()
+        }
+    };
+
+    assert!(config.is_whitelisted(&(10, "AdminSecretToken".to_owned())));
+    assert_eq!(call("AdminSecretToken").await, StatusCode::OK);
+    assert_eq!(call("AdminSecretToken").await, StatusCode::OK);
+    assert_eq!(call("UserToken").await, StatusCode::OK);
+    assert_eq!(call("UserToken").await, StatusCode::TOO_MANY_REQUESTS);
+
+    // The whitelist still applies after the window changed
+    NOW.fetch_add(1, Ordering::SeqCst);
+    assert!(config.is_whitelisted(&(11, "AdminSecretToken".to_owned())));
+    assert_eq!(call("AdminSecretToken").await, StatusCode::OK);
+    assert_eq!(call("AdminSecretToken").await, StatusCode::OK);
+    assert_eq!(call("UserToken").await, StatusCode::OK);
+    assert_eq!(call("UserToken").await, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_rt::test]
+async fn test_daily_window_key_extractor() {
+    use crate::{FixedWindowKeyExtractor, Governor, GovernorConfigBuilder, PeerIpKeyExtractor};
+    use actix_web::test;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    // 2024-01-01 22:59:59 UTC
+    static NOW: AtomicU64 = AtomicU64::new(1_704_149_999);
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(86400)
+        .burst_size(2)
+        .key_extractor(
+            FixedWindowKeyExtractor::daily(PeerIpKeyExtractor)
+                .utc_offset(3600)
+                .with_time_source(|| UNIX_EPOCH + Duration::from_secs(NOW.load(Ordering::SeqCst))),
+        )
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = || async {
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri("/")
+            .to_request();
+        test::call_service(&app, req).await.status()
+    };
+
+    assert_eq!(call().await, StatusCode::OK);
+    assert_eq!(call().await, StatusCode::OK);
+    assert_eq!(call().await, StatusCode::TOO_MANY_REQUESTS);
+
+    // The cap resets at midnight UTC+1
+    NOW.fetch_add(1, Ordering::SeqCst);
+    assert_eq!(call().await, StatusCode::OK);
+    assert_eq!(call().await, StatusCode::OK);
+    assert_eq!(call().await, StatusCode::TOO_MANY_REQUESTS);
+
+    // But not an hour later at midnight UTC
+    NOW.fetch_add(3600, Ordering::SeqCst);
+    assert_eq!(call().await, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_rt::test]
+async fn test_max_new_keys_per_ip() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use std::time::Duration;
+
+    let config = GovernorConfigBuilder::default()
+        .key_extractor(WhitelistedKeyExtractor)
+        .max_new_keys_per_ip(2, Duration::from_secs(60))
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = |peer: &'static str, token: &'static str| {
+        let req = test::TestRequest::get()
+            .peer_addr(peer.parse().unwrap())
+            .insert_header(("super-token", token))
+            .uri("/")
+            .to_request();
+        async {
+            match app.call(req).await {
+                Ok(response) => response.status(),
+                Err(err) => err.as_response_error().status_code(),
+            }
+        }
+    };
+
+    assert_eq!(call("127.0.0.1:80", "a").await, StatusCode::OK);
+    assert_eq!(call("127.0.0.1:80", "b").await, StatusCode::OK);
+
+    // The IP can't introduce further keys
+    assert_eq!(
+        call("127.0.0.1:80", "c").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(
+        call("127.0.0.1:80", "d").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // But its known keys and whitelisted keys keep working
+    assert_eq!(call("127.0.0.1:80", "a").await, StatusCode::OK);
+    assert_eq!(
+        call("127.0.0.1:80", "AdminSecretToken").await,
+        StatusCode::OK
+    );
+
+    // Other IPs are not affected
+    assert_eq!(call("127.0.0.2:80", "c").await, StatusCode::OK);
+
+    // Resetting a key frees its slot
+    config.reset_key(&"b".to_owned());
+    assert_eq!(call("127.0.0.1:80", "c").await, StatusCode::OK);
+    assert_eq!(
+        call("127.0.0.1:80", "b").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+}
+
+#[actix_rt::test]
+async fn test_combined_header() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    for replace in [false, true] {
+        let config = GovernorConfigBuilder::default()
+            .seconds_per_request(10)
+            .burst_size(3)
+            .use_headers()
+            .combined_header(replace)
+            .finish()
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .wrap(Governor::new(&config))
+                .route("/", web::get().to(hello)),
+        )
+        .await;
+
+        let call = || async {
+            let req = test::TestRequest::get()
+                .peer_addr("127.0.0.1:80".parse().unwrap())
+                .uri("/")
+                .to_request();
+            test::call_service(&app, req).await
+        };
+
+        let test = call().await;
+        assert_eq!(test.status(), StatusCode::OK);
+        assert_eq!(
+            test.headers().get("x-ratelimit").unwrap(),
+            "limit=3, remaining=2, reset=10"
+        );
+        assert_eq!(
+            test.headers().contains_key("x-ratelimit-remaining"),
+            !replace
+        );
+
+        call().await;
+        call().await;
+        let test = call().await;
+        assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+        let wait_time: u64 = test
+            .headers()
+            .get("retry-after")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(
+            test.headers().get("x-ratelimit").unwrap(),
+            format!("limit=3, remaining=0, reset={}", wait_time + 20).as_str()
+        );
+        assert_eq!(test.headers().contains_key("x-ratelimit-limit"), !replace);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct HumanizedKeyExtractor;
+
+impl KeyExtractor for HumanizedKeyExtractor {
+    type Key = ();
+    type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        "test"
+    }
+
+    fn extract(
+        &self,
+        _req: &actix_web::dev::ServiceRequest,
+    ) -> Result<Self::Key, Self::KeyExtractionError> {
+        Ok(())
+    }
+
+    fn format_retry_after(&self, wait: std::time::Duration) -> String {
+        match wait.as_secs() {
+            0..=9 => "a few seconds".to_owned(),
+            10..=89 => "about a minute".to_owned(),
+            secs => format!("about {} minutes", secs.div_ceil(60)),
+        }
+    }
+}
+
+#[actix_rt::test]
+async fn test_format_retry_after() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .key_extractor(HumanizedKeyExtractor)
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let req = test::TestRequest::get().uri("/").to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::OK);
+
+    let req = test::TestRequest::get().uri("/").to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+    let body = actix_web::body::to_bytes(test.into_body()).await.unwrap();
+    assert_eq!(body, "Too many requests, retry in about a minute");
+}
+
+#[actix_rt::test]
+async fn test_violation_grace() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .violation_grace(2)
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = || async {
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri("/")
+            .to_request();
+        let test = test::call_service(&app, req).await;
+        (
+            test.status(),
+            test.headers().contains_key("x-ratelimit-warning"),
+        )
+    };
+
+    assert_eq!(call().await, (StatusCode::OK, false));
+
+    // The first violations pass with a warning
+    for _ in 0..2 {
+        assert_eq!(call().await, (StatusCode::OK, true));
+    }
+
+    // Further ones are rejected
+    assert_eq!(call().await, (StatusCode::TOO_MANY_REQUESTS, false));
+}
+
+#[actix_rt::test]
+async fn test_per_endpoint() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .per_endpoint()
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello))
+            .route("/users/{id}", web::get().to(hello)),
+    )
+    .await;
+
+    let call = |uri: &str| {
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri(uri)
+            .to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await.status() }
+    };
+
+    assert_eq!(call("/").await, StatusCode::OK);
+    assert_eq!(call("/").await, StatusCode::TOO_MANY_REQUESTS);
+
+    // Another endpoint has its own budget, shared by all of its paths
+    assert_eq!(call("/users/1").await, StatusCode::OK);
+    assert_eq!(call("/users/2").await, StatusCode::TOO_MANY_REQUESTS);
+
+    // Unmatched paths share one budget
+    assert_eq!(call("/missing").await, StatusCode::NOT_FOUND);
+    assert_eq!(call("/other").await, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_rt::test]
+async fn test_help_link() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .help_link("https://docs.example.com/ratelimits")
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .peer_addr("127.0.0.1:80".parse().unwrap())
+        .uri("/")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::OK);
+    assert!(test.headers().get(header::LINK).is_none());
+
+    let req = test::TestRequest::get()
+        .peer_addr("127.0.0.1:80".parse().unwrap())
+        .uri("/")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(
+        test.headers().get(header::LINK).unwrap(),
+        "<https://docs.example.com/ratelimits>; rel=\"help\""
+    );
+}
+
+#[test]
+fn test_either_key() {
+    use crate::EitherKey;
+    use std::collections::HashSet;
+
+    let left = EitherKey::<String, String>::Left("alice".to_owned());
+    let right = EitherKey::<String, String>::Right("alice".to_owned());
+    assert_ne!(left, right);
+
+    let keys: HashSet<_> = [left.clone(), right.clone(), left].into_iter().collect();
+    assert_eq!(keys.len(), 2);
+    assert!(keys.contains(&right));
+}
+
+#[actix_rt::test]
+async fn test_either_key_extractor() {
+    use crate::{
+        EitherKeyExtractor, Governor, GovernorConfigBuilder, PeerIpKeyExtractor, UserKeyExtractor,
+        UserSource,
+    };
+    use actix_web::test;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .key_extractor(EitherKeyExtractor::new(
+            UserKeyExtractor::new(UserSource::Header(HeaderName::from_static("x-api-key"))),
+            EitherKeyExtractor::new(
+                UserKeyExtractor::new(UserSource::Header(HeaderName::from_static("x-user"))),
+                PeerIpKeyExtractor,
+            ),
+        ))
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = |header: Option<&'static str>| {
+        let mut req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri("/");
+        if let Some(name) = header {
+            req = req.insert_header((name, "alice"));
+        }
+        let req = req.to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await.status() }
+    };
+
+    // An API key and a user with the same value have separate buckets
+    assert_eq!(call(Some("x-api-key")).await, StatusCode::OK);
+    assert_eq!(call(Some("x-api-key")).await, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(call(Some("x-user")).await, StatusCode::OK);
+    assert_eq!(call(Some("x-user")).await, StatusCode::TOO_MANY_REQUESTS);
+
+    // Requests without either header fall back to the IP
+    assert_eq!(call(None).await, StatusCode::OK);
+    assert_eq!(call(None).await, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_rt::test]
+async fn test_quota_whitelist_headers() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    let config = GovernorConfigBuilder::default()
+        .key_extractor(WhitelistedKeyExtractor)
+        .burst_size(5)
+        .quota_whitelist_headers()
+        .use_headers()
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    for _ in 0..10 {
+        let req = test::TestRequest::get()
+            .insert_header(("super-token", "AdminSecretToken"))
+            .uri("/")
+            .to_request();
+        let test = test::call_service(&app, req).await;
+        assert_eq!(test.status(), StatusCode::OK);
+        assert_eq!(
+            test.headers().get("x-ratelimit-whitelisted").unwrap(),
+            "true"
+        );
+        assert_eq!(test.headers().get("x-ratelimit-limit").unwrap(), "5");
+        assert_eq!(test.headers().get("x-ratelimit-remaining").unwrap(), "5");
+        // The quota is full
+        assert_eq!(test.headers().get("x-ratelimit-reset").unwrap(), "0");
+    }
+}
+
+#[actix_rt::test]
+async fn test_brute_force_protection() {
+    use crate::{Governor, GovernorConfigBuilder, PeerIpKeyExtractor};
+    use actix_web::{test, HttpRequest, HttpResponse};
+    use governor::Quota;
+    use std::num::NonZeroU32;
+
+    async fn login(req: HttpRequest) -> HttpResponse {
+        match req.headers().get("password") {
+            Some(password) if password == "correct" => HttpResponse::Ok().finish(),
+            _ => HttpResponse::Unauthorized().finish(),
+        }
+    }
+
+    let quota = Quota::with_period(std::time::Duration::from_secs(60))
+        .unwrap()
+        .allow_burst(NonZeroU32::new(2).unwrap());
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(8)
+        .brute_force_protection(
+            PeerIpKeyExtractor,
+            quota,
+            vec![StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN],
+        )
+        // The configured quota is kept
+        .burst_size(4)
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/login", web::post().to(login)),
+    )
+    .await;
+
+    let call = |ip: &str, password: &'static str| {
+        let req = test::TestRequest::post()
+            .peer_addr(format!("{}:80", ip).parse().unwrap())
+            .insert_header(("password", password))
+            .uri("/login")
+            .to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await.status() }
+    };
+
+    // Successful logins don't deplete the quota of failed attempts
+    for _ in 0..3 {
+        assert_eq!(call("127.0.0.1", "correct").await, StatusCode::OK);
+    }
+
+    // Failed attempts do
+    for _ in 0..2 {
+        assert_eq!(call("127.0.0.2", "wrong").await, StatusCode::UNAUTHORIZED);
+    }
+    assert_eq!(
+        call("127.0.0.2", "wrong").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(
+        call("127.0.0.2", "correct").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // The configured quota still applies next to it
+    assert_eq!(call("127.0.0.1", "correct").await, StatusCode::OK);
+    assert_eq!(
+        call("127.0.0.1", "correct").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(config.quota.burst_size().get(), 4);
+}
+
+#[actix_rt::test]
+async fn test_brute_force_protection_reset_key() {
+    use crate::{Governor, GovernorConfigBuilder, PeerIpKeyExtractor};
+    use actix_web::{test, HttpResponse};
+    use governor::Quota;
+    use std::num::NonZeroU32;
+
+    let quota = Quota::with_period(std::time::Duration::from_secs(60))
+        .unwrap()
+        .allow_burst(NonZeroU32::new(1).unwrap());
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(8)
+        .brute_force_protection(PeerIpKeyExtractor, quota, vec![StatusCode::UNAUTHORIZED])
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/login", web::post().to(HttpResponse::Unauthorized)),
+    )
+    .await;
+
+    let call = || {
+        let req = test::TestRequest::post()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri("/login")
+            .to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await.status() }
+    };
+
+    assert_eq!(call().await, StatusCode::UNAUTHORIZED);
+    assert_eq!(call().await, StatusCode::TOO_MANY_REQUESTS);
+
+    // Resetting the key drops its failed attempts as well
+    config.reset_key(&"127.0.0.1".parse().unwrap());
+    assert_eq!(call().await, StatusCode::UNAUTHORIZED);
+    assert_eq!(call().await, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[cfg(feature = "log")]
+#[actix_rt::test]
+async fn test_key_memory_warn_threshold() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    capture_logs();
+
+    let config = GovernorConfigBuilder::default()
+        .key_memory_warn_threshold(3)
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let warnings = || {
+        LOGS.lock()
+            .unwrap()
+            .iter()
+            .filter(|(level, message)| {
+                *level == log::Level::Warn && message.contains("warning threshold of 3")
+            })
+            .count()
+    };
+
+    for ip in 1..=5 {
+        let req = test::TestRequest::get()
+            .peer_addr(format!("127.0.1.{}:80", ip).parse().unwrap())
+            .uri("/")
+            .to_request();
+        let test = test::call_service(&app, req).await;
+        assert_eq!(test.status(), StatusCode::OK);
+        assert_eq!(warnings(), usize::from(ip >= 3));
+    }
+    assert_eq!(config.tracked_keys(), 5);
+}
+
+/// Counts the clones of [`CloneCountingKey`]s.
+static KEY_CLONES: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+/// A large key that counts how often it is cloned.
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct CloneCountingKey(String);
+
+impl Clone for CloneCountingKey {
+    fn clone(&self) -> Self {
+        KEY_CLONES.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Self(self.0.clone())
+    }
+}
+
+#[derive(Clone)]
+struct CloneCountingKeyExtractor;
+
+impl KeyExtractor for CloneCountingKeyExtractor {
+    type Key = CloneCountingKey;
+    type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn name(&self) -> &'static str {
+        "clone counting"
+    }
+
+    fn extract(
+        &self,
+        _req: &actix_web::dev::ServiceRequest,
+    ) -> Result<Self::Key, Self::KeyExtractionError> {
+        Ok(CloneCountingKey("x".repeat(4096)))
+    }
+}
+
+#[actix_rt::test]
+async fn test_key_clones() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use std::sync::atomic::Ordering;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(5)
+        .key_extractor(CloneCountingKeyExtractor)
+        .use_headers()
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = || async {
+        let before = KEY_CLONES.load(Ordering::Relaxed);
+        let req = test::TestRequest::get().uri("/").to_request();
+        let status = test::call_service(&app, req).await.status();
+        (status, KEY_CLONES.load(Ordering::Relaxed) - before)
+    };
+
+    // The key is cloned once to store the state of the new key
+    assert_eq!(call().await, (StatusCode::OK, 1));
+
+    // Known keys aren't cloned, whether they are allowed or rejected
+    for _ in 0..4 {
+        assert_eq!(call().await, (StatusCode::OK, 0));
+    }
+    assert_eq!(call().await, (StatusCode::TOO_MANY_REQUESTS, 0));
+}
+
+#[test]
+fn test_x_forwarded_for_trusted_ranges() {
+    use crate::XForwardedForKeyExtractor;
+    use actix_web::test;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+    let extractor = XForwardedForKeyExtractor::new(Vec::new()).trusted_ranges(vec![
+        (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 8),
+        (IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 0)), 8),
+    ]);
+    let extract = |peer: IpAddr, chain: &str| {
+        let req = test::TestRequest::get()
+            .peer_addr(SocketAddr::new(peer, 80))
+            .insert_header((header::X_FORWARDED_FOR, chain))
+            .to_srv_request();
+        extractor.extract(&req).unwrap()
+    };
+    let client = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7));
+
+    // Proxies in the ranges are skipped, clients can't spoof addresses left of their own
+    let peer = IpAddr::V4(Ipv4Addr::new(10, 1, 2, 3));
+    assert_eq!(extract(peer, "1.1.1.1, 203.0.113.7, 10.0.0.1"), client);
+    let peer = IpAddr::V6(Ipv6Addr::new(0xfd12, 0, 0, 0, 0, 0, 0, 1));
+    assert_eq!(extract(peer, "203.0.113.7, 10.255.0.1"), client);
+
+    // Peers outside of the ranges are not trusted
+    let peer = IpAddr::V4(Ipv4Addr::new(11, 0, 0, 1));
+    assert_eq!(extract(peer, "203.0.113.7"), peer);
+    let peer = IpAddr::V6(Ipv6Addr::new(0xfe00, 0, 0, 0, 0, 0, 0, 1));
+    assert_eq!(
+        extract(peer, "203.0.113.7"),
+        IpAddr::V6(Ipv6Addr::new(0xfe00, 0, 0, 0, 0, 0, 0, 0))
+    );
+}
+
+#[actix_rt::test]
+async fn test_charge_timing() {
+    use crate::{ChargeTiming, Governor, GovernorConfigBuilder};
+    use actix_web::{test, HttpResponse};
+    use tokio::sync::Notify;
+
+    async fn slow(done: web::Data<Notify>) -> HttpResponse {
+        done.notified().await;
+        HttpResponse::Ok().finish()
+    }
+
+    async fn not_modified() -> HttpResponse {
+        HttpResponse::NotModified().finish()
+    }
+
+    for (timing, concurrent_status) in [
+        (ChargeTiming::Before, StatusCode::TOO_MANY_REQUESTS),
+        (ChargeTiming::After, StatusCode::OK),
+    ] {
+        let config = GovernorConfigBuilder::default()
+            .seconds_per_request(60)
+            .burst_size(1)
+            .refund_on_status(vec![StatusCode::NOT_MODIFIED])
+            .charge_timing(timing)
+            .finish()
+            .unwrap();
+
+        let done = web::Data::new(Notify::new());
+        let app = test::init_service(
+            App::new()
+                .app_data(done.clone())
+                .wrap(Governor::new(&config))
+                .route("/", web::get().to(hello))
+                .route("/slow", web::get().to(slow))
+                .route("/cached", web::get().to(not_modified)),
+        )
+        .await;
+
+        let call = |uri: &str| {
+            let req = test::TestRequest::get()
+                .peer_addr("127.0.0.1:80".parse().unwrap())
+                .uri(uri)
+                .to_request();
+            let app = &app;
+            async move { test::call_service(app, req).await.status() }
+        };
+
+        // Responses that are refunded are never charged
+        for _ in 0..3 {
+            assert_eq!(call("/cached").await, StatusCode::NOT_MODIFIED);
+        }
+
+        // A running request only holds the quota if it was charged before
+        let (slow_status, status) = futures::join!(call("/slow"), async {
+            let status = call("/").await;
+            done.notify_one();
+            status
+        });
+        assert_eq!(slow_status, StatusCode::OK);
+        assert_eq!(status, concurrent_status);
+
+        // Either way, the charged responses exhausted the quota
+        assert_eq!(call("/").await, StatusCode::TOO_MANY_REQUESTS);
+    }
+}
+
+#[test]
+fn test_header_key_extractor() {
+    use crate::HeaderKeyExtractor;
+    use actix_web::test;
+
+    let extractor = HeaderKeyExtractor::new("x-api-key");
+
+    let req = test::TestRequest::get()
+        .insert_header(("x-api-key", "secret-key"))
+        .to_srv_request();
+    assert_eq!(extractor.extract(&req).unwrap(), "secret-key");
+
+    // Missing header
+    let req = test::TestRequest::get().to_srv_request();
+    let err = extractor.extract(&req).unwrap_err();
+    assert_eq!(err.status_code, StatusCode::UNAUTHORIZED);
+
+    // Non-ASCII values are used if they are valid UTF-8
+    let req = test::TestRequest::get()
+        .insert_header((
+            "x-api-key",
+            HeaderValue::from_bytes("schlüssel".as_bytes()).unwrap(),
+        ))
+        .to_srv_request();
+    assert_eq!(extractor.extract(&req).unwrap(), "schlüssel");
+
+    let req = test::TestRequest::get()
+        .insert_header(("x-api-key", HeaderValue::from_bytes(&[0xff, 0xfe]).unwrap()))
+        .to_srv_request();
+    let err = extractor.extract(&req).unwrap_err();
+    assert_eq!(err.status_code, StatusCode::UNAUTHORIZED);
+}
+
+#[actix_rt::test]
+async fn test_tenant_key_extractor() {
+    use crate::{
+        Governor, GovernorConfigBuilder, HeaderKeyExtractor, PeerIpKeyExtractor, TenantKeyExtractor,
+    };
+    use actix_web::test;
+    use governor::Quota;
+    use std::num::NonZeroU32;
+
+    let premium = Quota::with_period(std::time::Duration::from_secs(60))
+        .unwrap()
+        .allow_burst(NonZeroU32::new(3).unwrap());
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .key_extractor(
+            TenantKeyExtractor::new(HeaderKeyExtractor::new("x-tenant"), PeerIpKeyExtractor)
+                .tenant_quota("premium".to_owned(), premium),
+        )
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = |tenant: &'static str| {
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .insert_header(("x-tenant", tenant))
+            .uri("/")
+            .to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await.status() }
+    };
+
+    // The same client is limited separately by each tenant
+    assert_eq!(call("free").await, StatusCode::OK);
+    assert_eq!(call("free").await, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(call("other").await, StatusCode::OK);
+    assert_eq!(call("other").await, StatusCode::TOO_MANY_REQUESTS);
+
+    // And with the quota of the tenant
+    for _ in 0..3 {
+        assert_eq!(call("premium").await, StatusCode::OK);
+    }
+    assert_eq!(call("premium").await, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_rt::test]
+async fn test_policy_name() {
+    use crate::{Governor, GovernorConfigBuilder, PeerIpKeyExtractor, ReadWriteKeyExtractor};
+    use actix_web::test;
+    use governor::Quota;
+    use std::num::NonZeroU32;
+
+    let read = Quota::with_period(std::time::Duration::from_secs(60))
+        .unwrap()
+        .allow_burst(NonZeroU32::new(2).unwrap());
+    let write = Quota::with_period(std::time::Duration::from_secs(60))
+        .unwrap()
+        .allow_burst(NonZeroU32::new(1).unwrap());
+    let config = GovernorConfigBuilder::default()
+        .key_extractor(ReadWriteKeyExtractor::new(PeerIpKeyExtractor, read, write))
+        .policy_name(read, "read-tier")
+        .policy_name(write, "write-tier")
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello))
+            .route("/", web::post().to(hello)),
+    )
+    .await;
+
+    let call = |req: test::TestRequest| {
+        let req = req
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri("/")
+            .to_request();
+        let app = &app;
+        async move {
+            let test = test::call_service(app, req).await;
+            let policy = test.headers().get("x-ratelimit-policy-name").cloned();
+            (test.status(), policy.unwrap())
+        }
+    };
+
+    for _ in 0..2 {
+        assert_eq!(
+            call(test::TestRequest::get()).await,
+            (StatusCode::OK, HeaderValue::from_static("read-tier"))
+        );
+    }
+    assert_eq!(
+        call(test::TestRequest::get()).await,
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            HeaderValue::from_static("read-tier")
+        )
+    );
+    assert_eq!(
+        call(test::TestRequest::post()).await,
+        (StatusCode::OK, HeaderValue::from_static("write-tier"))
+    );
+    assert_eq!(
+        call(test::TestRequest::post()).await,
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            HeaderValue::from_static("write-tier")
+        )
+    );
+}
+
+#[actix_rt::test]
+async fn test_quota_extractor() {
+    use crate::{Governor, GovernorConfigBuilder, HeaderKeyExtractor};
+    use actix_web::test;
+    use governor::Quota;
+    use std::num::NonZeroU32;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .key_extractor(HeaderKeyExtractor::new("x-api-key"))
+        .quota_extractor(|key: &String| {
+            let burst = if key.starts_with("premium-") { 3 } else { 1 };
+            Quota::with_period(std::time::Duration::from_secs(60))
+                .unwrap()
+                .allow_burst(NonZeroU32::new(burst).unwrap())
+        })
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = |key: &'static str| {
+        let req = test::TestRequest::get()
+            .insert_header(("x-api-key", key))
+            .uri("/")
+            .to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await.status() }
+    };
+
+    assert_eq!(call("free-1").await, StatusCode::OK);
+    assert_eq!(call("free-1").await, StatusCode::TOO_MANY_REQUESTS);
+
+    for _ in 0..3 {
+        assert_eq!(call("premium-1").await, StatusCode::OK);
+    }
+    assert_eq!(call("premium-1").await, StatusCode::TOO_MANY_REQUESTS);
+
+    // Keys with the same quota share a limiter, but not their state
+    assert_eq!(call("premium-2").await, StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn test_quota_extractor_called_once() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use governor::Quota;
+    use std::net::IpAddr;
+    use std::num::NonZeroU32;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    let quota = Quota::per_minute(NonZeroU32::new(3).unwrap());
+    let config = GovernorConfigBuilder::default()
+        .refund_on_status(vec![StatusCode::INTERNAL_SERVER_ERROR])
+        .policy_name(quota, "plan")
+        .quota_extractor(move |_: &IpAddr| {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            quota
+        })
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(HttpResponse::InternalServerError)),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .peer_addr("127.0.0.1:80".parse().unwrap())
+        .uri("/")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(
+        test.headers().get("x-ratelimit-policy-name").unwrap(),
+        "plan"
+    );
+
+    // The quota is looked up once, not again for the policy header and the refund
+    assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+}
+
+#[actix_rt::test]
+async fn test_category_key_extractor() {
+    use crate::{CategoryKeyExtractor, Governor, GovernorConfigBuilder, HeaderKeyExtractor};
+    use actix_web::test;
+    use governor::Quota;
+    use std::num::NonZeroU32;
+
+    let quota = |burst| {
+        Quota::with_period(std::time::Duration::from_secs(60))
+            .unwrap()
+            .allow_burst(NonZeroU32::new(burst).unwrap())
+    };
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .key_extractor(
+            CategoryKeyExtractor::new(HeaderKeyExtractor::new("x-api-key"))
+                .category("search", &["/search", "/search/{index}"], quota(3))
+                .category("write", &["/items"], quota(2)),
+        )
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/search", web::get().to(hello))
+            .route("/search/{index}", web::get().to(hello))
+            .route("/items", web::post().to(hello))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = |req: test::TestRequest, uri: &str| {
+        let req = req
+            .insert_header(("x-api-key", "key"))
+            .uri(uri)
+            .to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await.status() }
+    };
+
+    // The search budget is shared by all search routes
+    assert_eq!(
+        call(test::TestRequest::get(), "/search").await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        call(test::TestRequest::get(), "/search/a").await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        call(test::TestRequest::get(), "/search/b").await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        call(test::TestRequest::get(), "/search").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // The write budget is independent
+    for _ in 0..2 {
+        assert_eq!(
+            call(test::TestRequest::post(), "/items").await,
+            StatusCode::OK
+        );
+    }
+    assert_eq!(
+        call(test::TestRequest::post(), "/items").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // Uncategorized routes use the configured quota
+    assert_eq!(call(test::TestRequest::get(), "/").await, StatusCode::OK);
+    assert_eq!(
+        call(test::TestRequest::get(), "/").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+}
+
+#[actix_rt::test]
+async fn test_ietf_headers() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(10)
+        .burst_size(3)
+        .use_headers()
+        .use_ietf_headers()
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = || async {
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri("/")
+            .to_request();
+        test::call_service(&app, req).await
+    };
+
+    let test = call().await;
+    assert_eq!(test.status(), StatusCode::OK);
+    assert_eq!(
+        test.headers().get("ratelimit").unwrap(),
+        "limit=3, remaining=2, reset=10"
+    );
+    assert_eq!(test.headers().get("ratelimit-policy").unwrap(), "3;w=30");
+    assert!(!test.headers().contains_key("x-ratelimit-limit"));
+    assert!(!test.headers().contains_key("x-ratelimit-remaining"));
+
+    call().await;
+    call().await;
+    let test = call().await;
+    assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+    let wait_time: u64 = test
+        .headers()
+        .get("retry-after")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert_eq!(
+        test.headers().get("ratelimit").unwrap(),
+        format!("limit=3, remaining=0, reset={}", wait_time + 20).as_str()
+    );
+    assert_eq!(test.headers().get("ratelimit-policy").unwrap(), "3;w=30");
+    assert!(!test.headers().contains_key("x-ratelimit-limit"));
+}
+
+#[actix_rt::test]
+async fn test_full_reset_header() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(10)
+        .burst_size(3)
+        .use_headers()
+        .full_reset_header()
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = || async {
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri("/")
+            .to_request();
+        test::call_service(&app, req).await
+    };
+
+    // The more depleted the quota, the longer it takes to restore
+    for full_reset in ["10", "20", "30"] {
+        let test = call().await;
+        assert_eq!(test.status(), StatusCode::OK);
+        assert_eq!(
+            test.headers().get("x-ratelimit-full-reset").unwrap(),
+            full_reset
+        );
+    }
+
+    let test = call().await;
+    assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+    let wait_time: u64 = test
+        .headers()
+        .get("retry-after")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert_eq!(
+        test.headers().get("x-ratelimit-full-reset").unwrap(),
+        (wait_time + 20).to_string().as_str()
+    );
+}
+
+#[actix_rt::test]
+async fn test_align_reset_to() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(3)
+        .burst_size(1)
+        .align_reset_to(Duration::from_secs(10))
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = || async {
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri("/")
+            .to_request();
+        test::call_service(&app, req).await
+    };
+
+    assert_eq!(call().await.status(), StatusCode::OK);
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let test = call().await;
+    assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+    let wait_time: u64 = test
+        .headers()
+        .get("retry-after")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    // The reset lands within a second after a multiple of ten seconds
+    let reset = now.as_secs_f64() + wait_time as f64;
+    assert!(
+        reset % 10.0 < 1.1 || reset % 10.0 > 9.9,
+        "reset at {}",
+        reset
+    );
+    assert!((2..=13).contains(&wait_time));
+}
+
+#[actix_rt::test]
+async fn test_rejection_status() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    #[derive(Clone)]
+    struct FinishKeyExtractor;
+
+    impl KeyExtractor for FinishKeyExtractor {
+        type Key = ();
+        type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
+
+        #[cfg(any(feature = "log", feature = "tracing"))]
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
+        fn extract(
+            &self,
+            _req: &actix_web::dev::ServiceRequest,
+        ) -> Result<Self::Key, Self::KeyExtractionError> {
+            Ok(())
+        }
+
+        fn exceed_rate_limit_response(
+            &self,
+            _negative: &governor::NotUntil<governor::clock::QuantaInstant>,
+            mut response: HttpResponseBuilder,
+        ) -> HttpResponse {
+            response.finish()
+        }
+    }
+
+    let mut builder = GovernorConfigBuilder::default();
+    builder
+        .seconds_per_request(60)
+        .burst_size(1)
+        .rejection_status(StatusCode::SERVICE_UNAVAILABLE);
+
+    let config = builder.finish().unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+    for status in [StatusCode::OK, StatusCode::SERVICE_UNAVAILABLE] {
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri("/")
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), status);
+    }
+
+    // The builder passed to the key extractor has the configured status
+    let config = builder
+        .key_extractor(FinishKeyExtractor)
+        .use_headers()
+        .finish()
+        .unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+    for status in [StatusCode::OK, StatusCode::SERVICE_UNAVAILABLE] {
+        let req = test::TestRequest::get().uri("/").to_request();
+        let test = test::call_service(&app, req).await;
+        assert_eq!(test.status(), status);
+    }
+}
+
+#[actix_rt::test]
+async fn test_drain() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    #[cfg(feature = "log")]
+    capture_logs();
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = || async {
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri("/")
+            .to_request();
+        test::call_service(&app, req).await.status()
+    };
+
+    assert_eq!(call().await, StatusCode::OK);
+    assert_eq!(call().await, StatusCode::TOO_MANY_REQUESTS);
+
+    config.begin_drain("test shutdown");
+    assert!(config.is_draining());
+    for _ in 0..3 {
+        assert_eq!(call().await, StatusCode::OK);
+    }
+
+    #[cfg(feature = "log")]
+    assert!(LOGS
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|(_, message)| message.ends_with("while draining: test shutdown")));
+
+    config.end_drain();
+    assert!(!config.is_draining());
+    assert_eq!(call().await, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_rt::test]
+async fn test_async_key_extractor() {
+    use crate::{AsyncKeyExtractor, Governor, GovernorConfigBuilder};
+    use actix_web::{test, HttpRequest};
+    use std::time::Duration;
+
+    #[derive(Clone)]
+    struct SessionUser;
+
+    impl AsyncKeyExtractor for SessionUser {
+        type Key = String;
+        type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
+
+        #[cfg(any(feature = "log", feature = "tracing"))]
+        fn name(&self) -> &'static str {
+            "session user"
+        }
+
+        async fn extract(&self, req: &HttpRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+            let session = req
+                .headers()
+                .get("session")
+                .and_then(|session| session.to_str().ok())
+                .map(str::to_owned);
+            // Simulate a lookup in a database
+            actix_rt::time::sleep(Duration::from_millis(1)).await;
+            match session.as_deref() {
+                Some("a" | "b") => Ok("alice".to_owned()),
+                Some("c") => Ok("carol".to_owned()),
+                _ => Err(SimpleKeyExtractionError::new("Unknown session")
+                    .set_status_code(StatusCode::UNAUTHORIZED)),
+            }
+        }
+    }
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(2)
+        .async_key_extractor(SessionUser)
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = |session: &'static str| {
+        let req = test::TestRequest::get()
+            .insert_header(("session", session))
+            .uri("/")
+            .to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await.status() }
+    };
+
+    // Both sessions of the same user share a bucket
+    assert_eq!(call("a").await, StatusCode::OK);
+    assert_eq!(call("b").await, StatusCode::OK);
+    assert_eq!(call("a").await, StatusCode::TOO_MANY_REQUESTS);
+
+    assert_eq!(call("c").await, StatusCode::OK);
+
+    let req = test::TestRequest::get()
+        .insert_header(("session", "x"))
+        .uri("/")
+        .to_request();
+    let err_res = app.call(req).await.unwrap_err();
+    assert_eq!(
+        err_res.as_response_error().status_code(),
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[actix_rt::test]
+async fn test_async_key_extractor_per_endpoint() {
+    use crate::{AsyncKeyExtractor, Governor, GovernorConfigBuilder};
+    use actix_web::{test, HttpRequest};
+
+    #[derive(Clone)]
+    struct SessionUser;
+
+    impl AsyncKeyExtractor for SessionUser {
+        type Key = String;
+        type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
+
+        #[cfg(any(feature = "log", feature = "tracing"))]
+        fn name(&self) -> &'static str {
+            "session user"
+        }
+
+        async fn extract(&self, req: &HttpRequest) -> Result<Self::Key, Self::KeyExtractionError> {
+            req.headers()
+                .get("session")
+                .and_then(|session| session.to_str().ok())
+                .map(str::to_owned)
+                .ok_or_else(|| SimpleKeyExtractionError::new("Missing session"))
+        }
+    }
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .async_key_extractor(SessionUser)
+        .per_endpoint()
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/a", web::get().to(hello))
+            .route("/b", web::get().to(hello)),
+    )
+    .await;
+
+    let call = |session: &'static str, uri: &'static str| {
+        let req = test::TestRequest::get()
+            .insert_header(("session", session))
+            .uri(uri)
+            .to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await.status() }
+    };
+
+    // The key is resolved through the endpoint extractor and combined with the route
+    assert_eq!(call("alice", "/a").await, StatusCode::OK);
+    assert_eq!(call("alice", "/a").await, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(call("alice", "/b").await, StatusCode::OK);
+    assert_eq!(call("bob", "/a").await, StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn test_composite_key_extractor() {
+    use crate::{Governor, GovernorConfigBuilder, HeaderKeyExtractor, PeerIpKeyExtractor};
+    use actix_web::test;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .key_extractor(PeerIpKeyExtractor.and(HeaderKeyExtractor::new("x-api-key")))
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = |addr: &'static str, token: &'static str| {
+        let req = test::TestRequest::get()
+            .peer_addr(addr.parse().unwrap())
+            .insert_header(("x-api-key", token))
+            .uri("/")
+            .to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await.status() }
+    };
+
+    assert_eq!(call("127.0.0.1:80", "token").await, StatusCode::OK);
+    assert_eq!(
+        call("127.0.0.1:80", "token").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    // The same token from another IP has its own budget
+    assert_eq!(call("127.0.0.2:80", "token").await, StatusCode::OK);
+    // Another token from the same IP as well
+    assert_eq!(call("127.0.0.1:80", "other").await, StatusCode::OK);
+
+    // The first error is propagated
+    let req = test::TestRequest::get()
+        .peer_addr("127.0.0.1:80".parse().unwrap())
+        .uri("/")
+        .to_request();
+    let err_res = app.call(req).await.unwrap_err();
+    assert_eq!(
+        err_res.as_response_error().status_code(),
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[actix_rt::test]
+async fn test_uncacheable_routes() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use governor::Quota;
+    use std::num::NonZeroU32;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(3)
+        .uncacheable_routes(
+            |req| req.match_pattern().as_deref() == Some("/search/{query}"),
+            Quota::per_minute(NonZeroU32::new(1).unwrap()),
+        )
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello))
+            .route("/search/{query}", web::get().to(hello)),
+    )
+    .await;
+
+    let call = |uri: &'static str| {
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri(uri)
+            .to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await.status() }
+    };
+
+    // The uncacheable route is strict
+    assert_eq!(call("/search/a").await, StatusCode::OK);
+    assert_eq!(call("/search/b").await, StatusCode::TOO_MANY_REQUESTS);
+
+    // The cacheable route is lenient and has its own bucket
+    assert_eq!(call("/").await, StatusCode::OK);
+    assert_eq!(call("/").await, StatusCode::OK);
+    assert_eq!(call("/").await, StatusCode::OK);
+    assert_eq!(call("/").await, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_rt::test]
+async fn test_path_key_extractor() {
+    use crate::{Governor, GovernorConfigBuilder, PathKeyExtractor};
+    use actix_web::test;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .key_extractor(PathKeyExtractor)
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/a", web::get().to(hello))
+            .route("/users/{id}", web::get().to(hello)),
+    )
+    .await;
+
+    let call = |addr: &'static str, uri: &'static str| {
+        let req = test::TestRequest::get()
+            .peer_addr(addr.parse().unwrap())
+            .uri(uri)
+            .to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await.status() }
+    };
+
+    assert_eq!(call("127.0.0.1:80", "/a").await, StatusCode::OK);
+    // The quota is shared by all clients
+    assert_eq!(
+        call("127.0.0.2:80", "/a").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // Another endpoint has its own quota, which covers the whole route pattern
+    assert_eq!(call("127.0.0.1:80", "/users/1").await, StatusCode::OK);
+    assert_eq!(
+        call("127.0.0.1:80", "/users/2").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // Unmatched requests are limited by their raw path
+    assert_eq!(call("127.0.0.1:80", "/b").await, StatusCode::NOT_FOUND);
+    assert_eq!(call("127.0.0.1:80", "/c").await, StatusCode::NOT_FOUND);
+    assert_eq!(
+        call("127.0.0.1:80", "/b").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+}
+
+#[actix_rt::test]
+async fn test_quota_provider() {
+    use crate::{Governor, GovernorConfigBuilder, HeaderKeyExtractor};
+    use actix_web::test;
+    use governor::Quota;
+    use std::num::NonZeroU32;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let fetches = Arc::new(AtomicU32::new(0));
+    let counter = fetches.clone();
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .key_extractor(HeaderKeyExtractor::new("x-api-key"))
+        .quota_provider(
+            move |key: &String| {
+                counter.fetch_add(1, Ordering::Relaxed);
+                let burst = if key == "premium" { 3 } else { 2 };
+                async move {
+                    // Simulate a lookup in a database
+                    actix_rt::time::sleep(Duration::from_millis(1)).await;
+                    Quota::per_minute(NonZeroU32::new(burst).unwrap())
+                }
+            },
+            Duration::from_secs(60),
+        )
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = |key: &'static str| {
+        let req = test::TestRequest::get()
+            .insert_header(("x-api-key", key))
+            .uri("/")
+            .to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await.status() }
+    };
+
+    for _ in 0..3 {
+        assert_eq!(call("premium").await, StatusCode::OK);
+    }
+    assert_eq!(call("premium").await, StatusCode::TOO_MANY_REQUESTS);
+
+    for _ in 0..2 {
+        assert_eq!(call("free").await, StatusCode::OK);
+    }
+    assert_eq!(call("free").await, StatusCode::TOO_MANY_REQUESTS);
+
+    // The quotas are cached
+    assert_eq!(fetches.load(Ordering::Relaxed), 2);
+    config.invalidate_quota(&"free".to_owned());
+    assert_eq!(call("free").await, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(fetches.load(Ordering::Relaxed), 3);
+}
+
+#[actix_rt::test]
+async fn test_state_epoch() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(3)
+        .burst_size(2)
+        .finish()
+        .unwrap();
+    assert_eq!(config.state_epoch(), None);
+    assert!(config.set_state_epoch("v1"));
+    assert_eq!(config.state_epoch().as_deref(), Some("v1"));
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 80u16);
+    let exhaust = || async {
+        for _ in 0..2 {
+            let req = test::TestRequest::get()
+                .peer_addr(addr)
+                .uri("/")
+                .to_request();
+            let test = test::call_service(&app, req).await;
+            assert_eq!(test.status(), StatusCode::OK);
+        }
+        let req = test::TestRequest::get()
+            .peer_addr(addr)
+            .uri("/")
+            .to_request();
+        let test = test::call_service(&app, req).await;
+        assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+    };
+
+    exhaust().await;
+
+    // Keeping the epoch keeps the state
+    assert!(!config.set_state_epoch("v1"));
+    let req = test::TestRequest::get()
+        .peer_addr(addr)
+        .uri("/")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // A new epoch starts with a fresh quota
+    assert!(config.set_state_epoch("v2"));
+    assert_eq!(config.state_epoch().as_deref(), Some("v2"));
+    exhaust().await;
+}
+
+#[actix_rt::test]
+async fn test_header_prefix() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    assert!(GovernorConfigBuilder::default()
+        .header_prefix("x acme ")
+        .finish()
+        .is_none());
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .header_prefix("X-Acme-RateLimit-")
+        .use_headers()
+        .combined_header(false)
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 80u16);
+    let header = |headers: &header::HeaderMap, name: &str| {
+        headers.get(name).unwrap().to_str().unwrap().to_owned()
+    };
+
+    let req = test::TestRequest::get()
+        .peer_addr(addr)
+        .uri("/")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::OK);
+    let headers = test.headers();
+    assert_eq!(header(headers, "x-acme-ratelimit-limit"), "1");
+    assert_eq!(header(headers, "x-acme-ratelimit-remaining"), "0");
+    assert_eq!(header(headers, "x-acme-ratelimit-reset"), "60");
+    assert_eq!(
+        header(headers, "x-acme-ratelimit"),
+        "limit=1, remaining=0, reset=60"
+    );
+    assert!(headers.get("x-ratelimit-limit").is_none());
+
+    let req = test::TestRequest::get()
+        .peer_addr(addr)
+        .uri("/")
+        .to_request();
+    let test = test::call_service(&app, req).await;
+    assert_eq!(test.status(), StatusCode::TOO_MANY_REQUESTS);
+    let headers = test.headers();
+    assert_eq!(header(headers, "x-acme-ratelimit-after"), "59");
+    assert_eq!(header(headers, "x-acme-ratelimit-remaining"), "0");
+    assert!(headers.get("x-ratelimit-after").is_none());
+}
+
+#[actix_rt::test]
+async fn test_resumed_session_quota() {
+    use crate::{Governor, GovernorConfigBuilder, TlsSessionResumed};
+    use actix_web::dev::Service;
+    use actix_web::test;
+    use actix_web::HttpMessage;
+    use governor::Quota;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::num::NonZeroU32;
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .resumed_session_quota(Quota::per_minute(NonZeroU32::new(3).unwrap()))
+        .finish()
+        .unwrap();
+
+    // Stands in for the TLS layer, which marks resumed sessions
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .wrap_fn(|req, srv| {
+                if req.headers().contains_key("x-resumed") {
+                    req.extensions_mut().insert(TlsSessionResumed);
+                }
+                srv.call(req)
+            })
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = |ip: u8, resumed: bool| {
+        let mut req = test::TestRequest::get()
+            .peer_addr(SocketAddr::new(
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, ip)),
+                80,
+            ))
+            .uri("/");
+        if resumed {
+            req = req.insert_header(("x-resumed", "1"));
+        }
+        let req = req.to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await.status() }
+    };
+
+    // A full handshake gets the configured quota
+    assert_eq!(call(1, false).await, StatusCode::OK);
+    assert_eq!(call(1, false).await, StatusCode::TOO_MANY_REQUESTS);
+
+    // A resumed session gets the more generous quota
+    for _ in 0..3 {
+        assert_eq!(call(2, true).await, StatusCode::OK);
+    }
+    assert_eq!(call(2, true).await, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_rt::test]
+async fn test_probe_header() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(2)
+        .use_headers()
+        .probe_header(
+            HeaderName::from_static("x-ratelimit-probe"),
+            HeaderValue::from_static("true"),
+        )
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 80u16);
+    let call = |probe: bool| {
+        let mut req = test::TestRequest::get().peer_addr(addr).uri("/");
+        if probe {
+            req = req.insert_header(("x-ratelimit-probe", "TRUE"));
+        }
+        let req = req.to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await }
+    };
+    let header = |headers: &header::HeaderMap, name: &str| {
+        headers.get(name).unwrap().to_str().unwrap().to_owned()
+    };
+
+    // Probes report the quota without depleting it
+    for _ in 0..3 {
+        let res = call(true).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(header(res.headers(), "x-ratelimit-limit"), "2");
+        assert_eq!(header(res.headers(), "x-ratelimit-remaining"), "2");
+        assert_eq!(header(res.headers(), "x-ratelimit-reset"), "0");
+        assert_eq!(test::read_body(res).await, "");
+    }
+    // Probes of unknown keys don't create their state
+    assert_eq!(config.tracked_keys(), 0);
+
+    let res = call(false).await;
+    assert_eq!(header(res.headers(), "x-ratelimit-remaining"), "1");
+    assert_eq!(test::read_body(res).await, "Hello world!");
+    let res = call(true).await;
+    assert_eq!(header(res.headers(), "x-ratelimit-remaining"), "1");
+
+    // Refunds of keys whose state was dropped don't create it again
+    config.reset_key(&addr.ip());
+    config.limiter.refund(&addr.ip());
+    assert_eq!(config.tracked_keys(), 0);
+    let res = call(false).await;
+    assert_eq!(header(res.headers(), "x-ratelimit-remaining"), "1");
+
+    let res = call(false).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = call(true).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(header(res.headers(), "x-ratelimit-remaining"), "0");
+    assert!(res.headers().contains_key("retry-after"));
+
+    let res = call(false).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_rt::test]
+async fn test_on_limited() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let limited = Arc::new(AtomicUsize::new(0));
+    let counter = limited.clone();
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(2)
+        .on_limited(move |req, _| {
+            assert_eq!(req.path(), "/");
+            counter.fetch_add(1, Ordering::SeqCst);
+        })
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config.clone()))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 80u16);
+    for (status, count) in [
+        (StatusCode::OK, 0),
+        (StatusCode::OK, 0),
+        (StatusCode::TOO_MANY_REQUESTS, 1),
+        (StatusCode::TOO_MANY_REQUESTS, 2),
+    ] {
+        let req = test::TestRequest::get()
+            .peer_addr(addr)
+            .uri("/")
+            .to_request();
+        let test = test::call_service(&app, req).await;
+        assert_eq!(test.status(), status);
+        assert_eq!(limited.load(Ordering::SeqCst), count);
+    }
+}
+
+#[cfg(feature = "metrics")]
+#[actix_rt::test]
+async fn test_metrics() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(2)
+        .finish()
+        .unwrap();
+    let metrics = config.metrics();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    assert_eq!(metrics.allowed(), 0);
+    assert_eq!(metrics.blocked(), 0);
+    assert_eq!(metrics.tracked_keys(), 0);
+
+    for (ip, count) in [(1, 3), (2, 1)] {
+        for _ in 0..count {
+            let req = test::TestRequest::get()
+                .peer_addr(SocketAddr::new(
+                    IpAddr::V4(Ipv4Addr::new(127, 0, 0, ip)),
+                    80,
+                ))
+                .uri("/")
+                .to_request();
+            test::call_service(&app, req).await;
+        }
+    }
+
+    assert_eq!(metrics.allowed(), 3);
+    assert_eq!(metrics.blocked(), 1);
+    assert_eq!(metrics.tracked_keys(), 2);
+    // Clones share the counters
+    assert_eq!(config.clone().metrics().allowed(), 3);
+
+    drop(app);
+    drop(config);
+    assert_eq!(metrics.tracked_keys(), 0);
+}
+
+#[cfg(feature = "metrics")]
+#[actix_rt::test]
+async fn test_metrics_other_rejections() {
+    use crate::{Governor, GovernorConfigBuilder, PeerIpKeyExtractor};
+    use actix_web::test;
+    use governor::Quota;
+    use std::num::NonZeroU32;
+    use std::time::Duration;
+
+    let config = GovernorConfigBuilder::default()
+        .key_extractor(WhitelistedKeyExtractor)
+        .max_new_keys_per_ip(1, Duration::from_secs(60))
+        .penalize_extraction_errors(PeerIpKeyExtractor, Quota::per_minute(NonZeroU32::MIN))
+        .finish()
+        .unwrap();
+    let metrics = config.metrics();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let call = |token: Option<&'static str>| {
+        let mut req = test::TestRequest::get()
+            .peer_addr("127.0.0.1:80".parse().unwrap())
+            .uri("/");
+        if let Some(token) = token {
+            req = req.insert_header(("super-token", token));
+        }
+        let req = req.to_request();
+        let app = &app;
+        async move {
+            match app.call(req).await {
+                Ok(res) => res.status(),
+                Err(err) => err.as_response_error().status_code(),
+            }
+        }
+    };
+
+    assert_eq!(call(Some("first")).await, StatusCode::OK);
+    // Too many new keys of the peer
+    assert_eq!(call(Some("second")).await, StatusCode::TOO_MANY_REQUESTS);
+    // The penalty quota of failed extractions
+    assert_eq!(call(None).await, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(call(None).await, StatusCode::TOO_MANY_REQUESTS);
+
+    assert_eq!(metrics.allowed(), 1);
+    assert_eq!(metrics.blocked(), 2);
+}
+
+#[actix_rt::test]
+async fn test_whitelist_never_consumes_quota() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use governor::Quota;
+    use std::num::NonZeroU32;
+
+    let config = GovernorConfigBuilder::default()
+        .burst_size(1)
+        .seconds_per_request(60)
+        .global_cap(Quota::per_minute(NonZeroU32::new(2).unwrap()))
+        .key_extractor(WhitelistedKeyExtractor)
+        .finish()
+        .unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+    let create_res = |user_token: &'static str| {
+        let req = test::TestRequest::get()
+            .uri("/")
+            .insert_header(("super-token", user_token))
+            .to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await.status() }
+    };
+
+    // Whitelisted keys win before any limiter is checked
+    for _ in 0..50 {
+        assert_eq!(create_res("AdminSecretToken").await, StatusCode::OK);
+    }
+    assert_eq!(config.tracked_keys(), 0);
+
+    // Neither the quota of a key nor the global quota were consumed
+    assert_eq!(create_res("UserToken").await, StatusCode::OK);
+    assert_eq!(create_res("UserToken").await, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(create_res("OtherUserToken").await, StatusCode::OK);
+    assert_eq!(create_res("AdminSecretToken").await, StatusCode::OK);
+}
+
+#[cfg(feature = "tracing")]
+#[actix_rt::test]
+async fn test_tracing_block_event() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+
+    #[cfg(feature = "log")]
+    capture_logs();
+
+    let recorder = SpanRecorder::default();
+    let _guard = tracing::subscriber::set_default(recorder.clone());
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    for status in [StatusCode::OK, StatusCode::TOO_MANY_REQUESTS] {
+        let req = test::TestRequest::get()
+            .peer_addr("127.0.0.44:80".parse().unwrap())
+            .uri("/")
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), status);
+    }
+
+    // The block is emitted once, as an event instead of a log message
+    let events = recorder.events.lock().unwrap();
+    assert_eq!(
+        events.as_slice(),
+        [concat!(
+            "message=Rate limit exceeded extractor=\"peer IP\" key_name=\"127.0.0.44\" ",
+            "wait_seconds=59 suppressed=0 "
+        )]
+    );
+    #[cfg(feature = "log")]
+    assert!(!LOGS
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|(_, message)| message.contains("[127.0.0.44]")));
+}
+
+#[actix_rt::test]
+async fn test_info_header() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(2)
+        .use_headers()
+        .info_header()
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 80u16);
+    let header = |headers: &header::HeaderMap, name: &str| {
+        headers.get(name).unwrap().to_str().unwrap().to_owned()
+    };
+
+    for (status, expected) in [
+        (StatusCode::OK, r#"{"limit":2,"remaining":1,"reset":60}"#),
+        (StatusCode::OK, r#"{"limit":2,"remaining":0,"reset":120}"#),
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            r#"{"limit":2,"remaining":0,"reset":119}"#,
+        ),
+    ] {
+        let req = test::TestRequest::get()
+            .peer_addr(addr)
+            .uri("/")
+            .to_request();
+        let test = test::call_service(&app, req).await;
+        assert_eq!(test.status(), status);
+        let headers = test.headers();
+        let info = header(headers, "x-ratelimit-info");
+        assert_eq!(info, expected);
+        // The JSON object matches the other headers
+        assert_eq!(
+            info,
+            format!(
+                r#"{{"limit":{},"remaining":{},"reset":{}}}"#,
+                header(headers, "x-ratelimit-limit"),
+                header(headers, "x-ratelimit-remaining"),
+                header(headers, "x-ratelimit-reset"),
+            )
+        );
+    }
+}
+
+#[actix_rt::test]
+async fn test_sample_rate() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    let config = GovernorConfigBuilder::default()
+        .seconds_per_request(60)
+        .burst_size(1)
+        .sample_rate(0.25)
+        .use_headers()
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    let mut sampled = 0;
+    for client in 1..=100 {
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, client)), 80u16);
+        let mut responses = Vec::new();
+        for _ in 0..2 {
+            let req = test::TestRequest::get()
+                .peer_addr(addr)
+                .uri("/")
+                .to_request();
+            responses.push(test::call_service(&app, req).await);
+        }
+
+        // A client is either always or never sampled
+        if responses[0]
+            .headers()
+            .contains_key("x-ratelimit-whitelisted")
+        {
+            assert!(responses[1]
+                .headers()
+                .contains_key("x-ratelimit-whitelisted"));
+            assert_eq!(responses[1].status(), StatusCode::OK);
+        } else {
+            assert!(responses[0].headers().contains_key("x-ratelimit-remaining"));
+            assert_eq!(responses[1].status(), StatusCode::TOO_MANY_REQUESTS);
+            sampled += 1;
+        }
+    }
+
+    // About a fourth of the clients are rate limited, the others pass through
+    assert!((10..=40).contains(&sampled), "{} clients sampled", sampled);
+
+    for rate in [-0.1, 1.5, f64::NAN] {
+        assert!(GovernorConfigBuilder::default()
+            .sample_rate(rate)
+            .finish()
+            .is_none());
+    }
+}
+
+#[test]
+fn test_sustained_rate_and_burst() {
+    use crate::{GovernorConfigBuilder, TestDecision};
+    use std::net::IpAddr;
+    use std::time::{Duration, Instant};
+
+    // Zero values are still rejected
+    assert!(GovernorConfigBuilder::default()
+        .burst_size(0)
+        .finish()
+        .is_none());
+    assert!(GovernorConfigBuilder::default()
+        .period(Duration::ZERO)
+        .finish()
+        .is_none());
+
+    // Both sustain 60 requests per minute, but with different bursts
+    let burst = GovernorConfigBuilder::default()
+        .requests_per_minute(60)
+        .burst_size(5)
+        .finish()
+        .unwrap();
+    let steady = GovernorConfigBuilder::default()
+        .requests_per_minute(60)
+        .burst_size(1)
+        .finish()
+        .unwrap();
+    assert_eq!(burst.quota.replenish_interval(), Duration::from_secs(1));
+    assert_eq!(burst.quota.burst_size().get(), 5);
+
+    let client: IpAddr = "10.0.0.1".parse().unwrap();
+    let start = Instant::now();
+    let count_allowed = |config: &crate::GovernorConfig<_, _>| {
+        let mut tester = config.tester();
+        // One request every 100ms for ten seconds
+        (0..100)
+            .filter(|i| {
+                let at = start + Duration::from_millis(100 * i);
+                matches!(
+                    tester.test_decide(&client, at),
+                    TestDecision::Allowed { .. }
+                )
+            })
+            .count()
+    };
+
+    // The burst only allows a head start, the sustained rate is the same afterwards
+    assert_eq!(count_allowed(&steady), 10);
+    assert_eq!(count_allowed(&burst), 14);
+}
+
+#[test]
+fn test_builder_quota() {
+    use crate::GovernorConfigBuilder;
+    use governor::Quota;
+    use std::num::NonZeroU32;
+    use std::time::Duration;
+
+    let quota = Quota::per_minute(NonZeroU32::new(7).unwrap());
+    let config = GovernorConfigBuilder::default()
+        // Replaced by the quota
+        .burst_size(0)
+        .period(Duration::ZERO)
+        .quota(quota)
+        .finish()
+        .unwrap();
+    assert_eq!(config.quota, quota);
+    assert_eq!(config.quota.burst_size().get(), 7);
+    assert_eq!(
+        config.quota.replenish_interval(),
+        Duration::from_nanos(60_000_000_000 / 7)
+    );
+
+    // Without a quota, invalid periods and burst sizes are still rejected
+    assert!(GovernorConfigBuilder::default()
+        .burst_size(0)
+        .finish()
+        .is_none());
+
+    // The last call wins, so a later burst size replaces the quota
+    assert!(GovernorConfigBuilder::default()
+        .quota(quota)
+        .burst_size(0)
+        .finish()
+        .is_none());
+    let config = GovernorConfigBuilder::default()
+        .quota(quota)
+        .requests_per_second(2)
+        .finish()
+        .unwrap();
+    assert_eq!(
+        config.quota.replenish_interval(),
+        Duration::from_millis(500)
+    );
+    assert_eq!(config.quota.burst_size().get(), 8);
+}
+
+#[actix_rt::test]
+async fn test_long_wait_threshold() {
+    use crate::{Governor, GovernorConfigBuilder};
+    use actix_web::test;
+    use governor::Quota;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::num::NonZeroU32;
+    use std::time::Duration;
+
+    let slow = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2));
+    let config = GovernorConfigBuilder::default()
+        .quota_extractor(move |key: &IpAddr| match *key == slow {
+            true => Quota::per_hour(NonZeroU32::new(1).unwrap()),
+            false => Quota::per_second(NonZeroU32::new(1).unwrap()),
+        })
+        .long_wait_threshold(Duration::from_secs(30), StatusCode::SERVICE_UNAVAILABLE)
+        .finish()
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(Governor::new(&config))
+            .route("/", web::get().to(hello)),
+    )
+    .await;
+
+    for (ip, status) in [
+        (1, StatusCode::OK),
+        // Waits about a second
+        (1, StatusCode::TOO_MANY_REQUESTS),
+        (2, StatusCode::OK),
+        // Waits about an hour
+        (2, StatusCode::SERVICE_UNAVAILABLE),
+    ] {
+        let req = test::TestRequest::get()
+            .peer_addr(SocketAddr::new(
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, ip)),
+                80,
+            ))
+            .uri("/")
+            .to_request();
+        let test = test::call_service(&app, req).await;
+        assert_eq!(test.status(), status);
+    }
 }